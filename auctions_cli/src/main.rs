@@ -41,6 +41,48 @@ mod bootstrap;
 mod kademlia;
 #[path = "../../src/lib.rs"]
 mod lib;
+#[path = "../../src/mempool.rs"]
+mod mempool;
+#[path = "../../src/miner.rs"]
+mod miner;
+#[path = "../../src/identity.rs"]
+mod identity;
+#[path = "../../src/consensus.rs"]
+mod consensus;
+#[path = "../../src/explorer.rs"]
+mod explorer;
+#[path = "../../src/snapshot.rs"]
+mod snapshot;
+#[path = "../../src/merkle.rs"]
+mod merkle;
+#[path = "../../src/light.rs"]
+mod light;
+#[path = "../../src/finality.rs"]
+mod finality;
+#[path = "../../src/auction.rs"]
+mod auction;
+#[path = "../../src/sealed.rs"]
+mod sealed;
+#[path = "../../src/softclose.rs"]
+mod softclose;
+#[path = "../../src/cancel.rs"]
+mod cancel;
+#[path = "../../src/accounts.rs"]
+mod accounts;
+#[path = "../../src/engine.rs"]
+mod engine;
+#[path = "../../src/notify.rs"]
+mod notify;
+#[path = "../../src/search.rs"]
+mod search;
+#[path = "../../src/history.rs"]
+mod history;
+#[path = "../../src/coordinator.rs"]
+mod coordinator;
+#[path = "../../src/multiunit.rs"]
+mod multiunit;
+#[path = "../../src/reputation.rs"]
+mod reputation;
 #[path = "../../src/node.rs"]
 mod node;
 #[path = "../../src/pubsub.rs"]
@@ -49,6 +91,7 @@ mod pubsub;
 mod rpc;
 use crate::lib::{NODETIMEOUT, K_PARAM, N_KBUCKETS, KEY_LEN, ALPHA, TREPLICATE};
 use crate::bootstrap::{App, Bootstrap};
+use crate::auction::{AuctionKind, AuctionSpec, DEFAULT_DURATION_MINS};
 use crate::node::{Node};
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -116,7 +159,12 @@ fn main() -> Result<(), Box<dyn Error>> {//Box<dynapp.input_mode = InputMode::To
     // setup terminal
     enable_raw_mode()?;
 
-    let app = App::new(aux::get_ip().unwrap(), port, boot_node);
+    // cargo r <port> <boot_ip> [light|full] [signer,...] (checkpoint signers logged by the bootstrap)
+    let light = cmd_args.get(3).map(String::as_str) == Some("light");
+    let signers: Vec<String> = cmd_args.get(4)
+        .map(|signers| signers.split(',').map(String::from).collect())
+        .unwrap_or_default();
+    let app = App::with_signers(aux::get_ip().unwrap(), port, boot_node, signers, light);
     //let test_id = format!("test-{}", rand_n);
     //app.publish(test_id);
 
@@ -128,7 +176,7 @@ fn main() -> Result<(), Box<dyn Error>> {//Box<dynapp.input_mode = InputMode::To
 
     // create app and run it
     let appcli = AppCli::default();
-    let res = run_app(&mut terminal, appcli, app);
+    let res = run_app(&mut terminal, appcli, app.clone());
 
     // restore terminal
     disable_raw_mode()?;
@@ -138,6 +186,7 @@ fn main() -> Result<(), Box<dyn Error>> {//Box<dynapp.input_mode = InputMode::To
         DisableMouseCapture
     )?;
     terminal.show_cursor()?;
+    app.shutdown();
 
     if let Err(err) = res {
         println!("{:?}", err)
@@ -590,23 +639,18 @@ fn bid_topic(topic_list_state: &mut ListState, value: &Vec<String>, app: &App) -
 fn publish_topic(value: &Vec<String>, app: &App) -> Result<bool, Box<dyn Error>> {
     let mut result = false;
     
-    // split input (ex: topic_name:bid)
+    // split input (ex: topic_name:starting_price)
     let msg_split: Vec<&str> = value[0].split(":").collect();
 
     if msg_split.len() >= 2 {
-        match msg_split[1].parse::<i32>() {
+        match msg_split[1].parse::<usize>() {
             Ok(n) => {
                 let topic_name = msg_split[0];
-                let topic_bid = msg_split[1];
 
-                // publish topic
-                let status = app.publish(topic_name.to_string());
-
-                if status {
-                    // add new bid to topic
-                    let msg = format!("bid {}", topic_bid);
-                    result = app.add_msg(topic_name.to_string(), msg);
-                } 
+                // publish topic, starting price is part of its spec
+                let mut spec = AuctionSpec::lasting(topic_name, DEFAULT_DURATION_MINS);
+                spec.starting_price = n;
+                result = app.publish_auction(topic_name.to_string(), spec, AuctionKind::default(), None);
             },
             Err(e) => {
                 result = false;
@@ -0,0 +1,261 @@
+use super::auction::Bid;
+use super::blockchain::Blockchain;
+use super::bootstrap::Data;
+use super::explorer::msg_fields;
+use super::snapshot::LedgerState;
+use super::multiunit::committed;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use std::collections::BTreeMap;
+
+// Units credited to a key at its first registered address (faucet)
+pub const FAUCET_AMOUNT: u64 = 1000;
+
+// Units issued to an address by a minter
+pub const MINT_MSG_TYPE: usize = 8;
+
+// Bidder funds locked on a topic by its highest bid
+pub const ESCROW_MSG_TYPE: usize = 9;
+
+/*
+    Account records, msg formats:
+        8 -> "MINT: <addr>|AMOUNT: <amount>"
+        9 -> "ESCROW: <topic>|BIDDER: <addr>|AMOUNT: <amount>[|QUANTITY: <units>|ITEMS: <items>]"
+    Escrows of multi kind bids add their quantity and items
+    (base64 of their json), the amount is the bid data.
+*/
+pub fn mint_msg(addr: &str, amount: u64) -> String {
+    format!("MINT: {}|AMOUNT: {}", addr, amount)
+}
+
+pub fn escrow_msg(topic: &str, bidder: &str, amount: u64) -> String {
+    format!("ESCROW: {}|BIDDER: {}|AMOUNT: {}", topic, bidder, amount)
+}
+
+pub fn bid_escrow_msg(topic: &str, bid: &Bid) -> String {
+    let mut msg = escrow_msg(topic, &bid.sender_addr, bid.data as u64);
+    if bid.quantity != 1 || !bid.items.is_empty() {
+        let items = STANDARD.encode(serde_json::to_string(&bid.items).unwrap());
+        msg.push_str(&format!("|QUANTITY: {}|ITEMS: {}", bid.quantity, items));
+    }
+    msg
+}
+
+// Bid of an escrow msg
+pub fn escrowed_bid(msg: &str) -> Option<Bid> {
+    let record = msg_fields(msg);
+    let mut bid = Bid::new(record.get("AMOUNT")?.parse().ok()?, record.get("BIDDER")?.trim_matches('"').to_string());
+    if let Some(quantity) = record.get("QUANTITY") {
+        bid.quantity = quantity.parse().ok()?;
+    }
+    if let Some(items) = record.get("ITEMS") {
+        bid.items = serde_json::from_slice(&STANDARD.decode(items).ok()?).ok()?;
+    }
+    Some(bid)
+}
+
+/**
+ * Mint policy (per network, see ConsensusEngine::mint_policy):
+ *  - faucet: units credited to every key at its first registration.
+ *  - minters: public keys allowed to issue units (MINT).
+**/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MintPolicy {
+    pub faucet: u64,
+    pub minters: Vec<String>,
+}
+
+impl MintPolicy {
+    pub fn may_mint(&self, key: &str) -> bool {
+        self.minters.iter().any(|minter| minter == key)
+    }
+}
+
+fn amount(data: &Data) -> Option<u64> {
+    msg_fields(data.msg()).get("AMOUNT")?.parse().ok()
+}
+
+/**
+ * ACCOUNTS & ESCROW
+ *  Balances are available units, the units of a topic
+ *  escrow are held by the ledger until it is outbid (refund),
+ *  cancelled (refund) or ended (paid to the seller).
+ *  Multi kinds have several winners, every bidder locks
+ *  its standing bids (deposit) until the topic ends.
+**/
+impl LedgerState {
+    pub fn balance(&self, addr: &str) -> u64 {
+        self.balances.get(addr).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn credit(&mut self, addr: &str, amount: u64) {
+        if amount > 0 {
+            let balance = self.balances.entry(addr.to_string()).or_insert(0);
+            *balance = balance.saturating_add(amount);
+        }
+    }
+
+    fn debit(&mut self, addr: &str, amount: u64) -> bool {
+        match self.balances.get_mut(addr) {
+            Some(balance) if *balance >= amount => {
+                *balance -= amount;
+                true
+            },
+            _ => amount == 0
+        }
+    }
+
+    // Funds of bidder for a topic: balance and its own escrow (or deposit) of the topic
+    pub fn funds(&self, topic: &str, bidder: &str) -> u64 {
+        let funds = self.balance(bidder).saturating_add(self.deposited(topic, bidder));
+        match self.escrow.get(topic) {
+            Some((holder, locked)) if holder == bidder => funds.saturating_add(*locked),
+            _ => funds
+        }
+    }
+
+    /*
+        Lock of bid on an open topic, above its escrow and covered
+        by the bidder funds. Multi kinds lock the standing bids of
+        the bidder with bid (see multiunit::committed).
+    */
+    pub fn can_lock(&self, topic: &str, bid: &Bid) -> bool {
+        if !self.auctions.contains_key(topic) {
+            return false
+        }
+        let bidder = &bid.sender_addr;
+        if let Some(auction) = self.engine.auction(topic).filter(|auction| auction.kind.multi()) {
+            let mut bids = auction.bids.clone();
+            bids.push(bid.clone());
+            return self.can_deposit(topic, bidder, committed(&bids, bidder))
+        }
+        let amount = bid.data as u64;
+        let outbids = self.escrow.get(topic).is_none_or(|(_, locked)| amount > *locked);
+        outbids && self.funds(topic, bidder) >= amount
+    }
+
+    pub(crate) fn mint(&mut self, addr: &str, data: &Data) {
+        self.credit(addr, amount(data).unwrap_or(0));
+    }
+
+    // Previous escrow of the topic is refunded, multi kinds raise the deposit of the bidder
+    pub(crate) fn lock(&mut self, topic: &str, bidder: &str, data: &Data) {
+        let bid = match escrowed_bid(data.msg()) {
+            Some(bid) if bid.sender_addr == bidder => bid,
+            _ => return
+        };
+        // taken by the engine already (see LedgerState::apply_tx)
+        if let Some(auction) = self.engine.auction(topic).filter(|auction| auction.kind.multi()) {
+            let owed = committed(&auction.bids, bidder);
+            self.lock_deposit(topic, bidder, owed);
+            return
+        }
+        if !self.can_lock(topic, &bid) {
+            return
+        }
+        self.release(topic);
+        self.debit(bidder, bid.data as u64);
+        self.escrow.insert(topic.to_string(), (bidder.to_string(), bid.data as u64));
+    }
+
+    // Escrow and sealed deposits of the topic are refunded
+    pub(crate) fn release(&mut self, topic: &str) {
+        if let Some((holder, locked)) = self.escrow.remove(topic) {
+            self.credit(&holder, locked);
+        }
+        for (bidder, locked) in self.deposits.remove(topic).unwrap_or_default() {
+            self.credit(&bidder, locked);
+        }
+    }
+
+    /*
+        Sealed bid deposit:
+            A commitment locks the auction reserve, forfeited to the
+            seller if not revealed in time. A reveal raises the lock
+            of its bidder to the bid, so the winner pays from it.
+    */
+    pub fn deposit(&self, topic: &str) -> u64 {
+        self.engine.auction(topic).map_or(0, |auction| auction.kind.reserve() as u64)
+    }
+
+    fn deposited(&self, topic: &str, bidder: &str) -> u64 {
+        self.deposits.get(topic).and_then(|deposits| deposits.get(bidder)).copied().unwrap_or(0)
+    }
+
+    // Lock of bidder on topic raised to amount, covered by its balance and deposit
+    pub fn can_deposit(&self, topic: &str, bidder: &str, amount: u64) -> bool {
+        self.balance(bidder).saturating_add(self.deposited(topic, bidder)) >= amount
+    }
+
+    pub(crate) fn lock_deposit(&mut self, topic: &str, bidder: &str, amount: u64) {
+        let locked = self.deposited(topic, bidder);
+        if amount > locked && self.debit(bidder, amount - locked) {
+            self.deposits.entry(topic.to_string()).or_default().insert(bidder.to_string(), amount);
+        }
+    }
+
+    /*
+        Settlement (END_TOPIC, taken by the engine):
+            The winner pays the price to the seller (publisher) from
+            its escrow (or sealed deposit) of the topic only, up to
+            the units it locked, the rest is refunded. A winner
+            without one leaves it unpaid. Sealed deposits of losers
+            are refunded, those never revealed go to the seller.
+            Multi kinds are paid by the winners of their allocation
+            only, from their deposits, those of losers are refunded.
+            Every payment is a trade (see reputation.rs).
+    */
+    pub(crate) fn settle(&mut self, topic: &str) {
+        let seller = match self.auctions.get(topic) {
+            Some(auction) => auction.publisher.clone(),
+            None => {
+                self.release(topic);
+                return
+            }
+        };
+        let auction = self.engine.auction(topic).cloned();
+        let mut payments: BTreeMap<String, u64> = BTreeMap::new();
+        match &auction {
+            Some(auction) if auction.kind.multi() => {
+                for won in &auction.allocation {
+                    *payments.entry(won.bidder.clone()).or_insert(0) += won.price as u64;
+                }
+            },
+            Some(auction) => payments.extend(auction.outcome.iter().map(|(price, winner)| (winner.clone(), *price as u64))),
+            None => {}
+        }
+        let mut escrow = self.escrow.remove(topic);
+        let mut deposits = self.deposits.remove(topic).unwrap_or_default();
+        for (winner, price) in payments {
+            let locked = match escrow.take_if(|(holder, _)| *holder == winner) {
+                Some((_, locked)) => locked,
+                None => deposits.remove(&winner).unwrap_or(0)
+            };
+            let paid = price.min(locked);
+            self.credit(&seller, paid);
+            self.credit(&winner, locked - paid);
+            self.record_trade(topic, &seller, &winner, price, paid);
+        }
+        if let Some((holder, locked)) = escrow {
+            self.credit(&holder, locked);
+        }
+        let forfeits = |bidder: &String| auction.as_ref().is_some_and(|auction| auction.kind.sealed() && !auction.reveals.contains_key(bidder));
+        for (bidder, locked) in deposits {
+            match forfeits(&bidder) {
+                true => self.credit(&seller, locked),
+                false => self.credit(&bidder, locked)
+            }
+        }
+    }
+}
+
+impl Blockchain {
+    // Available units of address at our tip (None if its state was pruned)
+    pub fn balance(&self, addr: &str) -> Option<u64> {
+        self.with_tip_state(|state| state.balance(addr))
+    }
+
+    pub fn mint_policy(&self) -> MintPolicy {
+        self.consensus.mint_policy()
+    }
+}
@@ -0,0 +1,300 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{DateTime, Duration, Local, Utc};
+use serde::{Serialize, Deserialize};
+use super::reputation::BidPolicy;
+use super::multiunit::{Allocation, Pricing, allocate_units, allocate_bundles, allocation_outcome, verify_bundle};
+
+// Listing duration of App::publish (minutes)
+pub const DEFAULT_DURATION_MINS: i64 = 2;
+
+// Currency unit of listings that set none
+pub const DEFAULT_CURRENCY: &str = "EUR";
+
+/**
+ * Bid:
+ *  Message of a pubsub instance, {"data": <amount>, "sender_addr": <addr>}.
+ *  Multi-unit bids add "quantity" (units wanted, data is the unit
+ *  price), bundle bids "items" (topics, data is the price of all of them).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Bid {
+    pub data: usize,
+    pub sender_addr: String,
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub quantity: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<String>,
+}
+
+fn one() -> usize {
+    1
+}
+
+fn is_one(quantity: &usize) -> bool {
+    *quantity == 1
+}
+
+impl Bid {
+    // Bid for the (single) item
+    pub fn new(data: usize, sender_addr: String) -> Self {
+        Self { data, sender_addr, quantity: 1, items: Vec::new() }
+    }
+
+    pub fn from_msg(msg: &str) -> Option<Self> {
+        serde_json::from_str(msg).ok()
+    }
+
+    // Units paid if won in full (escrowed by the bidder)
+    pub fn total(&self) -> usize {
+        self.data.saturating_mul(self.quantity)
+    }
+}
+
+/**
+ * Auction kind (chosen at publish time):
+ *  - English: open ascending bids, the first one at least the
+ *    reserve, every other one increment above the last.
+ *  - Dutch: descending price clock, from start (opened, unix
+ *    secs) decrement per second down to floor, the first bid
+ *    at or above the clock wins.
+ *  - FirstPrice: sealed bids (one per bidder) of at least the
+ *    reserve, highest bid wins and pays its bid.
+ *  - SecondPrice (Vickrey): as FirstPrice, but the winner pays
+ *    the second highest bid (or the reserve).
+ *  - MultiUnit: open bids for some of units identical units, at a
+ *    unit price of at least the reserve (see multiunit.rs).
+ *  - Bundle: open bids on sets of the bundled topics (auctions
+ *    of the same publisher, taken by the bundle), at least the
+ *    reserve per topic, disjoint sets may win (see multiunit.rs).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum AuctionKind {
+    English { reserve: usize, increment: usize },
+    Dutch { start: usize, floor: usize, decrement: usize, opened: i64 },
+    FirstPrice { reserve: usize },
+    SecondPrice { reserve: usize },
+    MultiUnit { units: usize, reserve: usize, pricing: Pricing },
+    Bundle { topics: Vec<String>, reserve: usize },
+}
+
+// Plain ascending auction, any higher bid is accepted
+impl Default for AuctionKind {
+    fn default() -> Self {
+        AuctionKind::English { reserve: 0, increment: 1 }
+    }
+}
+
+impl AuctionKind {
+    // Dutch auction, clock starts now
+    pub fn dutch(start: usize, floor: usize, decrement: usize) -> Self {
+        AuctionKind::Dutch { start, floor, decrement, opened: Local::now().timestamp() }
+    }
+
+    pub fn from_json(kind: &str) -> Option<Self> {
+        serde_json::from_str(kind).ok()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    // Lowest accepted price (the floor of a Dutch clock)
+    pub fn reserve(&self) -> usize {
+        match self {
+            AuctionKind::English { reserve, .. } => *reserve,
+            AuctionKind::Dutch { floor, .. } => *floor,
+            AuctionKind::FirstPrice { reserve } | AuctionKind::SecondPrice { reserve } => *reserve,
+            AuctionKind::MultiUnit { reserve, .. } | AuctionKind::Bundle { reserve, .. } => *reserve,
+        }
+    }
+
+    // Bids are committed/revealed on chain (see sealed.rs), never in cleartext
+    pub fn sealed(&self) -> bool {
+        matches!(self, AuctionKind::FirstPrice { .. } | AuctionKind::SecondPrice { .. })
+    }
+
+    // Several winners, settled by the allocation of END_TOPIC
+    pub fn multi(&self) -> bool {
+        matches!(self, AuctionKind::MultiUnit { .. } | AuctionKind::Bundle { .. })
+    }
+
+    // Current price of a Dutch clock (None for other kinds)
+    pub fn clock_price(&self, now: DateTime<Local>) -> Option<usize> {
+        match self {
+            AuctionKind::Dutch { start, floor, decrement, opened } => {
+                let elapsed = (now.timestamp() - opened).max(0) as usize;
+                Some(start.saturating_sub(decrement.saturating_mul(elapsed)).max(*floor))
+            },
+            _ => None
+        }
+    }
+
+    // Bid is valid given the previous (accepted) bids
+    pub fn verify_bid(&self, bids: &[Bid], bid: &Bid, now: DateTime<Local>) -> bool {
+        if !self.multi() && (bid.quantity != 1 || !bid.items.is_empty()) {
+            return false
+        }
+        match self {
+            AuctionKind::English { reserve, increment } => match bids.last() {
+                Some(last) => bid.data >= last.data.saturating_add(*increment),
+                None => bid.data >= *reserve
+            },
+            AuctionKind::Dutch { .. } => {
+                bids.is_empty() && self.clock_price(now).is_some_and(|price| bid.data >= price)
+            },
+            AuctionKind::FirstPrice { reserve } | AuctionKind::SecondPrice { reserve } => {
+                bid.data >= *reserve && !bids.iter().any(|prev| prev.sender_addr == bid.sender_addr)
+            },
+            AuctionKind::MultiUnit { units, reserve, .. } => {
+                bid.data >= *reserve && (1..=*units).contains(&bid.quantity) && bid.items.is_empty()
+            },
+            AuctionKind::Bundle { topics, reserve } => verify_bundle(topics, *reserve, bid)
+        }
+    }
+
+    /*
+        Outcome (price, winner) of the accepted bids,
+        None if no bid was accepted. Ties of sealed
+        bids are won by the earliest one. Outcome of
+        multi kinds is (revenue, first winner) of
+        their allocation.
+    */
+    pub fn outcome(&self, bids: &[Bid]) -> Option<(usize, String)> {
+        match self {
+            AuctionKind::English { .. } => bids.last().map(|bid| (bid.data, bid.sender_addr.clone())),
+            AuctionKind::Dutch { .. } => bids.first().map(|bid| (bid.data, bid.sender_addr.clone())),
+            AuctionKind::FirstPrice { .. } => {
+                let winner = highest(bids)?;
+                Some((winner.data, winner.sender_addr.clone()))
+            },
+            AuctionKind::SecondPrice { reserve } => {
+                let winner = highest(bids)?;
+                let price = bids.iter()
+                    .filter(|bid| !std::ptr::eq(*bid, winner))
+                    .map(|bid| bid.data)
+                    .max()
+                    .unwrap_or(*reserve);
+                Some((price.max(*reserve), winner.sender_addr.clone()))
+            },
+            AuctionKind::MultiUnit { .. } | AuctionKind::Bundle { .. } => allocation_outcome(&self.allocate(bids))
+        }
+    }
+
+    // Winners of the accepted bids, the one of the outcome for single item kinds
+    pub fn allocate(&self, bids: &[Bid]) -> Vec<Allocation> {
+        match self {
+            AuctionKind::MultiUnit { units, pricing, .. } => allocate_units(*units, *pricing, bids),
+            AuctionKind::Bundle { .. } => allocate_bundles(bids),
+            _ => self.outcome(bids).into_iter()
+                .map(|(price, bidder)| Allocation { bidder, quantity: 1, items: Vec::new(), price })
+                .collect()
+        }
+    }
+}
+
+// Highest bid, earliest one on ties
+fn highest(bids: &[Bid]) -> Option<&Bid> {
+    bids.iter().rev().max_by_key(|bid| bid.data)
+}
+
+/**
+ * Auction spec (recorded on chain at publish):
+ *  - title, description, image_hash: the listed item.
+ *  - starting_price: lowest opening bid (start of a Dutch clock).
+ *  - reserve: lowest price the item is sold at.
+ *  - min_increment: of ascending bids over the last one.
+ *  - currency: unit of every price and bid.
+ *  - start_time, end_time: bidding period (unix secs).
+ *  - tags: keywords the listing is found by (see search.rs).
+ *  - policy: reputation required to bid (see reputation.rs).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuctionSpec {
+    pub title: String,
+    pub description: String,
+    pub starting_price: usize,
+    pub reserve: usize,
+    pub min_increment: usize,
+    pub currency: String,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub image_hash: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub policy: BidPolicy,
+}
+
+impl AuctionSpec {
+    // Listing open from now until end, no reserve
+    pub fn new(title: &str, end: DateTime<Local>) -> Self {
+        Self {
+            title: title.to_string(),
+            description: String::new(),
+            starting_price: 0,
+            reserve: 0,
+            min_increment: 1,
+            currency: String::from(DEFAULT_CURRENCY),
+            start_time: Local::now().timestamp(),
+            end_time: end.timestamp(),
+            image_hash: None,
+            tags: Vec::new(),
+            policy: BidPolicy::default(),
+        }
+    }
+
+    pub fn lasting(title: &str, minutes: i64) -> Self {
+        Self::new(title, Local::now() + Duration::minutes(minutes))
+    }
+
+    // Base64 (json) form, kept as is in ledger msgs and the DHT
+    pub fn encode(&self) -> String {
+        STANDARD.encode(serde_json::to_string(self).unwrap())
+    }
+
+    pub fn decode(spec: &str) -> Option<Self> {
+        serde_json::from_slice(&STANDARD.decode(spec).ok()?).ok()
+    }
+
+    pub fn start(&self) -> DateTime<Local> {
+        local(self.start_time)
+    }
+
+    pub fn end(&self) -> DateTime<Local> {
+        local(self.end_time)
+    }
+
+    pub fn valid(&self) -> bool {
+        !self.title.is_empty() && self.end_time > self.start_time && self.min_increment > 0
+    }
+
+    pub fn started(&self, now: DateTime<Local>) -> bool {
+        now.timestamp() >= self.start_time
+    }
+
+    // Auction kind priced by the spec (the kind only selects the mechanism)
+    pub fn price(&self, kind: AuctionKind) -> AuctionKind {
+        match kind {
+            AuctionKind::English { .. } => AuctionKind::English { reserve: self.starting_price, increment: self.min_increment },
+            AuctionKind::Dutch { decrement, .. } => AuctionKind::Dutch {
+                start: self.starting_price.max(self.reserve),
+                floor: self.reserve,
+                decrement,
+                opened: self.start_time
+            },
+            AuctionKind::FirstPrice { .. } => AuctionKind::FirstPrice { reserve: self.starting_price.max(self.reserve) },
+            AuctionKind::SecondPrice { .. } => AuctionKind::SecondPrice { reserve: self.starting_price.max(self.reserve) },
+            AuctionKind::MultiUnit { units, pricing, .. } => AuctionKind::MultiUnit { units, reserve: self.starting_price.max(self.reserve), pricing },
+            AuctionKind::Bundle { topics, .. } => AuctionKind::Bundle { topics, reserve: self.starting_price.max(self.reserve) },
+        }
+    }
+
+    // Item is only sold at (or above) the reserve
+    pub fn sold(&self, outcome: Option<(usize, String)>) -> Option<(usize, String)> {
+        outcome.filter(|(price, _)| *price >= self.reserve)
+    }
+}
+
+pub(crate) fn local(secs: i64) -> DateTime<Local> {
+    DateTime::<Utc>::from_timestamp(secs, 0).unwrap_or_default().with_timezone(&Local)
+}
@@ -1,7 +1,23 @@
+use super::miner::Miner;
+use super::consensus::{ConsensusEngine, ProofOfWork, engine_from_genesis, poa_genesis_data};
+use super::explorer::ChainIndex;
+use super::finality::Finality;
+use super::merkle::{merkle_root, merkle_mutated};
+use super::explorer::{tx_keys, msg_fields};
+use super::bootstrap::Data;
+use super::sealed::{COMMIT_MSG_TYPE, REVEAL_MSG_TYPE};
+use super::softclose::EXTEND_MSG_TYPE;
+use super::cancel::CANCEL_MSG_TYPE;
+use super::accounts::{MINT_MSG_TYPE, ESCROW_MSG_TYPE, escrowed_bid};
+use super::snapshot::{LedgerState, Snapshot, SNAPSHOT_MSG_TYPE};
+use super::reputation::FEEDBACK_MSG_TYPE;
+
+use chrono::Utc;
 use sha2::{Digest, Sha256};
 use serde::{Serialize, Deserialize};
-use chrono::prelude::*;
 use std::fmt::{Display, Formatter, Result};
+use std::sync::Arc;
+use std::collections::HashMap;
 
 /**
  * Hash (of data in block) must start with 00.
@@ -11,9 +27,59 @@ use std::fmt::{Display, Formatter, Result};
 **/
 const DIFFICULTY_PREFIX: &str = "00";
 
+// Genesis block is the same for every node (common ancestor for sync)
+const GENESIS_TIMESTAMP: i64 = 1658275200;
+
+// Seconds a block timestamp may be ahead of our clock
+pub const MAX_FUTURE_SECS: i64 = 15;
+
+// Max number of headers returned for a single GetHeaders request
+pub const MAX_HEADERS: usize = 20;
+
+// Max number of block bodies requested in a single GetBlocks request
+pub const MAX_BLOCKS: usize = 5;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Blockchain {
     pub blocks: Vec<Block>,
+    #[serde(skip, default = "default_engine")]
+    pub consensus: Arc<dyn ConsensusEngine>,
+    // Explorer lookup tables (see explorer.rs)
+    #[serde(skip)]
+    pub index: ChainIndex,
+    // Latest verified snapshot (see snapshot.rs)
+    #[serde(skip)]
+    pub snapshot: Option<Snapshot>,
+    // Cumulative work of our blocks (see ChainTip)
+    #[serde(skip)]
+    pub work: u64,
+    // Confirmation depth and hard checkpoint (see finality.rs)
+    #[serde(skip)]
+    pub finality: Finality,
+    // Hash of the network genesis (ours, or pinned from the node we join)
+    #[serde(skip)]
+    pub genesis_pin: Option<String>,
+    // Ledger state at our tip (tip hash, state), see state_at
+    #[serde(skip)]
+    pub tip_state: Option<(String, LedgerState)>,
+}
+
+fn default_engine() -> Arc<dyn ConsensusEngine> {
+    Arc::new(ProofOfWork)
+}
+
+/**
+ * Transactions context:
+ *  Registrations (address -> key) and publications (topic -> key)
+ *  of the blocks being validated, not yet in our chain index.
+ *  Ledger is the state (accounts) at height, then every valid
+ *  transaction is applied on it.
+**/
+#[derive(Debug, Clone, Default)]
+struct TxContext {
+    registered: HashMap<String, String>,
+    publishers: HashMap<String, String>,
+    ledger: Option<LedgerState>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -23,7 +89,49 @@ pub struct Block {
     pub prev_hash: String,
     pub timestamp: i64,
     pub data: String,
+    // Root of the transactions merkle tree (hashed instead of data)
+    #[serde(default)]
+    pub merkle_root: String,
     pub nonce: u64,
+    // Seal of authority based engines (empty for proof-of-work)
+    #[serde(default)]
+    pub signature: String,
+    // Body (data) dropped behind a snapshot checkpoint, never taken
+    // from peers (see Blockchain::accepts_pruned)
+    #[serde(skip)]
+    pub pruned: bool,
+}
+
+/**
+ * Block header:
+ *  Block without data, used to announce blocks, to find
+ *  missing blocks (headers-first sync) and by light clients.
+ *  The hash commits to the merkle root, thus a header can
+ *  be verified without the body.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub id: u64,
+    pub hash: String,
+    pub prev_hash: String,
+    pub timestamp: i64,
+    pub merkle_root: String,
+    pub nonce: u64,
+    #[serde(default)]
+    pub signature: String,
+}
+
+/**
+ * Chain tip commitment:
+ *  Tip hash, height and cumulative work, maintained
+ *  as blocks are added/removed. Nodes compare tips
+ *  instead of whole chains (see sync_tip).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainTip {
+    pub hash: String,
+    pub height: u64,
+    pub work: u64,
 }
 
 /**
@@ -39,11 +147,21 @@ fn hash_to_binary(hash: &[u8]) -> String {
     res
 }
 
-fn calc_hash(id: u64, timestamp: i64, prev_hash: &str, data: &str, nonce: u64) -> Vec<u8> {
+// Timestamp of a block sealed now on top of prev (always after it)
+pub fn next_timestamp(prev: &BlockHeader) -> i64 {
+    Utc::now().timestamp().max(prev.timestamp + 1)
+}
+
+// Block time is after its parent and not ahead of our clock (see MAX_FUTURE_SECS)
+pub fn valid_timestamp(header: &BlockHeader, prev: &BlockHeader) -> bool {
+    header.timestamp > prev.timestamp && header.timestamp <= Utc::now().timestamp() + MAX_FUTURE_SECS
+}
+
+pub fn calc_hash(id: u64, timestamp: i64, prev_hash: &str, merkle_root: &str, nonce: u64) -> Vec<u8> {
     let data = serde_json::json!({
         "id": id,
         "prev_hash": prev_hash,
-        "data": data,
+        "merkle_root": merkle_root,
         "timestamp": timestamp,
         "nonce": nonce
     });
@@ -52,30 +170,9 @@ fn calc_hash(id: u64, timestamp: i64, prev_hash: &str, data: &str, nonce: u64) -
     hasher.finalize().as_slice().to_owned()
 }
 
-/**
- * Mining:
- *  From block data and nonce generate hash
- *  that starts with our difficulty prefix.
-**/
-fn mine_block(id: u64, timestamp: i64, prev_hash: &str, data: &str) -> (u64, String) {
-    //info!("mining block");
-    let mut nonce = 0;
-
-    loop {
-        // if nonce % 100000 == 0 {
-        //    println!("nonce: {}", nonce);
-        // }
-        let hash = calc_hash(id, timestamp, prev_hash, data, nonce);
-        let bin_hash = hash_to_binary(&hash);
-        if bin_hash.starts_with(DIFFICULTY_PREFIX) {
-            let hex_hash = hex::encode(&hash);
-            //println!("mined nonce: {}, hash: {}, bin hash: {}",
-            //    nonce, hex_hash, bin_hash
-            //);
-            return (nonce, hex_hash);
-        }
-        nonce += 1;
-    }
+// Proof-of-work: hash (binary) starts with our difficulty prefix
+pub fn meets_difficulty(hash: &[u8]) -> bool {
+    hash_to_binary(hash).starts_with(DIFFICULTY_PREFIX)
 }
 
 impl Display for Blockchain {
@@ -88,27 +185,62 @@ impl Display for Blockchain {
  * Simple consensus criteria:
  *  Ask other nodes for their chains, if 
  *  there is any longer than ours, use theirs.
+ *  (fork choice and seal rules given by consensus engine)
 **/
 impl Blockchain {
     pub fn new() -> Self {
-        Self { blocks: vec![] }
+        Self { blocks: vec![], consensus: default_engine(), index: ChainIndex::new(), snapshot: None, work: 0, finality: Finality::default(), genesis_pin: None, tip_state: None }
     }
 
     pub fn genesis(&mut self) {
         let genesis_block = Block {
             id: 0,
-            timestamp: Utc::now().timestamp(),
+            timestamp: GENESIS_TIMESTAMP,
             prev_hash: String::from("none"),
             data: String::from("genesis_block"),
+            merkle_root: merkle_root(&[String::from("genesis_block")]),
             nonce: 2836,
             hash: "0000f816a87f806bb0073dcf026a64fb40c946b5abee2573702828694d5b4c43".to_string(),
+            signature: String::new(),
+            pruned: false,
         };
+        self.index.index_block(&genesis_block);
+        self.work += self.block_work(&genesis_block);
+        self.genesis_pin = Some(genesis_block.hash.clone());
         self.blocks.push(genesis_block);
+        self.keep_tip_state();
+    }
+
+    // Genesis of a proof-of-authority network (validators public keys)
+    pub fn genesis_poa(&mut self, validators: Vec<String>) {
+        let data = poa_genesis_data(&validators);
+        let root = merkle_root(std::slice::from_ref(&data));
+        let genesis_block = Block {
+            id: 0,
+            timestamp: GENESIS_TIMESTAMP,
+            prev_hash: String::from("none"),
+            hash: hex::encode(calc_hash(0, GENESIS_TIMESTAMP, "none", &root, 0)),
+            data,
+            merkle_root: root,
+            nonce: 0,
+            signature: String::new(),
+            pruned: false,
+        };
+        self.consensus = engine_from_genesis(&genesis_block);
+        self.genesis_pin = Some(genesis_block.hash.clone());
+        self.set_blocks(vec![genesis_block]);
+        self.keep_tip_state();
     }
 
     pub fn add_block(&mut self, block: Block) -> bool {
         let prev_block = self.blocks.last().expect("At least one block");
-        if self.is_block_valid(&block, prev_block) {
+        let mut ctx = TxContext::default();
+        if self.is_block_valid(&block, prev_block) && self.verify_txs(&block, &mut ctx, prev_block.id) {
+            // tip state: transactions applied on the state at our previous tip
+            let state = ctx.ledger.or_else(|| self.state_at(prev_block.id));
+            self.index.index_block(&block);
+            self.work += self.block_work(&block);
+            self.tip_state = state.map(|state| (block.hash.clone(), state));
             self.blocks.push(block);
             return true
         } else {
@@ -119,21 +251,54 @@ impl Blockchain {
     }
 
     pub fn remove_last_block(&mut self) {
-        self.blocks.pop();
+        if let Some(block) = self.blocks.pop() {
+            self.index.unindex_block(&block);
+            self.work -= self.block_work(&block);
+            self.keep_tip_state();
+        }
+    }
+
+    pub fn tip(&self) -> ChainTip {
+        match self.blocks.last() {
+            Some(block) => ChainTip { hash: block.hash.clone(), height: block.id, work: self.work },
+            None => ChainTip::default()
+        }
+    }
+
+    fn block_work(&self, block: &Block) -> u64 {
+        self.consensus.work(&block.header())
+    }
+
+    // Replace chain, only blocks after the common prefix are (un)indexed
+    // and (un)counted in our cumulative work
+    fn set_blocks(&mut self, blocks: Vec<Block>) {
+        let common = self.blocks.iter()
+            .zip(blocks.iter())
+            .take_while(|(local, remote)| local.hash == remote.hash)
+            .count();
+        for block in &self.blocks[common..] {
+            self.index.unindex_block(block);
+            self.work -= self.block_work(block);
+        }
+        for block in &blocks[common..] {
+            self.index.index_block(block);
+            self.work += self.block_work(block);
+        }
+        self.blocks = blocks;
     }
 
     fn is_block_valid(&self, block: &Block, prev_block: &Block) -> bool {
         if block.prev_hash != prev_block.hash {
             return false;
-        } else if !hash_to_binary(&hex::decode(&block.hash).expect("Decodable hex")).starts_with(DIFFICULTY_PREFIX) {
+        } else if !self.consensus.verify_seal(&block.header()) || !self.consensus.verify_turn(&block.header(), &prev_block.header()) {
             return false;
-        } else if block.id != prev_block.id + 1 {
+        } else if block.id != prev_block.id + 1 || !valid_timestamp(&block.header(), &prev_block.header()) {
             return false;
-        } else if hex::encode(calc_hash(
+        } else if (!block.pruned && (merkle_mutated(&block.transactions()) || merkle_root(&block.transactions()) != block.merkle_root)) || hex::encode(calc_hash(
             block.id,
             block.timestamp,
             &block.prev_hash,
-            &block.data,
+            &block.merkle_root,
             block.nonce,
         )) != block.hash {
             return false;
@@ -141,6 +306,168 @@ impl Blockchain {
         true
     }
 
+    /*
+        Transactions authorship:
+            Every transaction is a Data record signed by its author.
+            REGISTER binds an address to the author key (first one
+            wins), PUB_TOPIC must be signed by the key registered for
+            the publisher address and END_TOPIC/CANCEL_TOPIC by the topic
+            publisher.
+            Sealed bid commits/reveals, soft-close extensions and
+            escrows must be signed by the key registered for the
+            bidder address, on a published topic, an escrow must be
+            covered by the bidder funds (see LedgerState::can_lock),
+            a commit by its deposit and a reveal by its bid (see
+            LedgerState::can_deposit).
+            Escrows and commits must meet the topic bid policy and
+            FEEDBACK must be signed by the rater, a counterparty of
+            the settlement not yet rating (see reputation.rs).
+            MINT must be signed by a minter of the network and
+            SNAPSHOT by a snapshot signer, matching our state if
+            the committed block is ours (see snapshot.rs).
+            Auction transactions must be taken by the auction
+            engine at the block time: escrows meeting the kind,
+            reveals matching their commitment and END_TOPIC
+            recording the outcome of the bids (see engine.rs).
+            Only registrations/publications up to height (common
+            ancestor) are taken from our chain.
+    */
+    fn verify_txs(&self, block: &Block, ctx: &mut TxContext, height: u64) -> bool {
+        block.transactions().iter().all(|tx| self.verify_tx(block.timestamp, tx, ctx, height))
+    }
+
+    fn verify_tx(&self, timestamp: i64, tx: &str, ctx: &mut TxContext, height: u64) -> bool {
+        let data = match Data::from_json(tx) {
+            Some(data) if data.verify() => data,
+            _ => return false
+        };
+        if ctx.ledger.is_none() {
+            ctx.ledger = Some(self.state_at(height).unwrap_or_default());
+        }
+        let author = data.author().to_string();
+        let (msg_type, topic, addresses) = tx_keys(tx).unwrap_or_default();
+        match (msg_type, topic) {
+            (0, _) => {
+                for address in addresses {
+                    match self.registered_key(ctx, &address, height) {
+                        Some(key) if key != author => return false,
+                        _ => ctx.registered.insert(address, author.clone())
+                    };
+                }
+            },
+            (1, Some(topic)) => {
+                let publisher = addresses.first().and_then(|address| self.registered_key(ctx, address, height));
+                if publisher != Some(author.clone()) {
+                    return false
+                }
+                ctx.publishers.insert(topic, author);
+            },
+            (2, Some(topic)) | (CANCEL_MSG_TYPE, Some(topic)) if self.publisher_key(ctx, &topic, height) != Some(author.clone()) => return false,
+            (COMMIT_MSG_TYPE, Some(topic)) | (REVEAL_MSG_TYPE, Some(topic)) | (EXTEND_MSG_TYPE, Some(topic)) => {
+                let bidder = addresses.first().and_then(|address| self.registered_key(ctx, address, height));
+                if bidder != Some(author) || self.publisher_key(ctx, &topic, height).is_none() {
+                    return false
+                }
+                let policy_met = addresses.first().is_some_and(|address| ctx.ledger.as_ref().is_some_and(|ledger| ledger.may_bid(&topic, address)));
+                if msg_type == COMMIT_MSG_TYPE && !policy_met {
+                    return false
+                }
+                // sealed bids lock a deposit, raised to the bid on reveal
+                let lock = match msg_type {
+                    COMMIT_MSG_TYPE => ctx.ledger.as_ref().map(|ledger| ledger.deposit(&topic)),
+                    REVEAL_MSG_TYPE => msg_fields(data.msg()).get("BID").and_then(|bid| bid.parse().ok()),
+                    _ => Some(0)
+                };
+                let funded = lock.zip(addresses.first()).is_some_and(|(amount, bidder)| {
+                    ctx.ledger.as_ref().is_some_and(|ledger| ledger.can_deposit(&topic, bidder, amount))
+                });
+                if !funded {
+                    return false
+                }
+            },
+            (FEEDBACK_MSG_TYPE, Some(topic)) => {
+                let (rater, rated) = match (addresses.first(), addresses.get(1)) {
+                    (Some(rater), Some(rated)) if self.registered_key(ctx, rater, height) == Some(author) => (rater, rated),
+                    _ => return false
+                };
+                let rating = msg_fields(data.msg()).get("RATING").map(|rating| rating.to_string());
+                if !matches!(rating.as_deref(), Some("1") | Some("-1")) || !ctx.ledger.as_ref().is_some_and(|ledger| ledger.can_rate(&topic, rater, rated)) {
+                    return false
+                }
+            },
+            (ESCROW_MSG_TYPE, Some(topic)) => {
+                let bidder = match addresses.first() {
+                    Some(bidder) if self.registered_key(ctx, bidder, height) == Some(author) => bidder,
+                    _ => return false
+                };
+                let bid = match escrowed_bid(data.msg()) {
+                    Some(bid) if bid.sender_addr == *bidder => bid,
+                    _ => return false
+                };
+                if !ctx.ledger.as_ref().is_some_and(|ledger| ledger.can_lock(&topic, &bid) && ledger.may_bid(&topic, bidder)) {
+                    return false
+                }
+            },
+            (MINT_MSG_TYPE, _) if !self.mint_policy().may_mint(&author) => return false,
+            (SNAPSHOT_MSG_TYPE, _) => {
+                let (at, hash) = match self.verify_commitment(tx) {
+                    Some(commitment) => commitment,
+                    None => return false
+                };
+                // commitments of our blocks must match our own state
+                if at <= height {
+                    let state = self.state_at(at);
+                    let block_hash = self.block_by_id(at).map(|block| block.hash.clone());
+                    if let (Some(state), Some(block_hash)) = (state, block_hash) {
+                        if (Snapshot { height: at, block_hash, state }).hash() != hash {
+                            return false
+                        }
+                    }
+                }
+            },
+            (1, None) | (2, None) | (CANCEL_MSG_TYPE, None) | (COMMIT_MSG_TYPE, None) | (REVEAL_MSG_TYPE, None) | (EXTEND_MSG_TYPE, None) | (ESCROW_MSG_TYPE, None) | (FEEDBACK_MSG_TYPE, None) => return false,
+            _ => {}
+        }
+        // auction transactions must be taken by the engine (see engine.rs)
+        let policy = self.mint_policy();
+        ctx.ledger.as_mut().is_some_and(|ledger| ledger.apply_tx(timestamp, tx, &policy))
+    }
+
+    // Transactions (in order) valid on top of our tip (mined now), see verify_txs
+    pub fn valid_txs(&self, txs: Vec<String>) -> Vec<String> {
+        let mut ctx = TxContext::default();
+        let height = self.tip().height;
+        let timestamp = self.blocks.last().map_or_else(|| Utc::now().timestamp(), |block| next_timestamp(&block.header()));
+        txs.into_iter()
+            .filter(|tx| self.verify_tx(timestamp, tx, &mut ctx, height))
+            .collect()
+    }
+
+    // Key registered for address (blocks being validated, chain, snapshot)
+    fn registered_key(&self, ctx: &TxContext, address: &str, height: u64) -> Option<String> {
+        if let Some(key) = ctx.registered.get(address) {
+            return Some(key.clone())
+        }
+        match self.index.registered.get(address) {
+            Some((key, block_id)) if *block_id <= height => Some(key.clone()),
+            _ => self.snapshot.as_ref().and_then(|snapshot| snapshot.state.registered.get(address).cloned())
+        }
+    }
+
+    // Key of the topic publisher (blocks being validated, chain, snapshot)
+    fn publisher_key(&self, ctx: &TxContext, topic: &str, height: u64) -> Option<String> {
+        if let Some(key) = ctx.publishers.get(topic) {
+            return Some(key.clone())
+        }
+        match self.index.publishers.get(topic) {
+            Some((key, block_id)) if *block_id <= height => Some(key.clone()),
+            _ => {
+                let auction = self.snapshot.as_ref()?.state.auctions.get(topic)?;
+                self.registered_key(ctx, &auction.publisher, height)
+            }
+        }
+    }
+
     fn is_chain_valid(&self, chain: &[Block]) -> bool {
         for i in 0..chain.len() {
             if i == 0 {
@@ -160,10 +487,10 @@ impl Blockchain {
         let is_remote_valid = self.is_chain_valid(&remote);
 
         if is_local_valid && is_remote_valid {
-            if local.len() >= remote.len() {
-                local
-            } else {
+            if self.consensus.prefer(&local, &remote) {
                 remote
+            } else {
+                local
             }
         } else if is_local_valid && !is_remote_valid {
             local
@@ -193,6 +520,114 @@ impl Blockchain {
         res
     }
 
+    /**
+     * INCREMENTAL SYNC
+    **/
+    pub fn contains_hash(&self, hash: &str) -> bool {
+        self.blocks.iter().any(|block| block.hash == hash)
+    }
+
+    fn position(&self, hash: &str) -> Option<usize> {
+        self.blocks.iter().rposition(|block| block.hash == hash)
+    }
+
+    pub fn locator(&self) -> Vec<String> {
+        locator_indexes(self.blocks.len()).iter()
+            .map(|index| self.blocks[*index].hash.clone())
+            .collect()
+    }
+
+    // Headers after the first locator hash known by us (common ancestor)
+    pub fn headers_after(&self, locator: &[String]) -> Vec<BlockHeader> {
+        let start = locator.iter()
+            .find_map(|hash| self.position(hash))
+            .map_or(0, |index| index + 1);
+
+        self.blocks.iter()
+            .skip(start)
+            .take(MAX_HEADERS)
+            .map(|block| block.header())
+            .collect()
+    }
+
+    // Blocks after hash (whole chain if hash was reorganized away)
+    pub fn blocks_after(&self, hash: &str) -> Vec<Block> {
+        let start = self.position(hash).map_or(0, |index| index + 1);
+        self.blocks[start..].to_vec()
+    }
+
+    pub fn get_blocks(&self, hashes: &[String]) -> Vec<Block> {
+        self.blocks.iter()
+            .filter(|block| hashes.contains(&block.hash))
+            .cloned()
+            .collect()
+    }
+
+    /*
+        Apply blocks (suffix of a remote chain):
+            Blocks are attached to their parent (common ancestor),
+            if the resulting chain is valid and preferred by the
+            consensus engine it replaces the local chain
+            (unless it drops our checkpointed block). Bodies
+            are only taken as pruned up to our snapshot height.
+            A node with no blocks but its own genesis adopts the
+            network genesis (and its engine) when no common
+            ancestor exists, only if it is the pinned genesis
+            (see KademliaInstance::pin_genesis). Returns applied
+            blocks.
+    */
+    pub fn apply_blocks(&mut self, blocks: Vec<Block>) -> Vec<Block> {
+        let first = match blocks.first() {
+            Some(block) => block,
+            None => return Vec::new()
+        };
+
+        let prev_consensus = self.consensus.clone();
+        let mut ctx = TxContext::default();
+        let (mut candidate, suffix): (Vec<Block>, &[Block]) = match self.position(&first.prev_hash) {
+            Some(index) => (self.blocks[..=index].to_vec(), &blocks[..]),
+            None if first.id == 0 && self.blocks.len() == 1 && self.genesis_pin.as_ref() == Some(&first.hash) => {
+                self.consensus = engine_from_genesis(first);
+                (vec![first.clone()], &blocks[1..])
+            },
+            None => return Vec::new()
+        };
+
+        let height = candidate.last().expect("At least one block").id;
+        for block in suffix {
+            let prev_block = candidate.last().expect("At least one block");
+            let mut block = block.clone();
+            block.pruned = self.accepts_pruned(&block);
+            if !self.is_block_valid(&block, prev_block) || !self.verify_txs(&block, &mut ctx, height) {
+                self.consensus = prev_consensus;
+                return Vec::new()
+            }
+            candidate.push(block);
+        }
+        // pruned bodies must lead to the block of our snapshot
+        if let Some(snapshot) = self.snapshot.as_ref().filter(|_| candidate.iter().any(|block| block.pruned)) {
+            if candidate.get(snapshot.height as usize).is_some_and(|block| block.hash != snapshot.block_hash) {
+                self.consensus = prev_consensus;
+                return Vec::new()
+            }
+        }
+
+        let checkpointed = self.finality.allows(|height| candidate.get(height as usize).map(|block| block.hash.clone()));
+        if checkpointed && (candidate[0].hash != self.blocks[0].hash || self.consensus.prefer(&self.blocks, &candidate)) {
+            // no state kept over pruned bodies (replayed from the snapshot)
+            let state = match candidate.iter().any(|block| block.pruned) {
+                true => None,
+                false => ctx.ledger.or_else(|| self.state_at(height))
+            };
+            self.tip_state = state.zip(candidate.last()).map(|(state, tip)| (tip.hash.clone(), state));
+            self.set_blocks(candidate);
+            self.keep_tip_state();
+            return blocks
+        }
+        self.consensus = prev_consensus;
+        Vec::new()
+    }
+
     pub fn string(&self) -> String {
         self.to_string()
     }
@@ -205,16 +640,67 @@ impl Blockchain {
 }
 
 impl Block {
-    pub fn new(id: u64, prev_hash: String, data: String) -> Self {
-        let now = Utc::now();
-        let (nonce, hash) = mine_block(id, now.timestamp(), &prev_hash, &data);
-        Self {
-            id,
-            hash,
-            timestamp: now.timestamp(),
-            prev_hash,
-            data,
-            nonce,
+    // Mine block (blocking), see Miner for cancellable mining
+    pub fn new(prev: &Block, data: String) -> Self {
+        Miner::default().mine(&prev.header(), data)
+            .expect("Block mined without abort")
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            id: self.id,
+            hash: self.hash.clone(),
+            prev_hash: self.prev_hash.clone(),
+            timestamp: self.timestamp,
+            merkle_root: self.merkle_root.clone(),
+            nonce: self.nonce,
+            signature: self.signature.clone(),
         }
     }
+
+    /*
+        Transactions in block:
+            Blocks built from a mempool template hold a json
+            array of transactions, other blocks (genesis, register)
+            hold a single one (pruned blocks hold none).
+    */
+    pub fn transactions(&self) -> Vec<String> {
+        if self.pruned {
+            return Vec::new()
+        }
+        parse_transactions(&self.data)
+    }
+}
+
+/*
+    Block locator:
+        Indexes from tip to genesis, the first ten
+        are sequential then the step doubles, thus
+        a common ancestor is found in O(log n) hashes.
+*/
+pub fn locator_indexes(len: usize) -> Vec<usize> {
+    let mut res = Vec::new();
+    if len == 0 {
+        return res
+    }
+    let mut index = len - 1;
+    let mut step = 1;
+    loop {
+        res.push(index);
+        if index == 0 {
+            break
+        }
+        if res.len() >= 10 {
+            step *= 2;
+        }
+        index = index.saturating_sub(step);
+    }
+    res
+}
+
+pub fn parse_transactions(data: &str) -> Vec<String> {
+    match serde_json::from_str::<Vec<String>>(data) {
+        Ok(txs) => txs,
+        Err(_) => vec![data.to_string()],
+    }
 }
\ No newline at end of file
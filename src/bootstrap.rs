@@ -1,38 +1,100 @@
 use super::kademlia::{KademliaInstance};
-use super::blockchain::Block;
-use super::pubsub::PubSubInstance;
+use super::pubsub::{PubSubInstance, SUBSCRIPTION_LEASE};
+use super::mempool::{Transaction, tx_hash};
+use super::snapshot::SNAPSHOT_INTERVAL;
+use super::blockchain::{Block, ChainTip};
+use super::consensus::ConsensusEngine;
+use super::light::LightClient;
+use super::explorer::{TxEntry, TxRef};
+use super::auction::{AuctionKind, AuctionSpec, Bid, DEFAULT_DURATION_MINS, local};
+use super::softclose::{SoftClose, EXTEND_MSG_TYPE, extend_msg};
+use super::explorer::msg_fields;
+use super::cancel::{CANCEL_MSG_TYPE, cancel_msg};
+use super::accounts::{MINT_MSG_TYPE, ESCROW_MSG_TYPE, mint_msg, bid_escrow_msg};
+use super::sealed::{SealedBid, SealedOutcome, COMMIT_MSG_TYPE, REVEAL_MSG_TYPE, REVEAL_WINDOW, commit_msg, reveal_msg, commitment, new_salt};
+use super::engine::{AuctionEngine, AuctionRecord, LedgerEvent};
+use super::notify::{AuctionEvent, Notification};
+use super::search::{TopicQuery, TopicEntry, keywords};
+use super::coordinator::{BidRequest, BidReceipt, TopicUpdate, UpdateReceipt};
+use super::multiunit::allocation_msg;
+use super::reputation::{Reputation, FEEDBACK_MSG_TYPE, feedback_msg};
+use super::history::{BidEntry, MyBid, MyAuction, bid_history, my_bids, my_auctions};
+use super::light::ProofQuery;
+use super::identity::{Identity, verify};
 use super::node::{Node};
 use super::aux::{get_ip, LockResultRes};
 use super::rpc::{full_rpc_proc, KademliaRequest, KademliaResponse, QueryValueResult};
 use super::NODETIMEOUT;
 
+// Pulls (NODETIMEOUT apart) awaiting a transaction sealed by validators
+const MINED_POLLS: usize = 30;
+
 use std::sync::{Arc, Mutex};
+use crossbeam_channel::Receiver;
 use std::thread::{spawn, sleep};
 use std::time::Duration;
 use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use base64::decode;
 use log::{info, warn};
 
 #[derive(Clone)]
 pub struct Bootstrap {
     pub nodes: Vec<AppNode>,
-    pub bk_hash: Vec<u8>,
+    // Common tip of bootstrap nodes (last sync)
+    pub tip: ChainTip,
 }
 
 impl Bootstrap {
     pub fn new() -> Self {
+        Self::start(Self::boot_nodes())
+    }
+
+    /*
+        Proof-of-authority network:
+            Public keys of the bootstrap nodes are the validator set,
+            written in the genesis block (selects the engine).
+    */
+    pub fn new_poa() -> Self {
+        let nodes = Self::boot_nodes();
+        let validators: Vec<String> = nodes.iter()
+            .map(|node| node.kademlia.identity.public_key())
+            .collect();
+        for node in &nodes {
+            let mut blockchain = node.kademlia.blockchain.lock()
+                .expect("Error setting lock in local blockchain");
+            blockchain.genesis_poa(validators.clone());
+            drop(blockchain);
+        }
+        Self::start(nodes)
+    }
+
+    fn boot_nodes() -> Vec<AppNode> {
         let mut res = Vec::new();
         res.push(AppNode::new(get_ip().unwrap(), 1330, None));
         res.push(AppNode::new(get_ip().unwrap(), 1331, None));
         res.push(AppNode::new(get_ip().unwrap(), 1332, None));
         res.push(AppNode::new(get_ip().unwrap(), 1333, None));
+        res
+    }
+
+    fn start(nodes: Vec<AppNode>) -> Self {
+        // Checkpoints are signed by (any) bootstrap node
+        let signers: Vec<String> = nodes.iter()
+            .map(|node| node.kademlia.identity.public_key())
+            .collect();
+        for node in &nodes {
+            let mut blockchain = node.kademlia.blockchain.lock()
+                .expect("Error setting lock in local blockchain");
+            blockchain.finality.signers = signers.clone();
+            drop(blockchain);
+        }
+        info!("Checkpoint signers: {}", signers.join(","));
 
         let mut boot = Self {
-            nodes: res,
-            bk_hash: Vec::new()
+            nodes,
+            tip: ChainTip::default()
         };
 
         info!("Synchronizing Bootstrap...");
@@ -43,77 +105,216 @@ impl Bootstrap {
     }
 
     fn init_sync(mut self) -> Bootstrap {
-        let mut global_hash: Option<Vec<u8>> = None;
         let mut i = 0;
         while i < self.nodes.len() {
-            self.nodes[i].add_block(Data::new(format!("REGISTER: {id}", id=self.nodes[i].node.get_addr()), 0, None).to_json());
+            let data = Data::new(format!("REGISTER: {id}", id=self.nodes[i].node.get_addr()), 0, None)
+                .sign(&self.nodes[i].kademlia.identity);
+            self.nodes[i].add_block(data.to_json());
             let mut j = 0;
             while j < self.nodes.len() {
                 if i != j {
                     self.nodes[i].kademlia.ping(self.nodes[j].node.clone());
                     self.nodes[j].kademlia.ping(self.nodes[i].node.clone());
-                    self.nodes[j].choose_chain(self.nodes[i].clone());
-                    self.nodes[i].choose_chain(self.nodes[j].clone());
                 }
                 j += 1;
             }
-            let blockchain = self.nodes[i].kademlia.blockchain.lock()
-                .expect("Error setting lock in local blockchain");
-            global_hash = Some(blockchain.hash());
-            drop(blockchain);
+            if let Some(tip) = self.sync_nodes() {
+                self.tip = tip;
+            }
             i += 1;
         }
-        self.bk_hash = global_hash.unwrap();
+        if self.scheduled() {
+            self.produce_blocks();
+            self.tip = self.tips()[0].clone();
+        }
         self
     }
 
+    fn tips(&self) -> Vec<ChainTip> {
+        self.nodes.iter()
+            .map(|node| {
+                let blockchain = node.kademlia.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                blockchain.tip()
+            })
+            .collect()
+    }
+
+    /*
+        Sync bootstrap nodes:
+            Node with the best tip (most work) is the source,
+            every node with another tip fetches the divergent
+            suffix from it, thus O(n) tip comparisons per sync.
+            Returns the common tip, None if nodes still diverge.
+    */
+    fn sync_nodes(&self) -> Option<ChainTip> {
+        let tips = self.tips();
+        let best = tips.iter().max_by_key(|tip| (tip.work, tip.height))?.clone();
+        let source = &self.nodes[tips.iter().position(|tip| *tip == best)?];
+        for (node, tip) in self.nodes.iter().zip(&tips) {
+            if *tip != best {
+                node.kademlia.sync_tip(source.node.clone());
+            }
+        }
+
+        let tips = self.tips();
+        if tips.iter().all(|tip| *tip == tips[0]) {
+            return Some(tips[0].clone())
+        }
+        None
+    }
+
+    // Units issued by a validator (proof-of-authority networks only, see MintPolicy)
+    pub fn mint(&self, addr: &str, amount: u64) -> bool {
+        let kademlia = &self.nodes[0].kademlia;
+        if !kademlia.consensus().mint_policy().may_mint(&kademlia.identity.public_key()) {
+            return false
+        }
+        let data = Data::new(mint_msg(addr, amount), MINT_MSG_TYPE, None).sign(&kademlia.identity);
+        kademlia.submit_transaction(Transaction::new(data.to_json()));
+        true
+    }
+
+    // Checkpoint signers (bootstrap nodes keys), configured by Apps (see App::with_signers)
+    pub fn signers(&self) -> Vec<String> {
+        self.nodes.iter()
+            .map(|node| node.kademlia.identity.public_key())
+            .collect()
+    }
+
+    // Blocks are sealed on a schedule (proof-of-authority)
+    fn scheduled(&self) -> bool {
+        self.nodes[0].kademlia.consensus().scheduled()
+    }
+
+    /*
+        Produce blocks (scheduled engines):
+            Pending transactions are forwarded between bootstrap
+            nodes, the in-turn validator seals the next block (any
+            other one once the fallback delay passed) and every
+            other node syncs from it. Repeats until no block is
+            produced (no transactions pending).
+    */
+    fn produce_blocks(&self) {
+        loop {
+            let mut pending: Vec<Transaction> = Vec::new();
+            for node in &self.nodes {
+                let mempool = node.kademlia.mempool.lock()
+                    .expect("Error setting lock in mempool");
+                pending.extend(mempool.pending.values().cloned());
+                drop(mempool);
+            }
+            for node in &self.nodes {
+                let mut mempool = node.kademlia.mempool.lock()
+                    .expect("Error setting lock in mempool");
+                for tx in &pending {
+                    mempool.add(tx.clone());
+                }
+                drop(mempool);
+            }
+
+            let consensus = self.nodes[0].kademlia.consensus();
+            let height = self.tips()[0].height + 1;
+            let mut producers: Vec<&AppNode> = self.nodes.iter().collect();
+            producers.sort_by_key(|node| !consensus.in_turn(&node.kademlia.identity, height));
+
+            let mut produced = None;
+            for node in producers {
+                if let Some(template) = node.kademlia.block_template() {
+                    if let Some(block) = node.kademlia.mine_next(template) {
+                        produced = Some((node.clone(), block));
+                        break
+                    }
+                }
+            }
+
+            match produced {
+                Some((producer, block)) => {
+                    for node in &self.nodes {
+                        if node.node.id != producer.node.id {
+                            node.choose_chain(producer.clone());
+                        }
+                    }
+                    producer.kademlia.announce_block(block.header(), None);
+                },
+                None => break
+            }
+        }
+    }
+
+    /*
+        Checkpoint:
+            Every SNAPSHOT_INTERVAL blocks a snapshot of the ledger
+            state is committed (by hash) in a block, bodies behind
+            the latest commitment are pruned by every node once
+            the snapshot is verified against its own chain.
+    */
+    fn checkpoint(&self) {
+        let blockchain = self.nodes[0].kademlia.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let snapshot = blockchain.snapshot_due(SNAPSHOT_INTERVAL);
+        drop(blockchain);
+        if let Some(snapshot) = snapshot {
+            self.nodes[0].add_block(snapshot.commitment(&self.nodes[0].kademlia.identity));
+        }
+
+        for node in &self.nodes {
+            let mut blockchain = node.kademlia.blockchain.lock()
+                .expect("Error setting lock in local blockchain");
+            let pruned = blockchain.prune();
+            drop(blockchain);
+            if pruned > 0 {
+                info!("\t[BOOT]: BN[{}] pruned {} block bodies", node.node.port, pruned);
+            }
+        }
+    }
+
+    /*
+        Finalize:
+            Block at confirmation depth below the tip is
+            checkpointed (signed by the first bootstrap node)
+            on every bootstrap node, later reorgs must keep it.
+    */
+    fn finalize(&self) {
+        let blockchain = self.nodes[0].kademlia.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let checkpoint = blockchain.checkpoint_due(&self.nodes[0].kademlia.identity);
+        drop(blockchain);
+
+        if let Some(checkpoint) = checkpoint {
+            for node in &self.nodes {
+                let mut blockchain = node.kademlia.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                blockchain.add_checkpoint(checkpoint.clone());
+                drop(blockchain);
+            }
+        }
+    }
+
     // Note: Added node timeout
     pub fn full_bk_sync(mut boot: Bootstrap) {
         spawn(move || {
             loop {
                 sleep(Duration::from_secs(NODETIMEOUT));
-                let mut hit: usize = 0;
-                let mut hashes: Vec<Vec<u8>> = Vec::new();
-                for node in &boot.nodes {
-                    let blockchain = node.kademlia.blockchain.lock()
-                        .expect("Error setting lock in local blockchain");
-                    let blockchain_hash = blockchain.hash();
-                    drop(blockchain);
-                    if blockchain_hash != boot.bk_hash {
-                        hit = 1
-                    }
-                    hashes.push(blockchain_hash);
+                if boot.scheduled() {
+                    boot.produce_blocks();
                 }
-
-                if hit == 1 {
-                    hashes.clear();
-                    let mut i = 0;
-                    while i < boot.nodes.len() {
-                        let mut j = 0;
-                            while j < boot.nodes.len() {
-                                boot.nodes[j].choose_chain(boot.nodes[i].clone());
-                                boot.nodes[i].choose_chain(boot.nodes[j].clone());
-                                j += 1
-                            }
-                        i += 1
-                    }
-                    let mut global_hash: Option<Vec<u8>> = None;
-                    for node in &boot.nodes {
-                        let blockchain = node.kademlia.blockchain.lock()
-                            .expect("Error setting lock in local blockchain");
-                        let blockchain_hash = blockchain.hash();
-                        drop(blockchain);
-                        if global_hash == None {
-                            global_hash = Some(blockchain_hash)
-                        } else if global_hash != Some(blockchain_hash) {
+                boot.checkpoint();
+                if boot.tips().iter().any(|tip| *tip != boot.tip) {
+                    match boot.sync_nodes() {
+                        Some(tip) => {
+                            boot.tip = tip;
+                            // Debug
+                            boot.nodes[0].kademlia.log_blockchain();
+                        },
+                        None => {
                             warn!("\t[BOOT]: FULL SYNC - Error synchronizing blockchain");
-                            break; // sync next timeout
+                            continue // sync next timeout
                         }
                     }
-                    boot.bk_hash = global_hash.unwrap();
-                    // Debug
-                    boot.nodes[0].kademlia.log_blockchain();
                 }
+                // checkpoint only once nodes agree on the tip
+                boot.finalize();
             }
         });
     }
@@ -138,33 +339,47 @@ impl AppNode {
     }
 
     pub fn publish(&self, topic: String, ttl: DateTime<Local>) {
+        let spec = AuctionSpec::new(&topic, ttl);
+        self.publish_auction(topic, spec, AuctionKind::default(), None)
+    }
+
+    // Instance is open until the end of its spec
+    pub fn publish_auction(&self, topic: String, spec: AuctionSpec, kind: AuctionKind, soft_close: Option<SoftClose>) {
         let mut pubsub = self.pubsub.clone();
-        pubsub.set_ttl(ttl);
-        self.kademlia.insert(topic.clone(), pubsub.to_string());
+        pubsub.set_ttl(spec.end());
+        pubsub.set_kind(kind);
+        pubsub.set_soft_close(soft_close);
+        pubsub.set_spec(Some(spec));
+        self.update(&topic, TopicUpdate::Publish(pubsub.to_string()));
         //println!("\t[AN{}]: Published topic (in DHT): {}; Exp: {}", self.node.port, topic, ttl)
         // TODO: Maybe add block when publish is triggered, set ttl for pubsub instance 
     }
 
     pub fn subscribe(&self, topic: String) -> bool {
-        let pubsub = self.kademlia.get(topic.clone());
-        if pubsub == None {
-            println!("\t[AN{}]: Error subscribing - couldn't find topic: {}", self.node.port, topic)
-        } else {
-            let pubsub_str = pubsub.unwrap();
-            let pubsub_ins = self.get_pubsub_instance(pubsub_str).unwrap();
-            pubsub_ins.add_sub(self.node.get_addr());
-            self.kademlia.insert(topic.clone(), pubsub_ins.to_string());
+        if self.update(&topic, TopicUpdate::Subscribe(self.node.get_addr())) {
             //println!("\t[AN{}]: subscribed to topic: {}", self.node.port, topic);
             return true
         }
-        
+        println!("\t[AN{}]: Error subscribing - couldn't find topic: {}", self.node.port, topic);
         false
     }
 
-    // TODO:
-    //  - verify node addr
-    //  - verify msg (tuple -> (number to raise bid; sender addr))
-    pub fn add_msg(&self, topic: String, msg: String) -> bool {
+    /*
+        Instance writes (but bids) are applied by the coordinator
+        of the topic (see coordinator.rs), true once stored.
+    */
+    fn update(&self, topic: &str, update: TopicUpdate) -> bool {
+        matches!(self.kademlia.submit_update(topic, update), Some(UpdateReceipt::Applied))
+    }
+
+    /*
+        Bids are stored by the coordinator of the topic (see
+        coordinator.rs) one at a time, a bid made on bids we
+        have not seen (stale) is rejected. Bids are made at
+        the block time of their escrow (hash of the escrow transaction,
+        see BidRequest).
+    */
+    pub fn add_msg(&self, topic: String, msg: String, escrow: Option<String>) -> bool {
         let pubsub = self.kademlia.get(topic.clone());
         if pubsub == None {
             println!("\t[AN{}]: Error adding msg - couldn't find topic: {}", self.node.port, topic)
@@ -172,11 +387,18 @@ impl AppNode {
             let pubsub_str = pubsub.unwrap();
             let pubsub_ins = self.get_pubsub_instance(pubsub_str).unwrap();
             if pubsub_ins.verify_addr(self.node.get_addr()) {
-                let status = pubsub_ins.add_msg(msg);
-                if status == 0 {
-                    self.kademlia.insert(topic.clone(), pubsub_ins.to_string());
-                    //println!("\t[AN{}]: added msg to topic: {}", self.node.port, topic);
-                    return true
+                let request = BidRequest { topic: topic.clone(), bidder: self.node.get_addr(), msg: msg.clone(), seq: pubsub_ins.seq(), escrow };
+                match self.kademlia.submit_bid(request) {
+                    Some(BidReceipt::Accepted { outbid, .. }) => {
+                        //println!("\t[AN{}]: added msg to topic: {}", self.node.port, topic);
+                        if let Some(bid) = Bid::from_msg(&msg) {
+                            let event = AuctionEvent::Bid { bid: bid.data, bidder: bid.sender_addr, outbid };
+                            self.push(&topic, &pubsub_ins, event);
+                        }
+                        return true
+                    },
+                    Some(BidReceipt::Stale { seq }) => println!("\t[AN{}]: Stale bid - {} bids on topic: {}", self.node.port, seq, topic),
+                    _ => {}
                 }
             }
         }
@@ -184,6 +406,68 @@ impl AppNode {
         false
     }
 
+    // Auction end is only ever extended (see softclose.rs)
+    pub fn set_ttl(&self, topic: String, ttl: DateTime<Local>) -> bool {
+        self.update(&topic, TopicUpdate::Extend(ttl.timestamp()))
+    }
+
+    // Early close: auction ends at (if before its ttl)
+    pub fn close(&self, topic: String, at: DateTime<Local>) -> bool {
+        self.update(&topic, TopicUpdate::Close(at.timestamp()))
+    }
+
+    pub fn cancel(&self, topic: String) -> bool {
+        self.update(&topic, TopicUpdate::Cancel)
+    }
+
+    // Event pushed to the publisher and subscribers of topic (see notify.rs)
+    pub fn notify(&self, topic: String, event: AuctionEvent) -> bool {
+        let pubsub_ins = match self.kademlia.get(topic.clone()).and_then(|pubsub| self.get_pubsub_instance(pubsub)) {
+            Some(pubsub_ins) => pubsub_ins,
+            None => return false
+        };
+        self.push(&topic, &pubsub_ins, event);
+        true
+    }
+
+    /*
+        Subscribers not acknowledging the event are counted as
+        missing it (dropped after MAX_MISSED in a row), misses
+        of subscribers acknowledging it are forgotten.
+    */
+    fn push(&self, topic: &str, pubsub_ins: &PubSubInstance, event: AuctionEvent) {
+        let appnode = self.clone();
+        let topic_str = topic.to_string();
+        let missing = pubsub_ins.clone();
+        let notification = Notification::new(topic, event).sign(&self.kademlia.identity, &self.node.get_addr());
+        self.kademlia.fan_out(pubsub_ins.audience(), notification, move |addr, reached| {
+            if !reached {
+                appnode.update(&topic_str, TopicUpdate::Missed(addr.to_string()));
+            } else if missing.missed(addr) > 0 {
+                appnode.update(&topic_str, TopicUpdate::Reached(addr.to_string()));
+            }
+        });
+    }
+
+    /*
+        Subscription leases (see PubSubInstance::prune_leases):
+            unsubscribe removes our subscription at once, renew
+            extends our lease while the auction is open and we
+            are still subscribed.
+    */
+    pub fn unsubscribe(&self, topic: String) -> bool {
+        self.update(&topic, TopicUpdate::Unsubscribe(self.node.get_addr()))
+    }
+
+    pub fn renew(&self, topic: String) -> bool {
+        self.update(&topic, TopicUpdate::Renew(self.node.get_addr()))
+    }
+
+    // Leaving the network, the topics we coordinate are handed over (see coordinator.rs)
+    pub fn shutdown(&self) {
+        self.kademlia.resign();
+    }
+
     // register method - arg: AppNode, Note: Added node timeout
     pub fn join_network(&self, bootnode: Node) -> bool {
         let find_node = full_rpc_proc(&self.kademlia.rpc, KademliaRequest::NodeJoin(self.node.clone()), bootnode.clone());
@@ -200,37 +484,35 @@ impl AppNode {
                     }
                 }
                 
-                let query_blockchain = full_rpc_proc(&self.kademlia.rpc, KademliaRequest::QueryLocalBlockChain, bootnode.clone());
-                if let Some(KademliaResponse::QueryLocalBlockChain(blocks)) = query_blockchain {
+                self.kademlia.pin_genesis(bootnode.clone());
+                self.kademlia.sync_from(bootnode.clone());
+                let data = Data::new(
+                    format!("REGISTER: {id}", id=self.node.get_addr()), 
+                    0,
+                    None
+                ).sign(&self.kademlia.identity);
+                if self.kademlia.consensus().scheduled() {
+                    // sealed by validators
+                    self.kademlia.submit_transaction(Transaction::new(data.to_json()));
+                    return true
+                }
+                let block = match self.kademlia.mine_next(data.to_json()) {
+                    Some(block) => block,
+                    None => return false
+                };
+
+                let add_block = full_rpc_proc(&self.kademlia.rpc, KademliaRequest::AddBlock(block), bootnode.clone());
+                if let Some(KademliaResponse::Ping) = add_block {
+                    println!("\t[AN{}]: Added Block info ({})", self.node.port, data.to_json());
+                    sleep(Duration::from_secs(NODETIMEOUT));
+                    return true
+                } else if let Some(KademliaResponse::PingUnableProcReq) = add_block {
                     let mut blockchain = self.kademlia.blockchain.lock()
                         .expect("Error setting lock in local blockchain");
-                    blockchain.blocks = blockchain.choose_chain(blockchain.blocks.clone(), blocks);
-
-                    let id = blockchain.blocks[blockchain.blocks.len() - 1].id + 1;
-                    let prev_hash = blockchain.blocks[blockchain.blocks.len() - 1].hash.to_string();
-                    let data = Data::new(
-                        format!("REGISTER: {id}", id=self.node.get_addr()), 
-                        0,
-                        None
-                    );
-                    let block = Block::new(id, prev_hash, data.to_json());
-
-                    blockchain.add_block(block.clone());
+                    blockchain.remove_last_block();
                     drop(blockchain);
-
-                    let add_block = full_rpc_proc(&self.kademlia.rpc, KademliaRequest::AddBlock(block), bootnode.clone());
-                    if let Some(KademliaResponse::Ping) = add_block {
-                        println!("\t[AN{}]: Added Block info ({})", self.node.port, data.to_json());
-                        sleep(Duration::from_secs(NODETIMEOUT));
-                        return true
-                    } else if let Some(KademliaResponse::PingUnableProcReq) = add_block {
-                        let mut blockchain = self.kademlia.blockchain.lock()
-                            .expect("Error setting lock in local blockchain");
-                        blockchain.remove_last_block();
-                        drop(blockchain);
-                        println!("\t[AN{}]: Unable to add block info ({})", self.node.port, data.to_json());
-                        return false
-                    }
+                    println!("\t[AN{}]: Unable to add block info ({})", self.node.port, data.to_json());
+                    return false
                 }
             } else {
                 println!("\t[AN{}]: Error joining network - No nearby nodes found", self.node.port)
@@ -243,62 +525,30 @@ impl AppNode {
     }
 
     pub fn add_block(&self, data: String) {
-        let block = self.mine_block(data.clone());
-        let mut blockchain = self.kademlia.blockchain.lock()
-            .expect("Error setting lock in local blockchain");
-        let res = blockchain.add_block(block.clone());
-        drop(blockchain);
+        if self.kademlia.consensus().scheduled() {
+            // sealed by in-turn validator (see Bootstrap::produce_blocks)
+            self.kademlia.submit_transaction(Transaction::new(data));
+            return
+        }
+        let res = self.kademlia.mine_next(data.clone());
         
         // ---
-        if res {
+        if res.is_some() {
             println!("\t[AN{}]: Added Block info ({})", self.node.port, data)
         }
     }
 
-    fn mine_block(&self, data: String) -> Block {
-        let blockchain = self.kademlia.blockchain.lock()
-            .expect("Error setting lock in local blockchain");
-        let id = blockchain.blocks[blockchain.blocks.len() - 1].id + 1;
-        let prev_hash = blockchain.blocks[blockchain.blocks.len() - 1].hash.to_string();
-        drop(blockchain);
-
-        Block::new(id, prev_hash, data)
-    }
-
-    // Used to sync bootstrap nodes (AppNode's)
+    // Used to sync bootstrap nodes (AppNode's), only missing blocks are fetched
     // TODO: change appnode to reference
     fn choose_chain(&self, appnode: AppNode) {
-        let query_blockchain = full_rpc_proc(&self.kademlia.rpc, KademliaRequest::QueryLocalBlockChain, appnode.node.clone());
-        if let Some(KademliaResponse::QueryLocalBlockChain(remoteblocks)) = query_blockchain {
-            let mut blockchain = self.kademlia.blockchain.lock()
-                .expect("Error setting lock in blockchain");
-            blockchain.blocks = blockchain.choose_chain(blockchain.blocks.clone(), remoteblocks.clone());
-            drop(blockchain);
-
-            // ---
-            // println!("\t[AN{}]: Updated blockchain ({:?})", self.node.port, remoteblocks)
-        }
+        self.kademlia.sync_from(appnode.node.clone());
     }
 
+    // Expired subscriptions are left out (see PubSubInstance::prune_leases)
     fn get_pubsub_instance(&self, data: String) -> Option<PubSubInstance> {
-        let decoded_data = String::from_utf8(decode(data.to_string()).expect("Error decoding data"))
-            .expect("Error converting data to string");
-        let pattern: &[_] = &['[', ']'];
-        let data_vec: Vec<&str> = decoded_data.split(";").collect();
-        if !data_vec.is_empty() {
-            let id: Option<String> = Some(String::from(data_vec[0]));
-            let publisher: String = String::from(data_vec[1]);
-            let substack: Vec<String> = data_vec[2].trim_matches(pattern).split(' ').map(|s| String::from(s)).collect();
-            let msgstack: Vec<String> = data_vec[3].trim_matches(pattern).split(' ').map(|s| String::from(s)).collect();
-            let mut pubsub = PubSubInstance::new(id, publisher, Some(msgstack), Some(substack));
-            let ttl_str: String = data_vec[4].to_string();
-            if ttl_str != "NONE" {
-                let ttl: DateTime<Local> = ttl_str.parse().unwrap(); // TODO: TEST
-                pubsub.set_ttl(ttl);
-            }
-            return Some(pubsub);
-        }
-        None
+        let pubsub = PubSubInstance::decode(&data)?;
+        pubsub.prune_leases(Local::now());
+        Some(pubsub)
     }
 
     pub fn get_pubsub_json(&self, topic: String) -> Value {
@@ -317,6 +567,13 @@ impl AppNode {
                     "num_subs": json["num_subs"],
                     "highest_bid": json["highest_bid"],
                     "highest_bidder": json["highest_bidder"],
+                    "kind": json["kind"],
+                    "clock_price": json["clock_price"],
+                    "soft_close": json["soft_close"],
+                    "spec": json["spec"],
+                    "cancelled": json["cancelled"],
+                    "price": json["price"],
+                    "winner": json["winner"],
                     "ttl": json["ttl"],
                     "subscribed": pubsub_ins.verify_addr(self.node.get_addr())
                 }
@@ -331,11 +588,10 @@ impl AppNode {
 
         if let Some(pubsub) = pubsub {
             if let QueryValueResult::Value(pubsub_str) = pubsub {
-                let pubsub_ins = self.get_pubsub_instance(pubsub_str).unwrap();
-                pubsub_ins.add_sub(self.node.get_addr());
-                self.kademlia.insert(topic.clone(), pubsub_ins.to_string());
-                println!("\t[AN{}]: subscribed to topic: {}", self.node.port, topic);
-                return true
+                if self.get_pubsub_instance(pubsub_str).is_some() && self.subscribe(topic.clone()) {
+                    println!("\t[AN{}]: subscribed to topic: {}", self.node.port, topic);
+                    return true
+                }
             }
             println!("\t[AN{}]: Error subscribing to topic: {}", self.node.port, topic)
         }
@@ -344,11 +600,20 @@ impl AppNode {
     }
 }
 
+/*
+    Ledger record (transaction):
+    Author is the public key (hex) of the node identity
+    that signed the record, see Blockchain::verify_txs
+*/
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Data {
     msg: String,
     msg_type: usize,
-    exp_time: Option<String>
+    exp_time: Option<String>,
+    #[serde(default)]
+    author: String,
+    #[serde(default)]
+    signature: String,
 }
 
 impl Data {
@@ -357,24 +622,77 @@ impl Data {
             msg: msg,
             msg_type: msg_type,
             exp_time: exp_time,
+            author: String::new(),
+            signature: String::new(),
         }
     }
 
+    pub fn from_json(tx: &str) -> Option<Self> {
+        serde_json::from_str(tx).ok()
+    }
+
     pub fn to_json(&self) -> String {
         serde_json::to_string(&self).unwrap()
     }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn msg_type(&self) -> usize {
+        self.msg_type
+    }
+
+    pub fn exp_time(&self) -> Option<&str> {
+        self.exp_time.as_deref()
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    // Signed record, author is bound to the signing identity
+    pub fn sign(mut self, identity: &Identity) -> Self {
+        self.author = identity.public_key();
+        self.signature = identity.sign(self.payload().as_bytes());
+        self
+    }
+
+    pub fn verify(&self) -> bool {
+        verify(&self.author, self.payload().as_bytes(), &self.signature)
+    }
+
+    fn payload(&self) -> String {
+        json!({
+            "msg": self.msg,
+            "msg_type": self.msg_type,
+            "exp_time": self.exp_time,
+            "author": self.author
+        }).to_string()
+    }
 }
 
+// Auction result (topic, bid, bidder, final), bid and bidder are empty if cancelled
+pub type AuctionResult = (String, String, String, bool);
+
 /* 
     App Instance:
     Prerequisites -> AppNode instance & Bootstrap node addr
     Keep BootAppNode reference, used for sync 
+    Light Apps keep only block headers (see light.rs)
 */
 #[derive(Clone)]
 pub struct App {
     pub appnode: AppNode,
     pub bootappnode: Node,
     pub topics: Arc<Mutex<Vec<(String, String, String)>>>,
+    // Auction results, pending until their block is final
+    pub results: Arc<Mutex<Vec<AuctionResult>>>,
+    // Committed sealed bids awaiting their reveal window
+    pub sealed_bids: Arc<Mutex<Vec<SealedBid>>>,
+    // Topics we subscribed to, their leases are renewed
+    pub subscriptions: Arc<Mutex<Vec<String>>>,
+    pub light: Option<LightClient>,
 }
 
 /*
@@ -388,14 +706,36 @@ pub struct App {
 */
 impl App {
     pub fn new(addr: String, port: u16, bootappnode: Node) -> Self  {
+        Self::with_signers(addr, port, bootappnode, Vec::new(), false)
+    }
+
+    // Light client mode: headers only, auction transactions fetched with merkle proofs
+    pub fn new_light(addr: String, port: u16, bootappnode: Node) -> Self  {
+        Self::with_signers(addr, port, bootappnode, Vec::new(), true)
+    }
+
+    // Checkpoints are only taken if signed by one of signers (bootstrap nodes keys)
+    pub fn with_signers(addr: String, port: u16, bootappnode: Node, signers: Vec<String>, light: bool) -> Self  {
         //let bootnode = bootappnode.node.clone();
         let node = bootappnode.clone();
         let appnode = AppNode::new(addr, port, Some(node));
+        let light = if light {
+            let light = LightClient::new(appnode.kademlia.clone());
+            light.chain.lock().get_guard().finality.signers = signers;
+            Some(light)
+        } else {
+            appnode.kademlia.blockchain.lock().get_guard().finality.signers = signers;
+            None
+        };
 
         let app = Self {
             appnode: appnode,
             bootappnode: bootappnode.clone(),
             topics: Arc::new(Mutex::new(Vec::new())),
+            results: Arc::new(Mutex::new(Vec::new())),
+            sealed_bids: Arc::new(Mutex::new(Vec::new())),
+            subscriptions: Arc::new(Mutex::new(Vec::new())),
+            light,
         };
 
         let mut register = app.join_network(bootappnode.clone());
@@ -408,10 +748,101 @@ impl App {
 
         App::pull_bk(app.clone());
         App::teardow_pubsub(app.clone());
+        App::renew_leases(app.clone());
 
         app
     }
 
+    /*
+        Chain helpers:
+            Full Apps sync/mine on their local chain, light Apps
+            on their header chain (mined block is only added
+            once the bootstrap node accepts it).
+    */
+    fn consensus(&self) -> Arc<dyn ConsensusEngine> {
+        match &self.light {
+            Some(light) => light.consensus(),
+            None => self.appnode.kademlia.consensus()
+        }
+    }
+
+    fn sync_chain(&self, node: Node) -> Vec<Block> {
+        match &self.light {
+            Some(light) => {
+                light.sync(node);
+                Vec::new()
+            },
+            None => self.appnode.kademlia.sync_from(node)
+        }
+    }
+
+    fn mine_next(&self, data: String) -> Option<Block> {
+        match &self.light {
+            Some(light) => light.mine_next(data),
+            None => self.appnode.kademlia.mine_next(data)
+        }
+    }
+
+    /*
+        Finality of a block (id, hash):
+            None until id is at our finalized height, then
+            whether the block is (still) the one in our chain.
+    */
+    fn finalized(&self, id: u64, hash: &str) -> Option<bool> {
+        match &self.light {
+            Some(light) => {
+                let chain = light.chain.lock().get_guard();
+                if id > chain.finalized_height() {
+                    return None
+                }
+                Some(chain.is_final(id, hash))
+            },
+            None => {
+                let blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+                if id > blockchain.finalized_height() {
+                    return None
+                }
+                Some(blockchain.is_final(id, hash))
+            }
+        }
+    }
+
+    // Latest checkpoint of our bootstrap node (signed by one of our configured signers)
+    fn sync_checkpoint(&self) {
+        let checkpoint = match self.appnode.kademlia.query_checkpoint(self.bootappnode.clone()) {
+            Some(checkpoint) => checkpoint,
+            None => return
+        };
+        match &self.light {
+            Some(light) => {
+                let mut chain = light.chain.lock().get_guard();
+                chain.add_checkpoint(checkpoint);
+                drop(chain);
+            },
+            None => {
+                let mut blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+                blockchain.add_checkpoint(checkpoint);
+                drop(blockchain);
+            }
+        }
+    }
+
+    // Blocks on top of an END_TOPIC block before its result is final
+    pub fn set_confirmations(&self, depth: u64) {
+        match &self.light {
+            Some(light) => light.chain.lock().get_guard().finality.confirmations = depth,
+            None => self.appnode.kademlia.blockchain.lock().get_guard().finality.confirmations = depth
+        }
+    }
+
+    fn rollback(&self) {
+        if self.light.is_none() {
+            let mut blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+            blockchain.remove_last_block();
+            drop(blockchain);
+        }
+    }
+
     // register method - arg: AppNode, Note: Added node timeout
     /*
         Based on the AppNode join_network function but with small
@@ -435,77 +866,73 @@ impl App {
                     }
                 }
                 
-                let query_blockchain = full_rpc_proc(&self.appnode.kademlia.rpc, KademliaRequest::QueryLocalBlockChain, bootnode.clone());
-                if let Some(KademliaResponse::QueryLocalBlockChain(blocks)) = query_blockchain {
-                    let mut blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
-                    let new_blocks = blockchain.get_diff_from_chains(blockchain.blocks.clone(), blocks.clone());
-                    blockchain.blocks = blockchain.choose_chain(blockchain.blocks.clone(), blocks);
-
-                    let id = blockchain.blocks[blockchain.blocks.len() - 1].id + 1;
-                    let prev_hash = blockchain.blocks[blockchain.blocks.len() - 1].hash.to_string();
-                    let data = Data::new(
-                        format!("REGISTER: {id}", id=self.appnode.node.get_addr()), 
-                        0,
-                        None
-                    );
-                    let block = Block::new(id, prev_hash, data.to_json());
-
-                    blockchain.add_block(block.clone());
-                    drop(blockchain);
+                if self.light.is_none() {
+                    self.appnode.kademlia.pin_genesis(bootnode.clone());
+                }
+                // snapshot signers known before pruned blocks arrive
+                self.sync_checkpoint();
+                let new_blocks = self.sync_chain(bootnode.clone());
+                self.load_snapshot();
+                let data = Data::new(
+                    format!("REGISTER: {id}", id=self.appnode.node.get_addr()), 
+                    0,
+                    None
+                ).sign(&self.appnode.kademlia.identity);
+                if self.consensus().scheduled() {
+                    // sealed by validators, history is processed by pull_bk
+                    self.appnode.kademlia.submit_transaction(Transaction::new(data.to_json()));
+                    return true
+                }
+                let block = match self.mine_next(data.to_json()) {
+                    Some(block) => block,
+                    None => return false
+                };
 
-                    let add_block = full_rpc_proc(&self.appnode.kademlia.rpc, KademliaRequest::AddBlock(block), bootnode.clone());
-                    if let Some(KademliaResponse::Ping) = add_block {
-                        println!("\t[AN{}]: Added Block info ({})", self.appnode.node.port, data.to_json());
-
-                        for new_block in new_blocks.clone() {
-                            if new_block.id != 0 {
-                                let data: Data = serde_json::from_str(&new_block.data).
-                                    expect("Error converting data to json");
-                                match data.msg_type {
-                                    0 => {},
-                                    1 => {
-                                        let ttl: DateTime<Local> = data.exp_time.unwrap().parse().unwrap();
-                                        let ttl_str = format!("{}", ttl);
-                                        let time = Local::now();
-                                        let diff = (ttl - time).num_minutes();
-                                        if diff > 0 {
-                                            let mut topics = self.topics.lock().get_guard();
-                                            let msg_split: Vec<&str> = data.msg.split('|').collect();
-                                            let topic_split: Vec<&str> = msg_split[0].split(' ').collect();
-                                            let publisher_split: Vec<&str> = msg_split[1].split(' ').collect();
-                                            let topic_entry = (topic_split[1].to_string(), ttl_str, publisher_split[1].to_string());
-                                            if !topics.contains(&topic_entry) {
-                                                topics.push(topic_entry);
-                                            }
-                                            drop(topics);
-                                        }
-                                        // App::teardow_pubsub(app.clone(), 1 * 60);
-                                    },
-                                    2 => {
+                let add_block = full_rpc_proc(&self.appnode.kademlia.rpc, KademliaRequest::AddBlock(block), bootnode.clone());
+                if let Some(KademliaResponse::Ping) = add_block {
+                    println!("\t[AN{}]: Added Block info ({})", self.appnode.node.port, data.to_json());
+
+                    for new_block in new_blocks.clone() {
+                        if new_block.id == 0 {
+                            continue
+                        }
+                        for tx in new_block.transactions() {
+                            let data: Data = match serde_json::from_str(&tx) {
+                                Ok(data) => data,
+                                Err(_) => continue
+                            };
+                            match data.msg_type {
+                                0 => {},
+                                1 => {
+                                    let ttl: DateTime<Local> = data.exp_time.unwrap().parse().unwrap();
+                                    let ttl_str = format!("{}", ttl);
+                                    let time = Local::now();
+                                    let diff = (ttl - time).num_minutes();
+                                    if diff > 0 {
                                         let mut topics = self.topics.lock().get_guard();
-                                        let data_split: Vec<&str> = data.msg.split('|').collect();
-                                        let topic: Vec<&str> = data_split[0].split(' ').collect();
-                                        let index = topics.iter().position(|(x, _, _)| *x == topic[1]);
-                                        if index != None {
-                                            topics.remove(index.unwrap());
+                                        let msg_split: Vec<&str> = data.msg.split('|').collect();
+                                        let topic_split: Vec<&str> = msg_split[0].split(' ').collect();
+                                        let publisher_split: Vec<&str> = msg_split[1].split(' ').collect();
+                                        let topic_entry = (topic_split[1].to_string(), ttl_str, publisher_split[1].to_string());
+                                        if !topics.contains(&topic_entry) {
+                                            topics.push(topic_entry);
                                         }
                                         drop(topics);
-                                        // bid -> data_split[1] | bidder -> data_split[2]
-                                    },
-                                    _ => {},
-                                };
-                            }
+                                    }
+                                    // App::teardow_pubsub(app.clone(), 1 * 60);
+                                },
+                                2 => {}, // reported once final (see pull_bk)
+                                _ => {},
+                            };
                         }
-
-                        sleep(Duration::from_secs(NODETIMEOUT));
-                        return true
-                    } else if let Some(KademliaResponse::PingUnableProcReq) = add_block {
-                        let mut blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
-                        blockchain.remove_last_block();
-                        drop(blockchain);
-                        println!("\t[AN{}]: Unable to add block info ({})", self.appnode.node.port, data.to_json());
-                        return false
                     }
+
+                    sleep(Duration::from_secs(NODETIMEOUT));
+                    return true
+                } else if let Some(KademliaResponse::PingUnableProcReq) = add_block {
+                    self.rollback();
+                    println!("\t[AN{}]: Unable to add block info ({})", self.appnode.node.port, data.to_json());
+                    return false
                 }
             } else {
                 println!("\t[AN{}]: Error joining network - No nearby nodes found", self.appnode.node.port)
@@ -517,68 +944,188 @@ impl App {
         false   
     }
 
+    // Fast bootstrap: active auctions of a verified snapshot (bodies behind it are pruned)
+    fn load_snapshot(&self) {
+        let blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+        let snapshot = blockchain.snapshot.clone();
+        drop(blockchain);
+
+        if let Some(snapshot) = snapshot {
+            let mut topics = self.topics.lock().get_guard();
+            for (topic, auction) in snapshot.state.auctions {
+                let ttl: DateTime<Local> = match auction.exp_time.and_then(|ttl| ttl.parse().ok()) {
+                    Some(ttl) => ttl,
+                    None => continue
+                };
+                let topic_entry = (topic, format!("{}", ttl), auction.publisher);
+                if (ttl - Local::now()).num_seconds() > 0 && !topics.contains(&topic_entry) {
+                    topics.push(topic_entry);
+                }
+            }
+            drop(topics);
+        }
+    }
+
+    /*
+        Blocks may be added by sync (below) or by block announcements,
+        thus every block after the last processed one is analyzed.
+        END_TOPIC results are reported as pending once mined and
+        as final once their block is final (blocks may take long
+        to come), results of blocks reorganized away are dropped
+        (the transaction is pulled again from the new block).
+        On a reorg processing resumes at the fork point (cursors
+        of light Apps are moved back to it).
+        CANCEL_TOPIC is handled alike (reported without a sale).
+    */
     fn pull_bk(app: App) {
         spawn(move || {
+            // hashes (by height) of the chain processed so far
+            let mut processed: Vec<String> = Vec::new();
+            // light Apps: (PUB_TOPIC, END_TOPIC, EXTEND, CANCEL_TOPIC) transactions already pulled
+            let mut cursors: [TxRef; 4] = [(0, 0); 4];
+            // END_TOPIC (block id, block hash, data) not yet final
+            let mut pending: Vec<(u64, String, Data)> = Vec::new();
             loop {
                 sleep(Duration::from_secs(NODETIMEOUT * 2));
-                let query_blockchain = full_rpc_proc(&app.appnode.kademlia.rpc, KademliaRequest::QueryLocalBlockChain, app.bootappnode.clone());
-                if let Some(KademliaResponse::QueryLocalBlockChain(blocks)) = query_blockchain {
-                    let mut blockchain = match app.appnode.kademlia.blockchain.lock() {
-                        Ok(blockchain) => blockchain,
+                app.sync_checkpoint();
+                app.reveal_bids();
+                app.sync_chain(app.bootappnode.clone());
+                let txs: Vec<(u64, String, String)> = match &app.light {
+                    Some(light) => {
+                        let chain = light.chain.lock().get_guard().headers.iter().map(|header| header.hash.clone()).collect();
+                        let fork = App::fork_point(&mut processed, chain);
+                        if fork > 0 {
+                            let resume: TxRef = (fork as u64 - 1, usize::MAX);
+                            cursors.iter_mut().for_each(|cursor| *cursor = (*cursor).min(resume));
+                        }
+                        let mut proofs = light.pull_txs(app.bootappnode.clone(), 1, &mut cursors[0]);
+                        proofs.extend(light.pull_txs(app.bootappnode.clone(), 2, &mut cursors[1]));
+                        proofs.extend(light.pull_txs(app.bootappnode.clone(), EXTEND_MSG_TYPE, &mut cursors[2]));
+                        proofs.extend(light.pull_txs(app.bootappnode.clone(), CANCEL_MSG_TYPE, &mut cursors[3]));
+                        proofs.sort_by_key(|proof| (proof.block_id, proof.index));
+                        proofs.into_iter().map(|proof| (proof.block_id, proof.block_hash, proof.tx)).collect()
+                    },
+                    None => {
+                        let blockchain = app.appnode.kademlia.blockchain.lock().get_guard();
+                        let fork = App::fork_point(&mut processed, blockchain.blocks.iter().map(|block| block.hash.clone()).collect());
+                        let new_blocks = blockchain.blocks[fork..].to_vec();
+                        drop(blockchain);
+                        new_blocks.iter()
+                            .filter(|block| block.id != 0)
+                            .flat_map(|block| block.transactions().into_iter().map(|tx| (block.id, block.hash.clone(), tx)))
+                            .collect()
+                    }
+                };
+
+                for (block_id, block_hash, tx) in txs {
+                    let data: Data = match serde_json::from_str(&tx) {
+                        Ok(data) => data,
                         Err(_) => continue
                     };
-                    let new_blocks = blockchain.get_diff_from_chains(blockchain.blocks.clone(), blocks.clone());
-                    blockchain.blocks = blockchain.choose_chain(blockchain.blocks.clone(), blocks);
-                    drop(blockchain);
-
-                    for block in new_blocks.clone() {
-                        let data: Data = serde_json::from_str(&block.data).
-                            expect("Error converting data to json");
-                        match data.msg_type {
-                            0 => {},
-                            1 => {
-                                let ttl: DateTime<Local> = data.exp_time.unwrap().parse().unwrap();
-                                let ttl_str = format!("{}", ttl);
-                                let time = Local::now();
-                                let diff = (ttl - time).num_seconds();
-                                if diff > 0 {
-                                    let mut topics = match app.topics.lock() {
-                                        Ok(topics) => topics,
-                                        Err(_) => continue
-                                    };
-                                    let msg_split: Vec<&str> = data.msg.split('|').collect();
-                                    let topic_split: Vec<&str> = msg_split[0].split(' ').collect();
-                                    let publisher_split: Vec<&str> = msg_split[1].split(' ').collect();
-                                    let topic_entry = (topic_split[1].to_string(), ttl_str, publisher_split[1].to_string());
-                                    if !topics.contains(&topic_entry) {
-                                        topics.push(topic_entry);
-                                    }
-                                    drop(topics)
-                                }
-                            },
-                            2 => {
+                    match data.msg_type {
+                        0 => {},
+                        1 => {
+                            let ttl: DateTime<Local> = data.exp_time.unwrap().parse().unwrap();
+                            let ttl_str = format!("{}", ttl);
+                            let time = Local::now();
+                            let diff = (ttl - time).num_seconds();
+                            if diff > 0 {
                                 let mut topics = match app.topics.lock() {
                                     Ok(topics) => topics,
                                     Err(_) => continue
                                 };
-                                let data_split: Vec<&str> = data.msg.split('|').collect();
-                                let topic: Vec<&str> = data_split[0].split(' ').collect();
-                                let index = topics.iter().position(|(x, _, _)| *x == topic[1]);
-                                if index != None {
-                                    topics.remove(index.unwrap());
+                                let msg_split: Vec<&str> = data.msg.split('|').collect();
+                                let topic_split: Vec<&str> = msg_split[0].split(' ').collect();
+                                let publisher_split: Vec<&str> = msg_split[1].split(' ').collect();
+                                let topic_entry = (topic_split[1].to_string(), ttl_str, publisher_split[1].to_string());
+                                if !topics.contains(&topic_entry) {
+                                    topics.push(topic_entry);
                                 }
                                 drop(topics)
-                                // bid -> data_split[1] | bidder -> data_split[2]
-                            },
-                            _ => {},
-                        };
-                    }
+                            }
+                        },
+                        2 | CANCEL_MSG_TYPE => {
+                            app.stop_bids(&data);
+                            app.report_result(&data, false);
+                            pending.push((block_id, block_hash, data))
+                        },
+                        EXTEND_MSG_TYPE => {
+                            if let Some(topic) = msg_fields(&data.msg).get("EXTEND") {
+                                app.apply_extension(topic);
+                            }
+                        },
+                        _ => {},
+                    };
                 }
 
+                pending.retain(|(block_id, block_hash, data)| {
+                    match app.finalized(*block_id, block_hash) {
+                        Some(true) => app.report_result(data, true),
+                        Some(false) => app.withdraw_result(data),
+                        None => return true
+                    }
+                    false
+                });
+
             }
         });
     }
 
+    // Number of leading hashes shared by the processed chain and chain (which becomes processed)
+    fn fork_point(processed: &mut Vec<String>, chain: Vec<String>) -> usize {
+        let fork = processed.iter().zip(&chain).take_while(|(old, new)| old == new).count();
+        *processed = chain;
+        fork
+    }
+
+    // Auction we published ended or cancelled on chain: its DHT instance takes no further bids
+    fn stop_bids(&self, data: &Data) {
+        if data.author() != self.appnode.kademlia.identity.public_key() {
+            return
+        }
+        let record = msg_fields(&data.msg);
+        if let Some(topic) = record.get("END_TOPIC") {
+            self.appnode.close(topic.to_string(), Local::now());
+        }
+        if let Some(topic) = record.get("CANCEL_TOPIC") {
+            self.appnode.cancel(topic.to_string());
+        }
+    }
+
+    // Result (topic, bid, bidder) recorded by an END_TOPIC/CANCEL_TOPIC
+    fn result_of(data: &Data) -> (String, String, String) {
+        let data_split: Vec<&str> = data.msg.split('|').collect();
+        let topic: Vec<&str> = data_split[0].split(' ').collect();
+        let bid = data_split.get(1).and_then(|bid| bid.strip_prefix("BID: ")).unwrap_or_default();
+        let bidder = data_split.get(2).and_then(|bidder| bidder.strip_prefix("BIDDER: ")).unwrap_or_default();
+        (topic[1].to_string(), bid.to_string(), bidder.to_string())
+    }
+
+    // Auction ended: result reported as pending (mined), then final (topic is closed)
+    fn report_result(&self, data: &Data, is_final: bool) {
+        let (topic, bid, bidder) = App::result_of(data);
+
+        if is_final {
+            let mut topics = self.topics.lock().get_guard();
+            topics.retain(|(x, _, _)| *x != topic);
+            drop(topics);
+        }
+
+        let result = (topic, bid, bidder, is_final);
+        let mut results = self.results.lock().get_guard();
+        results.retain(|(x, bid, bidder, _)| (x, bid, bidder) != (&result.0, &result.1, &result.2));
+        results.push(result);
+        drop(results);
+    }
+
+    // Pending result of a block reorganized away
+    fn withdraw_result(&self, data: &Data) {
+        let (topic, bid, bidder) = App::result_of(data);
+        let mut results = self.results.lock().get_guard();
+        results.retain(|result| *result != (topic.clone(), bid.clone(), bidder.clone(), false));
+        drop(results);
+    }
+
     fn teardow_pubsub(app: App) {
         spawn(move || {
             loop {
@@ -591,20 +1138,23 @@ impl App {
                 if topics_state.len() == 0 {
                     sleep(Duration::from_secs(NODETIMEOUT * 50));
                 } else {
+                    sleep(Duration::from_secs(NODETIMEOUT));
                     let mut topic_to_delete: String = String::from("");
                     for (topic, ttl_str, publisher_addr) in topics_state.clone() {
                         let ttl: DateTime<Local> = ttl_str.parse().unwrap();
                         let diff: i64 = (ttl - Local::now()).num_seconds();
                         if diff <= 0 && publisher_addr == app.appnode.node.get_addr() {
-                            topic_to_delete = topic.clone();
                             let json = app.get_json(topic.clone());
-                            app.pull_bk_add_block(
-                                Data::new(
-                                    format!("END_TOPIC: {}|BID: {}|BIDDER: {}", topic, json["highest_bid"], json["highest_bidder"]), 
-                                    2, 
-                                    None
-                                )
-                            );
+                            // soft close: end is taken from the chain (late bids may extend it)
+                            if json["soft_close"].is_object() && app.apply_extension(&topic).is_some_and(|end| end > ttl) {
+                                continue
+                            }
+                            let kind: AuctionKind = serde_json::from_value(json["kind"].clone()).unwrap_or_default();
+                            if kind.sealed() && diff >= -REVEAL_WINDOW {
+                                continue // reveal window still open
+                            }
+                            topic_to_delete = topic.clone();
+                            app.end_auction(&topic);
                             break
                         }
                         if topic_to_delete != "" {
@@ -626,17 +1176,40 @@ impl App {
     }
 
     pub fn publish(&self, topic: String) -> bool {
-        let timeout_mins: i64 = 2; // 15
-        let ttl = Local::now() + chrono::Duration::minutes(timeout_mins);
+        let spec = AuctionSpec::lasting(&topic, DEFAULT_DURATION_MINS);
+        self.publish_auction(topic, spec, AuctionKind::default(), None)
+    }
+
+    /*
+        Auction spec, kind (priced by the spec) and soft-close rule
+        are recorded in the ledger (PUB_TOPIC) and in the DHT instance,
+        the auction ends at the spec end time. Sealed auctions have
+        no soft close (bids are only known after the deadline).
+        A bundle takes its topics (auctions of ours), they end
+        with it instead.
+    */
+    pub fn publish_auction(&self, topic: String, spec: AuctionSpec, kind: AuctionKind, soft_close: Option<SoftClose>) -> bool {
+        if !spec.valid() {
+            return false
+        }
+        let ttl = spec.end();
         let ttl_str = format!("{}", ttl);
-        let data = Data::new(
-            format!("PUB_TOPIC: {}|PUBLISHER: {}", topic, self.appnode.node.get_addr()), 
-            1,
-            Some(format!("{}", ttl)),
-        );
+        let kind = spec.price(kind);
+        let soft_close = soft_close.filter(|_| !kind.sealed());
+        let mut msg = format!("PUB_TOPIC: {}|PUBLISHER: {}|KIND: {}|SPEC: {}", topic, self.appnode.node.get_addr(), kind.to_json(), spec.encode());
+        if let Some(rule) = soft_close {
+            msg.push_str(&format!("|SOFT_CLOSE: {}", rule.to_json()));
+        }
+        let data = Data::new(msg, 1, Some(format!("{}", ttl)));
         if self.pull_bk_add_block(data.clone()) {
+            if let AuctionKind::Bundle { topics: lots, .. } = &kind {
+                for lot in lots {
+                    self.remove_topic(lot);
+                }
+            }
             // TODO: error handeling
-            self.appnode.publish(topic.clone(), ttl);
+            self.appnode.kademlia.index_keywords(&topic, &keywords(&topic, Some(&spec)));
+            self.appnode.publish_auction(topic.clone(), spec, kind, soft_close);
 
             let topic_entry = (topic, ttl_str, self.appnode.node.get_addr());
             let mut topics = self.topics.lock().expect("Error settig lock in topics");
@@ -650,6 +1223,83 @@ impl App {
         false
     }
 
+    /*
+        Seller actions (publisher only):
+            cancel_auction withdraws the listing (no sale) and
+            close_auction_now ends it at the current bid, both are
+            ledger records signed by the publisher key. The DHT
+            instance is then marked by the publisher (the topic
+            coordinator takes it once the record is in its chain,
+            retried as the record is pulled, see stop_bids).
+            Sealed auctions are only closed at their deadline.
+    */
+    pub fn cancel_auction(&self, topic: String) -> bool {
+        if !self.is_publisher(&topic) {
+            return false
+        }
+        let data = Data::new(cancel_msg(&topic, &self.appnode.node.get_addr()), CANCEL_MSG_TYPE, None);
+        if !self.pull_bk_add_block(data) {
+            return false
+        }
+        self.appnode.cancel(topic.clone());
+        self.appnode.notify(topic.clone(), AuctionEvent::Cancelled);
+        self.remove_topic(&topic);
+        sleep(Duration::from_secs(NODETIMEOUT));
+        true
+    }
+
+    pub fn close_auction_now(&self, topic: String) -> bool {
+        if !self.is_publisher(&topic) {
+            return false
+        }
+        let kind: AuctionKind = serde_json::from_value(self.get_json(topic.clone())["kind"].clone()).unwrap_or_default();
+        if kind.sealed() || !self.end_auction(&topic) {
+            return false
+        }
+        self.appnode.close(topic.clone(), Local::now());
+        self.remove_topic(&topic);
+        sleep(Duration::from_secs(NODETIMEOUT));
+        true
+    }
+
+    /*
+        END_TOPIC records the outcome of the bids on chain (the one
+        the engine takes), multi kinds the allocation of their
+        escrowed bids, price and winner are its revenue and first
+        winner.
+    */
+    fn end_auction(&self, topic: &str) -> bool {
+        let auction = self.auction(topic);
+        let (price, winner) = auction.as_ref().and_then(|auction| auction.expected_outcome()).unzip();
+        let allocation = auction.map_or(Vec::new(), |auction| auction.expected_allocation());
+        let mut msg = format!("END_TOPIC: {}|BID: {}|BIDDER: {}", topic, json!(price), json!(winner));
+        if !allocation.is_empty() {
+            msg.push_str(&format!("|ALLOCATION: {}", allocation_msg(&allocation)));
+        }
+        let ended = self.pull_bk_add_block(
+            Data::new(
+                msg, 
+                2, 
+                None
+            )
+        );
+        if ended {
+            self.appnode.notify(topic.to_string(), AuctionEvent::Ended { price, winner });
+        }
+        ended
+    }
+
+    fn is_publisher(&self, topic: &str) -> bool {
+        let addr = self.appnode.node.get_addr();
+        self.get_topics().iter().any(|(x, _, publisher)| x == topic && *publisher == addr)
+    }
+
+    fn remove_topic(&self, topic: &str) {
+        let mut topics = self.topics.lock().get_guard();
+        topics.retain(|(x, _, _)| x != topic);
+        drop(topics);
+    }
+
     // TODO: - Retry mech
     // Maybe add timeout before return (?)
     pub fn subscribe(&self, topic: String) -> bool {
@@ -662,7 +1312,14 @@ impl App {
             if topic == topic_state.0 {
                 let ttl: DateTime<Local> = topic_state.1.parse().unwrap();
                 if (ttl - Local::now()).num_seconds() > 0 {
-                    sub = self.appnode.subscribe(topic);
+                    sub = self.appnode.subscribe(topic.clone());
+                    if sub {
+                        let mut subscriptions = self.subscriptions.lock().get_guard();
+                        if !subscriptions.contains(&topic) {
+                            subscriptions.push(topic);
+                        }
+                        drop(subscriptions);
+                    }
                     sleep(Duration::from_secs(NODETIMEOUT));
                     break
                 }
@@ -672,18 +1329,345 @@ impl App {
         sub
     }
 
+    pub fn unsubscribe(&self, topic: String) -> bool {
+        let mut subscriptions = self.subscriptions.lock().get_guard();
+        subscriptions.retain(|x| *x != topic);
+        drop(subscriptions);
+        self.appnode.unsubscribe(topic)
+    }
+
+    // Leaving: our subscriptions are dropped, then our node resigns
+    pub fn shutdown(&self) {
+        let subscriptions = self.subscriptions.lock().get_guard();
+        let topics = subscriptions.clone();
+        drop(subscriptions);
+        for topic in topics {
+            self.unsubscribe(topic);
+        }
+        self.appnode.shutdown();
+    }
+
+    /*
+        Leases of our subscriptions are renewed (well before they
+        expire), subscriptions that can no longer be renewed (auction
+        over, or we were dropped as unreachable) are forgotten.
+    */
+    fn renew_leases(app: App) {
+        spawn(move || {
+            loop {
+                sleep(Duration::from_secs((SUBSCRIPTION_LEASE / 3) as u64));
+                let subscriptions = app.subscriptions.lock().get_guard();
+                let topics = subscriptions.clone();
+                drop(subscriptions);
+                let expired: Vec<String> = topics.into_iter()
+                    .filter(|topic| !app.appnode.renew(topic.clone()))
+                    .collect();
+                let mut subscriptions = app.subscriptions.lock().get_guard();
+                subscriptions.retain(|topic| !expired.contains(topic));
+                drop(subscriptions);
+            }
+        });
+    }
+
     // TODO: - Retry mech
     pub fn add_msg(&self, topic: String, msg: String) -> bool {
         // bid X (only)
         let msg_split: Vec<&str> = msg.split(' ').collect();
         let raise: usize = msg_split[1].parse::<usize>().unwrap();
-        let res_msg = json!({"data": raise, "sender_addr": self.appnode.node.get_addr()});
-        let status = self.appnode.add_msg(topic, res_msg.to_string());
+        let json = self.get_json(topic.clone());
+        let kind: AuctionKind = serde_json::from_value(json["kind"].clone()).unwrap_or_default();
+        if kind.sealed() {
+            return json["subscribed"] == true && self.commit_bid(topic, raise, json["ttl"].as_str())
+        }
+        self.add_bid(&topic, Bid::new(raise, self.appnode.node.get_addr()), &json)
+    }
+
+    /*
+        Multi-unit and bundle bids (see multiunit.rs), escrowed
+        like any other, the seller records the allocation of the
+        escrowed bids on END_TOPIC.
+    */
+    pub fn bid_units(&self, topic: String, price: usize, quantity: usize) -> bool {
+        let mut bid = Bid::new(price, self.appnode.node.get_addr());
+        bid.quantity = quantity;
+        self.add_bid(&topic, bid, &self.get_json(topic.clone()))
+    }
+
+    pub fn bid_bundle(&self, topic: String, items: Vec<String>, price: usize) -> bool {
+        let mut bid = Bid::new(price, self.appnode.node.get_addr());
+        bid.items = items;
+        self.add_bid(&topic, bid, &self.get_json(topic.clone()))
+    }
+
+    // The bid is only taken by the DHT once its escrow is mined (at its block time)
+    fn add_bid(&self, topic: &str, bid: Bid, json: &Value) -> bool {
+        if !self.can_fund(topic, bid.total() as u64) {
+            return false
+        }
+        let (at, escrow) = match self.escrow(topic, &bid) {
+            Some(escrow) => escrow,
+            None => return false
+        };
+        let status = self.appnode.add_msg(topic.to_string(), json!(bid).to_string(), Some(escrow));
+        if status {
+            self.extend_auction(topic, json, at);
+        }
 
         sleep(Duration::from_secs(NODETIMEOUT));
         status
     }
 
+    /*
+        Escrow:
+            Funds of a bid are locked on chain before the DHT takes
+            it, the previous highest bidder is refunded and the seller
+            is paid on END_TOPIC (see accounts.rs). Light Apps hold
+            no state, their funds are answered by their bootstrap node.
+    */
+    fn can_fund(&self, topic: &str, amount: u64) -> bool {
+        let addr = self.appnode.node.get_addr();
+        if self.light.is_some() {
+            return self.appnode.kademlia.query_funds(self.bootappnode.clone(), topic, &addr).is_some_and(|funds| funds >= amount)
+        }
+        let blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+        let funds = blockchain.with_tip_state(|state| state.funds(topic, &addr));
+        drop(blockchain);
+        funds.is_some_and(|funds| funds >= amount)
+    }
+
+    // Block time and hash of the escrow of our bid once mined
+    fn escrow(&self, topic: &str, bid: &Bid) -> Option<(i64, String)> {
+        let data = Data::new(bid_escrow_msg(topic, bid), ESCROW_MSG_TYPE, None)
+            .sign(&self.appnode.kademlia.identity);
+        if !self.pull_bk_add_block(data.clone()) {
+            return None
+        }
+        Some((self.mined(&data)?, tx_hash(&data.to_json())))
+    }
+
+    /*
+        Block time of a transaction (signed data) once mined:
+        full Apps find it in their chain, light Apps in the proofs of
+        their bootstrap node. On scheduled networks it is awaited
+        until sealed by validators.
+    */
+    fn mined(&self, data: &Data) -> Option<i64> {
+        let tx = data.to_json();
+        for _ in 0..MINED_POLLS {
+            let entry = match &self.light {
+                Some(_) => self.tx_entries(ProofQuery::Tx(tx_hash(&tx))).into_iter().find(|entry| entry.data == tx),
+                None => {
+                    self.sync_chain(self.bootappnode.clone());
+                    let blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+                    let entry = blockchain.tx_by_hash(&tx_hash(&tx));
+                    drop(blockchain);
+                    entry
+                }
+            };
+            if let Some(entry) = entry {
+                return Some(entry.timestamp)
+            }
+            sleep(Duration::from_secs(NODETIMEOUT));
+        }
+        None
+    }
+
+    // Available units of our address (None for light Apps)
+    pub fn balance(&self) -> Option<u64> {
+        if self.light.is_some() {
+            return None
+        }
+        let blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+        let balance = blockchain.balance(&self.appnode.node.get_addr());
+        drop(blockchain);
+        balance
+    }
+
+    // Reputation of addr at our tip (None for light Apps)
+    pub fn reputation(&self, addr: &str) -> Option<Reputation> {
+        if self.light.is_some() {
+            return None
+        }
+        let blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+        let reputation = blockchain.reputation(addr);
+        drop(blockchain);
+        reputation
+    }
+
+    // Rate our counterparty (rated) of a settled topic, once
+    pub fn leave_feedback(&self, topic: &str, rated: &str, positive: bool) -> bool {
+        let data = Data::new(feedback_msg(topic, &self.appnode.node.get_addr(), rated, positive), FEEDBACK_MSG_TYPE, None);
+        self.pull_bk_add_block(data)
+    }
+
+    /*
+        Soft close:
+            A bid escrowed (at block time) within the soft-close
+            window is recorded on chain (EXTEND), every node then
+            extends the auction end alike (see engine.rs).
+    */
+    fn extend_auction(&self, topic: &str, json: &Value, at: i64) {
+        let rule: SoftClose = match serde_json::from_value(json["soft_close"].clone()) {
+            Ok(rule) => rule,
+            Err(_) => return
+        };
+        let end: DateTime<Local> = match json["ttl"].as_str().and_then(|ttl| ttl.parse().ok()) {
+            Some(end) => end,
+            None => return
+        };
+        if !rule.triggered(end, at) {
+            return
+        }
+        let data = Data::new(extend_msg(topic, &self.appnode.node.get_addr()), EXTEND_MSG_TYPE, None);
+        if self.pull_bk_add_block(data) {
+            if let Some(end) = self.apply_extension(topic) {
+                self.appnode.notify(topic.to_string(), AuctionEvent::Extended { ttl: format!("{}", end) });
+            }
+        }
+    }
+
+    // Auction end (topics, DHT instance) follows its effective end on chain
+    fn apply_extension(&self, topic: &str) -> Option<DateTime<Local>> {
+        let end = local(self.auction(topic)?.end);
+        let mut topics = self.topics.lock().get_guard();
+        for topic_entry in topics.iter_mut().filter(|(x, _, _)| x == topic) {
+            topic_entry.1 = format!("{}", end);
+        }
+        drop(topics);
+        self.appnode.set_ttl(topic.to_string(), end);
+        Some(end)
+    }
+
+    /*
+        Sealed bid (commit phase):
+            Only the commitment (hash of bid and a random salt)
+            is recorded on chain, bid and salt are kept until
+            the auction deadline (ttl) passes.
+    */
+    fn commit_bid(&self, topic: String, bid: usize, ttl: Option<&str>) -> bool {
+        let deadline: DateTime<Local> = match ttl.and_then(|ttl| ttl.parse().ok()) {
+            Some(deadline) => deadline,
+            None => return false
+        };
+        let addr = self.appnode.node.get_addr();
+        let salt = new_salt();
+        let hash = commitment(&topic, bid, &salt, &addr);
+        if !self.pull_bk_add_block(Data::new(commit_msg(&topic, &addr, &hash), COMMIT_MSG_TYPE, None)) {
+            return false
+        }
+
+        let mut sealed_bids = self.sealed_bids.lock().get_guard();
+        sealed_bids.push(SealedBid { topic, bid, salt, deadline });
+        drop(sealed_bids);
+        sleep(Duration::from_secs(NODETIMEOUT));
+        true
+    }
+
+    /*
+        Reveal phase:
+            Sealed bids past their deadline are revealed (bid
+            and salt) within the reveal window, bids not
+            revealed in time are dropped (deposit forfeited).
+    */
+    fn reveal_bids(&self) {
+        let sealed_bids = self.sealed_bids.lock().get_guard();
+        let pending = sealed_bids.clone();
+        drop(sealed_bids);
+
+        let addr = self.appnode.node.get_addr();
+        let now = Local::now();
+        for sealed in pending {
+            let elapsed = (now - sealed.deadline).num_seconds();
+            if elapsed <= 0 {
+                continue
+            }
+            if elapsed <= REVEAL_WINDOW {
+                let data = Data::new(reveal_msg(&sealed.topic, &addr, sealed.bid, &sealed.salt), REVEAL_MSG_TYPE, None);
+                if !self.pull_bk_add_block(data) {
+                    continue // retried next pull
+                }
+            }
+            let mut sealed_bids = self.sealed_bids.lock().get_guard();
+            sealed_bids.retain(|bid| *bid != sealed);
+            drop(sealed_bids);
+        }
+    }
+
+    // Outcome of a sealed auction, computed from its events on chain
+    pub fn sealed_outcome(&self, topic: &str) -> Option<SealedOutcome> {
+        self.auction(topic)?.sealed()
+    }
+
+    // End of an auction, extended by its soft-close rule
+    pub fn effective_end(&self, topic: &str) -> Option<DateTime<Local>> {
+        Some(local(self.auction(topic)?.end))
+    }
+
+    // Events (block timestamp, tx) of an auction
+    fn topic_events(&self, topic: &str) -> Vec<(i64, String)> {
+        self.auction_entries(topic).into_iter()
+            .map(|entry| (entry.timestamp, entry.data))
+            .collect()
+    }
+
+    // Entries of a topic in chain order, a bundle with those of its topics (see engine.rs)
+    fn auction_entries(&self, topic: &str) -> Vec<TxEntry> {
+        let mut entries = self.tx_entries(ProofQuery::Topic(topic.to_string(), (0, 0)));
+        let lots = entries.iter().find_map(|entry| match LedgerEvent::parse(&entry.data) {
+            Some((_, LedgerEvent::Publish { kind: AuctionKind::Bundle { topics, .. }, .. })) => Some(topics),
+            _ => None
+        });
+        for lot in lots.unwrap_or_default() {
+            entries.extend(self.tx_entries(ProofQuery::Topic(lot, (0, 0))));
+        }
+        entries.sort_by_key(|entry| (entry.block_id, entry.index));
+        entries.dedup_by_key(|entry| (entry.block_id, entry.index));
+        entries
+    }
+
+    // Ledger entries answering query, light Apps verify them with merkle proofs
+    fn tx_entries(&self, query: ProofQuery) -> Vec<TxEntry> {
+        match &self.light {
+            Some(light) => {
+                light.sync(self.bootappnode.clone());
+                let proofs = light.pull_proofs(self.bootappnode.clone(), query);
+                let chain = light.chain.lock().get_guard();
+                let entries = proofs.into_iter()
+                    .filter_map(|proof| Some(TxEntry {
+                        block_id: proof.block_id,
+                        block_hash: proof.block_hash,
+                        timestamp: chain.headers.get(proof.block_id as usize)?.timestamp,
+                        index: proof.index,
+                        data: proof.tx,
+                    }))
+                    .collect();
+                drop(chain);
+                entries
+            },
+            None => {
+                self.appnode.kademlia.sync_from(self.bootappnode.clone());
+                let blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+                let entries = blockchain.query_entries(&query);
+                drop(blockchain);
+                entries
+            }
+        }
+    }
+
+    /*
+        Topic search (see search.rs): full Apps answer from their
+        ledger, light Apps send the query to their bootstrap node.
+    */
+    pub fn search_topics(&self, query: TopicQuery) -> Vec<TopicEntry> {
+        match &self.light {
+            Some(_) => self.appnode.kademlia.query_topics(self.bootappnode.clone(), query).unwrap_or_default(),
+            None => {
+                self.appnode.kademlia.sync_from(self.bootappnode.clone());
+                self.appnode.kademlia.search_topics(&query, Local::now().timestamp())
+            }
+        }
+    }
+
     pub fn get_topics(&self) -> Vec<(String, String, String)> {
         let topics = self.topics.lock().get_guard();
         let res = topics.clone();
@@ -692,36 +1676,144 @@ impl App {
         res
     }
 
+    pub fn get_results(&self) -> Vec<AuctionResult> {
+        let results = self.results.lock().get_guard();
+        let res = results.clone();
+        drop(results);
+
+        res
+    }
+
+    /*
+        Auction events pushed to us (bids, extensions, ends and
+        cancellations of topics we publish or subscribe to), in
+        arrival order, each one once, from the time of the call
+        on (see notify.rs). Only signed ones are delivered.
+    */
+    pub fn events(&self) -> Receiver<Notification> {
+        self.appnode.kademlia.inbox.events()
+    }
+
+    /*
+        Auction state (None until its PUB_TOPIC is mined): full Apps
+        read the engine of their local chain, light Apps replay the
+        ledger events proved by their bootstrap node.
+    */
+    pub fn auction(&self, topic: &str) -> Option<AuctionRecord> {
+        if self.light.is_none() {
+            let blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+            let auction = blockchain.auction(topic);
+            drop(blockchain);
+            return auction
+        }
+        AuctionEngine::replay(&self.topic_events(topic)).auction(topic).cloned()
+    }
+
+    // Bids of a topic in chain order, with their block references (see history.rs)
+    pub fn bid_history(&self, topic: &str) -> Vec<BidEntry> {
+        bid_history(topic, &self.auction_entries(topic))
+    }
+
+    /*
+        Our bids and auctions, found from the ledger activity of
+        our address, each with the current state of its auction.
+    */
+    pub fn my_bids(&self) -> Vec<MyBid> {
+        let addr = self.appnode.node.get_addr();
+        let activity = self.tx_entries(ProofQuery::Address(addr.clone(), (0, 0)));
+        my_bids(&addr, &activity, |topic| self.auction_entries(topic), Local::now().timestamp())
+    }
+
+    pub fn my_auctions(&self) -> Vec<MyAuction> {
+        let addr = self.appnode.node.get_addr();
+        let activity = self.tx_entries(ProofQuery::Address(addr.clone(), (0, 0)));
+        my_auctions(&addr, &activity, |topic| self.auction_entries(topic), Local::now().timestamp())
+    }
+
+    /*
+        Auction json answered by the engine (see engine.rs), the
+        DHT instance only adds what is not on chain (id, subs) and
+        stands in for auctions not mined yet.
+    */
     pub fn get_json(&self, topic: String) -> Value {
-        self.appnode.get_pubsub_json(topic).clone()
+        let mut json = self.appnode.get_pubsub_json(topic.clone());
+        if let Some(auction) = self.auction(&topic) {
+            if let Value::Object(state) = auction.as_json(Local::now().timestamp()) {
+                json["name"] = json!(topic);
+                for (key, value) in state {
+                    json[key] = value;
+                }
+            }
+        }
+        json
     }
 
+    /*
+        Submit data as transaction (gossiped to peers mempool),
+        then mine a block from our mempool template, which may
+        also include transactions submitted by other nodes.
+        On scheduled networks the block sealed by validators is
+        awaited (false if the transaction is not included).
+    */
     fn pull_bk_add_block(&self, data: Data) -> bool {
-        let query_blockchain = full_rpc_proc(&self.appnode.kademlia.rpc, KademliaRequest::QueryLocalBlockChain, self.bootappnode.clone());
-        if let Some(KademliaResponse::QueryLocalBlockChain(blocks)) = query_blockchain {
-            let mut blockchain = self.appnode.kademlia.blockchain.lock()
-                .expect("Error setting lock in local blockchain");
-            blockchain.blocks = blockchain.choose_chain(blockchain.blocks.clone(), blocks);
+        let data = data.sign(&self.appnode.kademlia.identity);
+        if let Some(light) = &self.light {
+            light.sync(self.bootappnode.clone());
+            if light.consensus().scheduled() {
+                // sealed by validators, awaited until included
+                self.appnode.kademlia.submit_transaction(Transaction::new(data.to_json()));
+                return self.mined(&data).is_some()
+            }
+            let template = serde_json::to_string(&vec![data.to_json()])
+                .expect("Error serializing block template");
+            let block = match light.mine_next(template) {
+                Some(block) => block,
+                None => return false
+            };
+            let add_block = full_rpc_proc(&self.appnode.kademlia.rpc, KademliaRequest::AddBlock(block), self.bootappnode.clone());
+            return matches!(add_block, Some(KademliaResponse::Ping))
+        }
 
-            let id = blockchain.blocks[blockchain.blocks.len() - 1].id + 1;
-            let prev_hash = blockchain.blocks[blockchain.blocks.len() - 1].hash.to_string();
-            let block = Block::new(id, prev_hash, data.to_json());
+        self.appnode.kademlia.submit_transaction(Transaction::new(data.to_json()));
 
-            blockchain.add_block(block.clone());
-            drop(blockchain);
+        self.appnode.kademlia.sync_from(self.bootappnode.clone());
+        if self.appnode.kademlia.consensus().scheduled() {
+            // sealed by validators, awaited until included
+            return self.mined(&data).is_some()
+        }
 
-            let add_block = full_rpc_proc(&self.appnode.kademlia.rpc, KademliaRequest::AddBlock(block), self.bootappnode.clone());
-            if let Some(KademliaResponse::Ping) = add_block {
-                // println!("\t[AN{}]: Added Block info ({})", self.appnode.node.port, data.to_json());
-            } else if let Some(KademliaResponse::PingUnableProcReq) = add_block {
-                let mut blockchain = self.appnode.kademlia.blockchain.lock()
-                    .expect("Error setting lock in local blockchain");
-                blockchain.remove_last_block();
-                drop(blockchain);
-                println!("\t[AN{}]: Unable to add block info ({})", self.appnode.node.port, data.to_json());
-                return false
-            }
+        let template = match self.appnode.kademlia.block_template() {
+            Some(template) => template,
+            None => return self.included(&data) // already included in chain (or invalid)
+        };
+
+        let block = match self.appnode.kademlia.mine_next(template) {
+            Some(block) => block,
+            None => return false // competing block, template is stale
+        };
+
+        let add_block = full_rpc_proc(&self.appnode.kademlia.rpc, KademliaRequest::AddBlock(block.clone()), self.bootappnode.clone());
+        if let Some(KademliaResponse::Ping) = add_block {
+            let mut mempool = self.appnode.kademlia.mempool.lock().get_guard();
+            mempool.prune(&[block]);
+            drop(mempool);
+            // println!("\t[AN{}]: Added Block info ({})", self.appnode.node.port, data.to_json());
+        } else if let Some(KademliaResponse::PingUnableProcReq) = add_block {
+            let mut blockchain = self.appnode.kademlia.blockchain.lock()
+                .expect("Error setting lock in local blockchain");
+            blockchain.remove_last_block();
+            drop(blockchain);
+            println!("\t[AN{}]: Unable to add block info ({})", self.appnode.node.port, data.to_json());
+            return false
         }
-        true
+        self.included(&data)
+    }
+
+    // Transaction is in our local chain (left out of templates if invalid)
+    fn included(&self, data: &Data) -> bool {
+        let blockchain = self.appnode.kademlia.blockchain.lock().get_guard();
+        let included = blockchain.tx_by_hash(&tx_hash(&data.to_json())).is_some();
+        drop(blockchain);
+        included
     }
 }
\ No newline at end of file
@@ -0,0 +1,12 @@
+// Listing withdrawn by its publisher (no sale)
+pub const CANCEL_MSG_TYPE: usize = 7;
+
+/*
+    Cancellation record, msg format:
+        7 -> "CANCEL_TOPIC: <topic>|PUBLISHER: <addr>"
+    Early close has no record of its own, it is the
+    END_TOPIC of the publisher before the auction ttl.
+*/
+pub fn cancel_msg(topic: &str, publisher: &str) -> String {
+    format!("CANCEL_TOPIC: {}|PUBLISHER: {}", topic, publisher)
+}
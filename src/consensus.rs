@@ -0,0 +1,193 @@
+use super::blockchain::{Block, BlockHeader, calc_hash, meets_difficulty, next_timestamp, parse_transactions, MAX_FUTURE_SECS};
+use super::merkle::merkle_root;
+use super::identity::{Identity, verify};
+use super::miner::Miner;
+use super::accounts::{MintPolicy, FAUCET_AMOUNT};
+
+use serde_json::{json, Value};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+// Seconds after its parent before any validator may seal out of turn (in-turn one missing),
+// beyond the clock skew we accept so a forward-dated block cannot skip the schedule
+pub const POA_FALLBACK_SECS: i64 = 2 * MAX_FUTURE_SECS;
+
+/**
+ * Consensus Engine:
+ *  Rules agreed upon between nodes of a network, selected
+ *  at genesis (see engine_from_genesis). Blockchain checks
+ *  links/hashes, the engine checks who may seal a block
+ *  and which chain wins on a fork.
+**/
+pub trait ConsensusEngine: Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Blocks are produced on a schedule (authorities), thus other
+    // nodes submit transactions instead of sealing blocks
+    fn scheduled(&self) -> bool {
+        false
+    }
+
+    // Seal block template on top of prev, None if aborted or not allowed to seal
+    fn seal(&self, miner: &Miner, identity: &Identity, prev: &BlockHeader, data: String) -> Option<Block>;
+
+    // Seal is verified on the header (light clients hold no bodies)
+    fn verify_seal(&self, header: &BlockHeader) -> bool;
+
+    // Identity is the preferred sealer of block id (scheduled engines)
+    fn in_turn(&self, _identity: &Identity, _id: u64) -> bool {
+        true
+    }
+
+    // Header may be sealed on top of prev (turn rules of scheduled engines)
+    fn verify_turn(&self, _header: &BlockHeader, _prev: &BlockHeader) -> bool {
+        true
+    }
+
+    // Work of a sealed block (summed in the chain tip), blocks of a
+    // fixed difficulty/authority weigh the same as in prefer
+    fn work(&self, _header: &BlockHeader) -> u64 {
+        1
+    }
+
+    // Fork choice: true if remote chain should replace local
+    fn prefer(&self, local: &[Block], remote: &[Block]) -> bool {
+        remote.len() > local.len()
+    }
+
+    // Keys sealing blocks of the network (none if anyone may)
+    fn authorities(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Units in circulation: faucet only, no minters
+    fn mint_policy(&self) -> MintPolicy {
+        MintPolicy { faucet: FAUCET_AMOUNT, minters: Vec::new() }
+    }
+}
+
+/**
+ * Proof-of-Work:
+ *  Any node may mine, hash must start with the difficulty prefix.
+**/
+#[derive(Debug, Clone, Default)]
+pub struct ProofOfWork;
+
+impl ConsensusEngine for ProofOfWork {
+    fn name(&self) -> &'static str {
+        "pow"
+    }
+
+    fn seal(&self, miner: &Miner, _identity: &Identity, prev: &BlockHeader, data: String) -> Option<Block> {
+        miner.mine(prev, data)
+    }
+
+    fn verify_seal(&self, header: &BlockHeader) -> bool {
+        match hex::decode(&header.hash) {
+            Ok(hash) => meets_difficulty(&hash),
+            Err(_) => false
+        }
+    }
+}
+
+/**
+ * Proof-of-Authority:
+ *  Fixed validator set (public keys of bootstrap nodes), block
+ *  with id N is signed by validators[N % len] (round-robin).
+ *  Any other validator may seal it out of turn POA_FALLBACK_SECS
+ *  after its parent (in-turn validator offline), in-turn blocks
+ *  weigh twice as much, thus the in-turn fork wins. The nonce
+ *  of a block is the index of its sealer (committed in the hash).
+**/
+#[derive(Debug, Clone)]
+pub struct ProofOfAuthority {
+    pub validators: Vec<String>,
+}
+
+impl ProofOfAuthority {
+    pub fn new(validators: Vec<String>) -> Self {
+        Self { validators }
+    }
+
+    pub fn validator(&self, id: u64) -> Option<&String> {
+        if self.validators.is_empty() {
+            return None
+        }
+        self.validators.get((id % self.validators.len() as u64) as usize)
+    }
+
+    fn sealed_in_turn(&self, header: &BlockHeader) -> bool {
+        !self.validators.is_empty() && header.nonce == header.id % self.validators.len() as u64 && self.verify_seal(header)
+    }
+}
+
+impl ConsensusEngine for ProofOfAuthority {
+    fn name(&self) -> &'static str {
+        "poa"
+    }
+
+    fn scheduled(&self) -> bool {
+        true
+    }
+
+    // Out of turn blocks are sealed too, they are only valid after the fallback delay
+    fn seal(&self, _miner: &Miner, identity: &Identity, prev: &BlockHeader, data: String) -> Option<Block> {
+        let nonce = self.validators.iter().position(|validator| *validator == identity.public_key())? as u64;
+        let (id, prev_hash) = (prev.id + 1, prev.hash.clone());
+        let timestamp = next_timestamp(prev);
+        let root = merkle_root(&parse_transactions(&data));
+        let hash = hex::encode(calc_hash(id, timestamp, &prev_hash, &root, nonce));
+        let signature = identity.sign(hash.as_bytes());
+        Some(Block { id, hash, prev_hash, timestamp, data, merkle_root: root, nonce, signature, pruned: false })
+    }
+
+    fn verify_seal(&self, header: &BlockHeader) -> bool {
+        match self.validators.get(header.nonce as usize) {
+            Some(validator) => verify(validator, header.hash.as_bytes(), &header.signature),
+            None => false
+        }
+    }
+
+    fn in_turn(&self, identity: &Identity, id: u64) -> bool {
+        self.validator(id) == Some(&identity.public_key())
+    }
+
+    fn verify_turn(&self, header: &BlockHeader, prev: &BlockHeader) -> bool {
+        self.sealed_in_turn(header) || header.timestamp >= prev.timestamp + POA_FALLBACK_SECS
+    }
+
+    fn work(&self, header: &BlockHeader) -> u64 {
+        if self.sealed_in_turn(header) { 2 } else { 1 }
+    }
+
+    fn prefer(&self, local: &[Block], remote: &[Block]) -> bool {
+        let work = |blocks: &[Block]| blocks.iter().map(|block| self.work(&block.header())).sum::<u64>();
+        work(remote) > work(local)
+    }
+
+    fn authorities(&self) -> Vec<String> {
+        self.validators.clone()
+    }
+
+    // Validators may also mint units
+    fn mint_policy(&self) -> MintPolicy {
+        MintPolicy { faucet: FAUCET_AMOUNT, minters: self.authorities() }
+    }
+}
+
+// Genesis data of a proof-of-authority network
+pub fn poa_genesis_data(validators: &[String]) -> String {
+    json!({"consensus": "poa", "validators": validators}).to_string()
+}
+
+// Engine selected by genesis block (proof-of-work by default)
+pub fn engine_from_genesis(genesis: &Block) -> Arc<dyn ConsensusEngine> {
+    if let Ok(data) = serde_json::from_str::<Value>(&genesis.data) {
+        if data["consensus"] == "poa" {
+            let validators: Vec<String> = serde_json::from_value(data["validators"].clone())
+                .unwrap_or_default();
+            return Arc::new(ProofOfAuthority::new(validators))
+        }
+    }
+    Arc::new(ProofOfWork)
+}
@@ -0,0 +1,572 @@
+use super::auction::{Bid, local};
+use super::accounts::ESCROW_MSG_TYPE;
+use super::bootstrap::Data;
+use super::cancel::CANCEL_MSG_TYPE;
+use super::explorer::tx_keys;
+use super::kademlia::KademliaInstance;
+use super::node::{Distance, Key, Node};
+use super::notify::addr_node;
+use super::pubsub::PubSubInstance;
+use super::rpc::{timed_rpc_proc, KademliaRequest, KademliaResponse};
+
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Seconds to wait for a coordinator before handing its topics over
+pub const COORDINATOR_TIMEOUT: u64 = 2;
+
+// Coordinators (redirects included) tried before a bid is given up on
+pub const COORDINATOR_ATTEMPTS: usize = 3;
+
+// Timeouts in a row before a coordinator is departed (skipped until then)
+pub const MAX_TIMEOUTS: usize = 3;
+
+/**
+ * Bid request:
+ *  Bid msg of topic sent by bidder (a subscriber or the
+ *  publisher), seq is the number of bids it has seen and
+ *  escrow the hash of its escrow transaction (if any).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BidRequest {
+    pub topic: String,
+    pub bidder: String,
+    pub msg: String,
+    pub seq: usize,
+    pub escrow: Option<String>,
+}
+
+/**
+ * Bid receipt (answer of the topic coordinator):
+ *  - Accepted: bid stored with sequence number seq, outbid
+ *    is the previous highest bidder.
+ *  - Stale: bids changed since the request (now seq of them).
+ *  - Rejected: invalid bid, bidder or topic.
+ *  - Redirect: node is the coordinator of the topic.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum BidReceipt {
+    Accepted { seq: usize, outbid: Option<String> },
+    Stale { seq: usize },
+    Rejected,
+    Redirect(Node),
+}
+
+/**
+ * Topic update (every write of a topic instance but bids),
+ * applied by the topic coordinator under the topic lock:
+ *  - Publish: instance (encoded) stored by its publisher,
+ *    replacing only an instance of the same publisher.
+ *  - Subscribe / Renew: lease of sub renewed while the auction
+ *    is open (Renew only if sub is still subscribed), sent by sub.
+ *  - Unsubscribe: sub removed, sent by sub.
+ *  - Missed / Reached: sub did (not) acknowledge a notification,
+ *    dropped after MAX_MISSED in a row (see PubSubInstance::miss).
+ *  - Extend / Close: end moved to (unix secs) by the publisher,
+ *    only ever later when extended and earlier when closed
+ *    (once its END_TOPIC is mined).
+ *  - Cancel: withdrawn by the publisher (once its CANCEL_TOPIC
+ *    is mined).
+ *  - Index: topic merged into the list of a keyword (the key
+ *    updated is its keyword_key, see search.rs).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum TopicUpdate {
+    Publish(String),
+    Subscribe(String),
+    Renew(String),
+    Unsubscribe(String),
+    Missed(String),
+    Reached(String),
+    Extend(i64),
+    Close(i64),
+    Cancel,
+    Index(String),
+}
+
+/**
+ * Update receipt (answer of the topic coordinator):
+ *  - Applied: instance stored with the update.
+ *  - Rejected: unknown topic or nothing to update.
+ *  - Redirect: node is the coordinator of the topic.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum UpdateReceipt {
+    Applied,
+    Rejected,
+    Redirect(Node),
+}
+
+impl TopicUpdate {
+    // Update applied to the stored instance (false if nothing changed)
+    fn apply(&self, pubsub_ins: &mut PubSubInstance) -> bool {
+        match self {
+            TopicUpdate::Publish(_) | TopicUpdate::Index(_) => false,
+            TopicUpdate::Subscribe(sub) => {
+                if !pubsub_ins.verify_pubsub() {
+                    return false
+                }
+                pubsub_ins.add_sub(sub.clone());
+                true
+            },
+            TopicUpdate::Renew(sub) => {
+                if !pubsub_ins.verify_pubsub() || *sub == pubsub_ins.publisher || !pubsub_ins.verify_addr(sub.clone()) {
+                    return false
+                }
+                pubsub_ins.add_sub(sub.clone());
+                true
+            },
+            TopicUpdate::Unsubscribe(sub) => pubsub_ins.remove_sub(sub),
+            TopicUpdate::Missed(sub) => {
+                if *sub == pubsub_ins.publisher || !pubsub_ins.verify_addr(sub.clone()) {
+                    return false
+                }
+                pubsub_ins.miss(sub);
+                true
+            },
+            TopicUpdate::Reached(sub) => pubsub_ins.reach(sub),
+            TopicUpdate::Extend(at) => {
+                let ttl = local(*at);
+                if pubsub_ins.ttl.is_some_and(|end| end >= ttl) {
+                    return false
+                }
+                pubsub_ins.set_ttl(ttl);
+                true
+            },
+            TopicUpdate::Close(at) => {
+                let ttl = local(*at);
+                if pubsub_ins.ttl.is_some_and(|end| end <= ttl) {
+                    return false
+                }
+                pubsub_ins.set_ttl(ttl);
+                true
+            },
+            TopicUpdate::Cancel => {
+                if pubsub_ins.cancelled {
+                    return false
+                }
+                pubsub_ins.cancel();
+                true
+            }
+        }
+    }
+}
+
+/**
+ * Coordinator:
+ *  The coordinator of a topic is the live node closest to its key,
+ *  holding the topic lock while a bid (or update) is verified and
+ *  stored.
+ *  - locks: topic -> lock, one bid or update of a topic at a time.
+ *  - departed: nodes timed out MAX_TIMEOUTS times in a row or
+ *    resigned, never chosen (nor kept in the routing table) until
+ *    they join the network again.
+ *  - timeouts: node -> timeouts in a row (reset once it answers),
+ *    suspected nodes are left out of DHT lookups meanwhile.
+ *  - resigned: we left, our topics are handed over.
+**/
+#[derive(Debug, Clone, Default)]
+pub struct Coordinator {
+    locks: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+    departed: Arc<Mutex<HashSet<Key>>>,
+    timeouts: Arc<Mutex<HashMap<Key, usize>>>,
+    resigned: Arc<AtomicBool>,
+}
+
+impl Coordinator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self, topic: &str) -> Arc<Mutex<()>> {
+        let mut locks = self.locks.lock()
+            .expect("Error setting lock in coordinator locks");
+        let lock = locks.entry(topic.to_string()).or_default().clone();
+        drop(locks);
+        lock
+    }
+
+    pub fn depart(&self, id: &Key) {
+        let mut departed = self.departed.lock()
+            .expect("Error setting lock in departed nodes");
+        departed.insert(id.clone());
+        drop(departed);
+    }
+
+    pub fn rejoin(&self, id: &Key) {
+        let mut departed = self.departed.lock()
+            .expect("Error setting lock in departed nodes");
+        departed.remove(id);
+        drop(departed);
+        self.answered(id);
+    }
+
+    // Timeout of node counted, true once it timed out MAX_TIMEOUTS times in a row
+    pub fn time_out(&self, id: &Key) -> bool {
+        let mut timeouts = self.timeouts.lock()
+            .expect("Error setting lock in coordinator timeouts");
+        let count = timeouts.entry(id.clone()).or_default();
+        *count += 1;
+        let res = *count >= MAX_TIMEOUTS;
+        drop(timeouts);
+        res
+    }
+
+    // Timed out since it last answered (not queried by lookups meanwhile)
+    pub fn suspected(&self, id: &Key) -> bool {
+        let timeouts = self.timeouts.lock()
+            .expect("Error setting lock in coordinator timeouts");
+        let res = timeouts.contains_key(id);
+        drop(timeouts);
+        res
+    }
+
+    pub fn answered(&self, id: &Key) {
+        let mut timeouts = self.timeouts.lock()
+            .expect("Error setting lock in coordinator timeouts");
+        timeouts.remove(id);
+        drop(timeouts);
+    }
+
+    pub fn departed(&self, id: &Key) -> bool {
+        let departed = self.departed.lock()
+            .expect("Error setting lock in departed nodes");
+        let res = departed.contains(id);
+        drop(departed);
+        res
+    }
+
+    pub fn resigned(&self) -> bool {
+        self.resigned.load(Ordering::SeqCst)
+    }
+}
+
+/**
+ * TOPIC COORDINATOR (bids serialized by the node closest to the topic)
+**/
+impl KademliaInstance {
+    // Candidates to coordinate topic, closest to its key first
+    pub fn coordinators(&self, topic: &str) -> Vec<Node> {
+        let key = Key::new(topic.to_string());
+        let routingtable = self.routingtable.lock()
+            .expect("Error setting lock in routing table");
+        let mut nodes = routingtable.get_nodes();
+        drop(routingtable);
+        if !self.coordinator.resigned() {
+            nodes.push(self.node.clone());
+        }
+        nodes.retain(|node| !self.coordinator.departed(&node.id));
+        nodes.sort_by_key(|node| Distance::new(&node.id, &key));
+        nodes
+    }
+
+    pub fn coordinator_of(&self, topic: &str) -> Option<Node> {
+        self.coordinators(topic).into_iter().next()
+    }
+
+    // Departed node is never chosen nor queried again (until it joins)
+    pub fn depart(&self, node: &Node) {
+        self.coordinator.depart(&node.id);
+        self.coordinator.answered(&node.id);
+        let mut routingtable = self.routingtable.lock()
+            .expect("Error setting lock in routing table");
+        routingtable.remove_node(&node.id);
+        drop(routingtable);
+    }
+
+    // Node not answering in time, departed once it timed out MAX_TIMEOUTS times in a row
+    fn timed_out(&self, node: &Node) {
+        if self.coordinator.time_out(&node.id) {
+            self.depart(node);
+        }
+    }
+
+    fn alive(&self, node: &Node) -> bool {
+        let res = timed_rpc_proc(&self.rpc, KademliaRequest::Ping, node.clone(), Duration::from_secs(COORDINATOR_TIMEOUT));
+        if matches!(res, Some(KademliaResponse::Ping)) {
+            self.coordinator.answered(&node.id);
+            return true
+        }
+        self.timed_out(node);
+        false
+    }
+
+    // Live node closer to topic than us (closer nodes not answering are skipped)
+    fn closer_coordinator(&self, topic: &str) -> Option<Node> {
+        self.coordinators(topic).into_iter()
+            .take_while(|node| node.id != self.node.id)
+            .find(|node| self.alive(node))
+    }
+
+    /*
+        Bid request received (from src) as (we believe to be) coordinator:
+            closer live nodes are redirected to, closer nodes not
+            answering are skipped (we take their topics over, see
+            timed_out), only bids of their sender (the bidder of
+            the request) are taken.
+            Under the topic lock the instance is read, the bid is
+            verified against it at the block time of its escrow in
+            our chain (our clock if we do not have it, see
+            escrowed_at) and the bid policy of its spec, then
+            stored with the next sequence.
+    */
+    pub fn coordinate_bid(&self, request: &BidRequest, src: &str) -> BidReceipt {
+        if let Some(node) = self.closer_coordinator(&request.topic) {
+            return BidReceipt::Redirect(node)
+        }
+        if self.coordinator.resigned() {
+            return BidReceipt::Rejected
+        }
+        if request.bidder != src || Bid::from_msg(&request.msg).is_none_or(|bid| bid.sender_addr != request.bidder) {
+            return BidReceipt::Rejected
+        }
+
+        let lock = self.coordinator.lock(&request.topic);
+        let _guard = lock.lock()
+            .expect("Error setting lock in topic");
+        let pubsub_ins = match self.get(request.topic.clone()).and_then(|value| PubSubInstance::decode(&value)) {
+            Some(pubsub_ins) => pubsub_ins,
+            None => return BidReceipt::Rejected
+        };
+        let seq = pubsub_ins.seq();
+        if request.seq != seq {
+            return BidReceipt::Stale { seq }
+        }
+        if !pubsub_ins.verify_addr(request.bidder.clone()) || !self.meets_policy(&pubsub_ins, &request.bidder) {
+            return BidReceipt::Rejected
+        }
+        let at = self.escrowed_at(request).unwrap_or_else(|| Utc::now().timestamp());
+        let outbid = pubsub_ins.bids().iter().rev().max_by_key(|bid| bid.data).map(|bid| bid.sender_addr.clone());
+        if pubsub_ins.add_msg(request.msg.clone(), local(at)) != 0 {
+            return BidReceipt::Rejected
+        }
+        self.insert(request.topic.clone(), pubsub_ins.to_string());
+        BidReceipt::Accepted { seq: seq + 1, outbid }
+    }
+
+    // Block time of the escrow of the request in our chain (an ESCROW of its topic by its bidder)
+    fn escrowed_at(&self, request: &BidRequest) -> Option<i64> {
+        let hash = request.escrow.as_ref()?;
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in blockchain");
+        let entry = blockchain.tx_by_hash(hash);
+        drop(blockchain);
+        let entry = entry?;
+        let (msg_type, topic, addresses) = tx_keys(&entry.data)?;
+        let escrowed = msg_type == ESCROW_MSG_TYPE
+            && topic.as_ref() == Some(&request.topic)
+            && addresses.first() == Some(&request.bidder);
+        escrowed.then_some(entry.timestamp)
+    }
+
+    /*
+        Raw Store of key (replicas written by the coordinator, see
+        insert): topic instances are only taken from nodes at least
+        as close to the topic as us (its coordinator), any other
+        write of a topic goes through coordinate_update/coordinate_bid.
+    */
+    pub fn accepts_store(&self, key: &str, value: &str, src: &Node) -> bool {
+        let hashmap = self.hashmap.lock()
+            .expect("Error setting lock in hashmap");
+        let topic = hashmap.get(key).is_some_and(|stored| PubSubInstance::decode(stored).is_some());
+        drop(hashmap);
+        if !topic && PubSubInstance::decode(value).is_none() {
+            return true
+        }
+        let key = Key::new(key.to_string());
+        Distance::new(&src.id, &key) <= Distance::new(&self.node.id, &key)
+    }
+
+    // Bidder reputation (at our tip) meets the spec policy of the topic
+    fn meets_policy(&self, pubsub_ins: &PubSubInstance, bidder: &str) -> bool {
+        let policy = match &pubsub_ins.spec {
+            Some(spec) => &spec.policy,
+            None => return true
+        };
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in blockchain");
+        let reputation = blockchain.reputation(bidder).unwrap_or_default();
+        drop(blockchain);
+        policy.allows(&reputation)
+    }
+
+    /*
+        Bid sent to the coordinator of its topic, following its
+        redirects, a coordinator not answering in time is
+        skipped (see timed_out) and the next closest node is tried.
+    */
+    pub fn submit_bid(&self, request: BidRequest) -> Option<BidReceipt> {
+        let mut skipped: Vec<Key> = Vec::new();
+        let mut target = self.coordinator_of(&request.topic);
+        for _ in 0..COORDINATOR_ATTEMPTS {
+            let node = target?;
+            if node.id == self.node.id {
+                return Some(self.coordinate_bid(&request, &self.node.get_addr()))
+            }
+            let res = timed_rpc_proc(&self.rpc, KademliaRequest::SubmitBid(request.clone()), node.clone(), Duration::from_secs(COORDINATOR_TIMEOUT));
+            if res.is_some() {
+                self.coordinator.answered(&node.id);
+            }
+            target = match res {
+                Some(KademliaResponse::SubmitBid(BidReceipt::Redirect(next))) => Some(next),
+                Some(KademliaResponse::SubmitBid(receipt)) => return Some(receipt),
+                _ => self.next_coordinator(&request.topic, &node, &mut skipped)
+            };
+        }
+        None
+    }
+
+    // Next closest node once node timed out (nodes skipped in a submission are not tried again)
+    fn next_coordinator(&self, topic: &str, node: &Node, skipped: &mut Vec<Key>) -> Option<Node> {
+        self.timed_out(node);
+        skipped.push(node.id.clone());
+        self.coordinators(topic).into_iter().find(|node| !skipped.contains(&node.id))
+    }
+
+    /*
+        Update of key (a topic or keyword) received (from src) as
+        (we believe to be) coordinator, redirected as bids are.
+        Under the key lock the value is read, updated and stored
+        if src may update it (see authorized): a published instance
+        is only stored over one of its publisher, a topic already
+        listed under a keyword is not again.
+    */
+    pub fn coordinate_update(&self, key: &str, update: &TopicUpdate, src: &str) -> UpdateReceipt {
+        if let Some(node) = self.closer_coordinator(key) {
+            return UpdateReceipt::Redirect(node)
+        }
+        if self.coordinator.resigned() {
+            return UpdateReceipt::Rejected
+        }
+        // (not) reached as reported by another node only if sub does (not) answer us either
+        let witnessed = match update {
+            TopicUpdate::Missed(sub) | TopicUpdate::Reached(sub) if sub != src => {
+                let answers = addr_node(sub).is_some_and(|node| self.answers(&node));
+                answers == matches!(update, TopicUpdate::Reached(_))
+            },
+            _ => true
+        };
+        if !witnessed {
+            return UpdateReceipt::Rejected
+        }
+
+        let lock = self.coordinator.lock(key);
+        let _guard = lock.lock()
+            .expect("Error setting lock in topic");
+        let stored = self.get(key.to_string());
+        let instance = stored.as_deref().and_then(PubSubInstance::decode);
+        let value = match update {
+            TopicUpdate::Index(topic) => {
+                let mut topics: Vec<String> = stored.and_then(|topics| serde_json::from_str(&topics).ok()).unwrap_or_default();
+                if topics.contains(topic) {
+                    return UpdateReceipt::Rejected
+                }
+                topics.push(topic.clone());
+                serde_json::to_string(&topics).expect("Error serializing keyword topics")
+            },
+            TopicUpdate::Publish(value) => match PubSubInstance::decode(value) {
+                Some(pubsub_ins) if pubsub_ins.publisher == src && instance.is_none_or(|stored| stored.publisher == pubsub_ins.publisher) => pubsub_ins.to_string(),
+                _ => return UpdateReceipt::Rejected
+            },
+            _ => {
+                let mut pubsub_ins = match instance {
+                    Some(pubsub_ins) => pubsub_ins,
+                    None => return UpdateReceipt::Rejected
+                };
+                if !self.authorized(key, update, &pubsub_ins, src) || !update.apply(&mut pubsub_ins) {
+                    return UpdateReceipt::Rejected
+                }
+                pubsub_ins.to_string()
+            }
+        };
+        self.insert(key.to_string(), value);
+        UpdateReceipt::Applied
+    }
+
+    /*
+        Update of topic src may make: the publisher extends,
+        closes and cancels (once its END_TOPIC / CANCEL_TOPIC
+        is mined), a subscriber subscribes, renews, unsubscribes
+        and misses for itself (misses and acknowledgments of
+        others are witnessed, see coordinate_update).
+    */
+    fn authorized(&self, topic: &str, update: &TopicUpdate, pubsub_ins: &PubSubInstance, src: &str) -> bool {
+        match update {
+            TopicUpdate::Extend(_) => pubsub_ins.publisher == src,
+            TopicUpdate::Close(_) => pubsub_ins.publisher == src && self.mined_by(topic, 2, src),
+            TopicUpdate::Cancel => pubsub_ins.publisher == src && self.mined_by(topic, CANCEL_MSG_TYPE, src),
+            TopicUpdate::Subscribe(sub) | TopicUpdate::Renew(sub) | TopicUpdate::Unsubscribe(sub) => sub == src,
+            TopicUpdate::Missed(_) | TopicUpdate::Reached(_) => true,
+            // not instance updates (see coordinate_update)
+            TopicUpdate::Publish(_) | TopicUpdate::Index(_) => false
+        }
+    }
+
+    // Transaction of msg_type on topic in our chain, signed by the key registered for publisher
+    fn mined_by(&self, topic: &str, msg_type: usize, publisher: &str) -> bool {
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in blockchain");
+        let key = blockchain.with_tip_state(|state| state.registered.get(publisher).cloned()).flatten();
+        let mined = key.is_some_and(|key| blockchain.topic_events(topic).iter()
+            .filter_map(|entry| Data::from_json(&entry.data))
+            .any(|data| data.msg_type() == msg_type && data.author() == key));
+        drop(blockchain);
+        mined
+    }
+
+    // Node answers a ping in time (nothing counted otherwise, unlike alive)
+    fn answers(&self, node: &Node) -> bool {
+        let res = timed_rpc_proc(&self.rpc, KademliaRequest::Ping, node.clone(), Duration::from_secs(COORDINATOR_TIMEOUT));
+        matches!(res, Some(KademliaResponse::Ping))
+    }
+
+    // Update sent to the coordinator of key (as submit_bid)
+    pub fn submit_update(&self, key: &str, update: TopicUpdate) -> Option<UpdateReceipt> {
+        let mut skipped: Vec<Key> = Vec::new();
+        let mut target = self.coordinator_of(key);
+        for _ in 0..COORDINATOR_ATTEMPTS {
+            let node = target?;
+            if node.id == self.node.id {
+                return Some(self.coordinate_update(key, &update, &self.node.get_addr()))
+            }
+            let res = timed_rpc_proc(&self.rpc, KademliaRequest::UpdateTopic(key.to_string(), update.clone()), node.clone(), Duration::from_secs(COORDINATOR_TIMEOUT));
+            if res.is_some() {
+                self.coordinator.answered(&node.id);
+            }
+            target = match res {
+                Some(KademliaResponse::UpdateTopic(UpdateReceipt::Redirect(next))) => Some(next),
+                Some(KademliaResponse::UpdateTopic(receipt)) => return Some(receipt),
+                _ => self.next_coordinator(key, &node, &mut skipped)
+            };
+        }
+        None
+    }
+
+    /*
+        Leaving the network: bids (and updates) in progress are finished
+        (topic locks taken once) and known nodes are told we
+        departed, the next closest nodes take our topics over.
+    */
+    pub fn resign(&self) {
+        self.coordinator.resigned.store(true, Ordering::SeqCst);
+        let locks: Vec<Arc<Mutex<()>>> = self.coordinator.locks.lock()
+            .expect("Error setting lock in coordinator locks")
+            .values()
+            .cloned()
+            .collect();
+        for lock in locks {
+            drop(lock.lock().expect("Error setting lock in topic"));
+        }
+        let routingtable = self.routingtable.lock()
+            .expect("Error setting lock in routing table");
+        let nodes = routingtable.get_nodes();
+        drop(routingtable);
+        for node in nodes {
+            timed_rpc_proc(&self.rpc, KademliaRequest::Depart(self.node.clone()), node, Duration::from_secs(COORDINATOR_TIMEOUT));
+        }
+    }
+}
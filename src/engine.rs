@@ -0,0 +1,502 @@
+use super::auction::{AuctionKind, AuctionSpec, Bid, local};
+use super::blockchain::Blockchain;
+use super::bootstrap::Data;
+use super::explorer::msg_fields;
+use super::sealed::{COMMIT_MSG_TYPE, REVEAL_MSG_TYPE, REVEAL_WINDOW, SealedOutcome, commitment};
+use super::softclose::{SoftClose, EXTEND_MSG_TYPE, MAX_EXTENSIONS};
+use super::cancel::CANCEL_MSG_TYPE;
+use super::accounts::{ESCROW_MSG_TYPE, escrowed_bid};
+use super::multiunit::{Allocation, parse_allocation, allocation_outcome};
+
+use chrono::{DateTime, Local};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/**
+ * Auction phase:
+ *  - Pending: published, its spec start time not reached.
+ *  - Open: taking bids (escrows, sealed commitments) until its end.
+ *  - Closing: end passed, awaiting its END_TOPIC (reveals of sealed bids).
+ *  - Settled: END_TOPIC mined, outcome is final.
+ *  - Cancelled: CANCEL_TOPIC mined, no sale.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuctionPhase {
+    Pending,
+    Open,
+    Closing,
+    Settled,
+    Cancelled,
+}
+
+impl AuctionPhase {
+    pub fn terminal(&self) -> bool {
+        matches!(self, AuctionPhase::Settled | AuctionPhase::Cancelled)
+    }
+}
+
+/**
+ * Ledger event:
+ *  Typed auction transaction, (topic, event) of a Data record.
+**/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerEvent {
+    Publish { publisher: String, kind: AuctionKind, spec: Option<Box<AuctionSpec>>, soft_close: Option<SoftClose>, end: i64 },
+    Escrow { bid: Bid },
+    Commit { bidder: String, hash: String },
+    Reveal { bidder: String, bid: usize, salt: String },
+    Extend { bidder: String },
+    End { price: Option<usize>, winner: Option<String>, allocation: Vec<Allocation> },
+    Cancel,
+}
+
+impl LedgerEvent {
+    // Msg types of the auction transactions
+    pub fn auction_tx(msg_type: usize) -> bool {
+        matches!(msg_type, 1 | 2 | COMMIT_MSG_TYPE | REVEAL_MSG_TYPE | EXTEND_MSG_TYPE | CANCEL_MSG_TYPE | ESCROW_MSG_TYPE)
+    }
+
+    pub fn parse(tx: &str) -> Option<(String, Self)> {
+        let data = Data::from_json(tx)?;
+        let record = msg_fields(data.msg());
+        let field = |key: &str| record.get(key).map(|value| value.trim_matches('"').to_string());
+        match data.msg_type() {
+            1 => {
+                let end: DateTime<Local> = data.exp_time()?.parse().ok()?;
+                let event = LedgerEvent::Publish {
+                    publisher: field("PUBLISHER")?,
+                    kind: match record.get("KIND") {
+                        Some(kind) => AuctionKind::from_json(kind)?,
+                        None => AuctionKind::default()
+                    },
+                    spec: record.get("SPEC").and_then(|spec| AuctionSpec::decode(spec)).map(Box::new),
+                    soft_close: record.get("SOFT_CLOSE").and_then(|rule| SoftClose::from_json(rule)),
+                    end: end.timestamp(),
+                };
+                Some((field("PUB_TOPIC")?, event))
+            },
+            2 => {
+                let price = field("BID").and_then(|price| price.parse().ok());
+                let winner = field("BIDDER").filter(|winner| winner != "null");
+                let allocation = record.get("ALLOCATION").and_then(|allocation| parse_allocation(allocation)).unwrap_or_default();
+                Some((field("END_TOPIC")?, LedgerEvent::End { price, winner, allocation }))
+            },
+            COMMIT_MSG_TYPE => Some((field("COMMIT")?, LedgerEvent::Commit { bidder: field("BIDDER")?, hash: field("HASH")? })),
+            REVEAL_MSG_TYPE => {
+                let event = LedgerEvent::Reveal { bidder: field("BIDDER")?, bid: field("BID")?.parse().ok()?, salt: field("SALT")? };
+                Some((field("REVEAL")?, event))
+            },
+            EXTEND_MSG_TYPE => Some((field("EXTEND")?, LedgerEvent::Extend { bidder: field("BIDDER")? })),
+            CANCEL_MSG_TYPE => Some((field("CANCEL_TOPIC")?, LedgerEvent::Cancel)),
+            ESCROW_MSG_TYPE => Some((field("ESCROW")?, LedgerEvent::Escrow { bid: escrowed_bid(data.msg())? })),
+            _ => None
+        }
+    }
+}
+
+/**
+ * Auction record:
+ *  State of a published auction, changed only by its
+ *  ledger events (block timestamp, event) in chain order.
+ *
+ *  - end: effective end (unix secs), extended by soft close.
+ *  - bids: escrowed bids accepted by the kind, in chain order.
+ *  - commits: sealed bid commitments (bidder, hash) taken while
+ *    open, one per bidder.
+ *  - reveals: bidder -> bid of commitments revealed in time.
+ *  - late_bid: (bidder, block time) of the last escrow taken
+ *    within the soft-close window, until it extends the end.
+ *  - extensions: times the end was extended (see softclose.rs).
+ *  - bundle: topic of the bundle auctioning it (a lot), it
+ *    takes no events of its own, ended with its bundle.
+ *  - outcome: (price, winner) recorded by END_TOPIC.
+ *  - allocation: winners recorded by END_TOPIC (multi kinds).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuctionRecord {
+    pub topic: String,
+    pub publisher: String,
+    pub kind: AuctionKind,
+    pub spec: Option<AuctionSpec>,
+    pub soft_close: Option<SoftClose>,
+    pub end: i64,
+    pub phase: AuctionPhase,
+    pub bids: Vec<Bid>,
+    pub commits: Vec<(String, String)>,
+    #[serde(default)]
+    pub reveals: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub late_bid: Option<(String, i64)>,
+    #[serde(default)]
+    pub extensions: usize,
+    #[serde(default)]
+    pub bundle: Option<String>,
+    pub outcome: Option<(usize, String)>,
+    #[serde(default)]
+    pub allocation: Vec<Allocation>,
+}
+
+impl AuctionRecord {
+    fn start(&self) -> i64 {
+        self.spec.as_ref().map_or(i64::MIN, |spec| spec.start_time)
+    }
+
+    /*
+        Time driven transitions:
+            Pending -> Open once the start time is reached,
+            Open -> Closing once the (effective) end passed.
+    */
+    fn tick(&mut self, now: i64) {
+        if self.phase == AuctionPhase::Pending && now >= self.start() {
+            self.phase = AuctionPhase::Open;
+        }
+        if self.phase == AuctionPhase::Open && now > self.end {
+            self.phase = AuctionPhase::Closing;
+        }
+    }
+
+    // Phase at now (unix secs), later events are not known yet
+    pub fn phase_at(&self, now: i64) -> AuctionPhase {
+        let mut record = self.clone();
+        record.tick(now);
+        record.phase
+    }
+
+    /*
+        Event driven transitions, returns whether the event was
+        taken (events invalid in the current phase are not):
+            Open: escrows (valid bids of the kind, at the block
+            time) and commitments.
+            Open or Closing: extensions of the last escrow if it
+            was taken within the soft-close window, once each and
+            up to MAX_EXTENSIONS (reopens a closing auction).
+            Closing: reveals of sealed commitments (within the
+            reveal window).
+            Any non terminal phase: END_TOPIC -> Settled if it
+            records the outcome of the bids (see settles), early
+            close while open, sealed auctions once the reveal
+            window is over.
+            Pending or Open: CANCEL_TOPIC -> Cancelled (no sale
+            taken back once its end passed).
+    */
+    fn apply(&mut self, timestamp: i64, event: LedgerEvent) -> bool {
+        if self.bundle.is_some() {
+            return false
+        }
+        self.tick(timestamp);
+        match (self.phase, event) {
+            (AuctionPhase::Open, LedgerEvent::Escrow { bid }) => {
+                let valid = !self.kind.sealed() && self.kind.verify_bid(&self.bids, &bid, local(timestamp));
+                if valid {
+                    let late = self.soft_close.is_some_and(|rule| rule.triggered(local(self.end), timestamp));
+                    self.late_bid = late.then_some((bid.sender_addr.clone(), timestamp));
+                    self.bids.push(bid);
+                }
+                valid
+            },
+            (AuctionPhase::Open, LedgerEvent::Commit { bidder, hash }) => {
+                let valid = self.kind.sealed() && !self.commits.iter().any(|(committer, _)| *committer == bidder);
+                if valid {
+                    self.commits.push((bidder, hash));
+                }
+                valid
+            },
+            (AuctionPhase::Closing, LedgerEvent::Reveal { bidder, bid, salt }) => {
+                let valid = timestamp <= self.end + REVEAL_WINDOW
+                    && !self.reveals.contains_key(&bidder)
+                    && self.commits.iter().any(|(committer, hash)| *committer == bidder && commitment(&self.topic, bid, &salt, &bidder) == *hash)
+                    && self.kind.verify_bid(&self.revealed(), &Bid::new(bid, bidder.clone()), local(self.end));
+                if valid {
+                    self.reveals.insert(bidder, bid);
+                }
+                valid
+            },
+            (AuctionPhase::Open | AuctionPhase::Closing, LedgerEvent::Extend { bidder }) => {
+                let rule = match self.soft_close {
+                    Some(rule) if self.extensions < MAX_EXTENSIONS => rule,
+                    _ => return false
+                };
+                let valid = self.late_bid.take_if(|(late, _)| *late == bidder).is_some();
+                if valid {
+                    self.end = rule.extend(local(self.end)).timestamp();
+                    self.extensions += 1;
+                    self.phase = AuctionPhase::Open;
+                    self.tick(timestamp);
+                }
+                valid
+            },
+            (phase, LedgerEvent::End { price, winner, allocation }) if !phase.terminal() => {
+                let valid = self.settleable(timestamp) && self.settles(&price, &winner, &allocation);
+                if valid {
+                    self.outcome = price.zip(winner);
+                    self.allocation = allocation;
+                    self.phase = AuctionPhase::Settled;
+                }
+                valid
+            },
+            (AuctionPhase::Pending | AuctionPhase::Open, LedgerEvent::Cancel) => {
+                self.phase = AuctionPhase::Cancelled;
+                true
+            },
+            _ => false
+        }
+    }
+
+    // END_TOPIC may be mined at timestamp (sealed bids are revealed first)
+    fn settleable(&self, timestamp: i64) -> bool {
+        !self.kind.sealed() || timestamp > self.end + REVEAL_WINDOW
+    }
+
+    /*
+        Outcome derived from the bids: escrowed bids (revealed
+        ones for sealed kinds) given by the kind, only sold at
+        (or above) the spec reserve. Multi kinds price their
+        reserve per unit (or topic), their outcome is the one
+        of their allocation.
+    */
+    pub fn expected_outcome(&self) -> Option<(usize, String)> {
+        let outcome = match self.kind.sealed() {
+            true => self.kind.outcome(&self.revealed()),
+            false => self.kind.outcome(&self.bids)
+        };
+        match &self.spec {
+            Some(spec) if !self.kind.multi() => spec.sold(outcome),
+            _ => outcome
+        }
+    }
+
+    // Winners of the escrowed bids of multi kinds (none for other kinds)
+    pub fn expected_allocation(&self) -> Vec<Allocation> {
+        match self.kind.multi() {
+            true => self.kind.allocate(&self.bids),
+            false => Vec::new()
+        }
+    }
+
+    /*
+        END_TOPIC (price, winner, allocation) records the outcome
+        of the bids, multi kinds their allocation (its revenue and
+        first winner as price and winner).
+    */
+    fn settles(&self, price: &Option<usize>, winner: &Option<String>, allocation: &[Allocation]) -> bool {
+        let expected = self.expected_allocation();
+        let (expected_price, expected_winner) = match self.kind.multi() {
+            true => allocation_outcome(&expected).unzip(),
+            false => self.expected_outcome().unzip()
+        };
+        allocation == expected && *price == expected_price && *winner == expected_winner
+    }
+
+    // Revealed bids, in commitment order (earliest wins ties)
+    pub fn revealed(&self) -> Vec<Bid> {
+        self.commits.iter()
+            .filter_map(|(bidder, _)| Some(Bid::new(*self.reveals.get(bidder)?, bidder.clone())))
+            .collect()
+    }
+
+    // Sealed outcome: committers without a reveal forfeit the reserve (see sealed.rs)
+    pub fn sealed(&self) -> Option<SealedOutcome> {
+        if !self.kind.sealed() {
+            return None
+        }
+        let forfeited = self.commits.iter()
+            .filter(|(bidder, _)| !self.reveals.contains_key(bidder))
+            .map(|(bidder, _)| (bidder.clone(), self.kind.reserve()))
+            .collect();
+        let revealed = self.revealed();
+        let winner = self.kind.outcome(&revealed);
+        Some(SealedOutcome { kind: self.kind.clone(), revealed, forfeited, winner })
+    }
+
+    // Highest escrowed bid
+    pub fn highest(&self) -> Option<&Bid> {
+        self.bids.iter().rev().max_by_key(|bid| bid.data)
+    }
+
+    /*
+        Outcome at now: recorded by END_TOPIC once settled, derived
+        from the bids while closing (once sealed bids are revealed),
+        none otherwise.
+    */
+    pub fn outcome_at(&self, now: i64) -> Option<(usize, String)> {
+        match self.phase_at(now) {
+            AuctionPhase::Settled => self.outcome.clone(),
+            AuctionPhase::Closing if self.settleable(now) => self.expected_outcome(),
+            _ => None
+        }
+    }
+
+    /*
+        Fields of App::get_json recorded on chain (see AppNode::get_pubsub_json),
+        the allocation of multi kinds only once recorded (bids are in the DHT).
+    */
+    pub fn as_json(&self, now: i64) -> Value {
+        let phase = self.phase_at(now);
+        let outcome = self.outcome_at(now);
+        let highest = self.highest();
+        let mut json = json!(
+            {
+                "phase": phase,
+                "highest_bid": highest.map_or(0, |bid| bid.data),
+                "highest_bidder": highest.map_or("unknown", |bid| bid.sender_addr.as_str()),
+                "kind": self.kind,
+                "clock_price": self.kind.clock_price(local(now)),
+                "soft_close": self.soft_close,
+                "spec": self.spec,
+                "bundle": self.bundle,
+                "cancelled": phase == AuctionPhase::Cancelled,
+                "price": outcome.as_ref().map(|(price, _)| *price),
+                "winner": outcome.map(|(_, winner)| winner),
+                "ttl": format!("{}", local(self.end)),
+            }
+        );
+        if phase == AuctionPhase::Settled && !self.allocation.is_empty() {
+            json["allocation"] = json!(self.allocation);
+        }
+        json
+    }
+}
+
+/**
+ * Auction engine:
+ *  Deterministic replay of the ledger auction events,
+ *  every node holding the same chain gets the same
+ *  records. The DHT instance is only a cache of it.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuctionEngine {
+    pub auctions: BTreeMap<String, AuctionRecord>,
+}
+
+impl AuctionEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Events (block timestamp, tx) in chain order
+    pub fn replay(events: &[(i64, String)]) -> Self {
+        let mut engine = Self::new();
+        for (timestamp, tx) in events {
+            engine.apply(*timestamp, tx);
+        }
+        engine
+    }
+
+    /*
+        First PUB_TOPIC of a topic creates its record, other events
+        need one. Returns whether the event was taken (see
+        AuctionRecord::apply), the ledger rejects events that are not.
+        A bundle takes its topics (see bundles) and ends them as it
+        ends, each with the allocation of the winner of it.
+    */
+    pub fn apply(&mut self, timestamp: i64, tx: &str) -> bool {
+        let (topic, event) = match LedgerEvent::parse(tx) {
+            Some(res) => res,
+            None => return false
+        };
+        match event {
+            LedgerEvent::Publish { publisher, kind, spec, soft_close, end } => {
+                if self.auctions.contains_key(&topic) {
+                    return false
+                }
+                let mut record = AuctionRecord {
+                    topic: topic.clone(),
+                    publisher,
+                    kind,
+                    spec: spec.map(|spec| *spec),
+                    soft_close,
+                    end,
+                    phase: AuctionPhase::Pending,
+                    bids: Vec::new(),
+                    commits: Vec::new(),
+                    reveals: BTreeMap::new(),
+                    late_bid: None,
+                    extensions: 0,
+                    bundle: None,
+                    outcome: None,
+                    allocation: Vec::new(),
+                };
+                record.tick(timestamp);
+                if let AuctionKind::Bundle { topics, .. } = &record.kind {
+                    if !self.bundles(&record.publisher, topics, timestamp) {
+                        return false
+                    }
+                    for lot in topics {
+                        if let Some(lot) = self.auctions.get_mut(lot) {
+                            lot.bundle = Some(topic.clone());
+                        }
+                    }
+                }
+                self.auctions.insert(topic, record);
+                true
+            },
+            event => {
+                let record = match self.auctions.get_mut(&topic) {
+                    Some(record) => record,
+                    None => return false
+                };
+                if !record.apply(timestamp, event) {
+                    return false
+                }
+                if let AuctionKind::Bundle { topics, .. } = &record.kind {
+                    let (phase, allocation, topics) = (record.phase, record.allocation.clone(), topics.clone());
+                    if phase.terminal() {
+                        for lot in &topics {
+                            if let Some(lot) = self.auctions.get_mut(lot) {
+                                lot.phase = phase;
+                                lot.allocation = allocation.iter().filter(|won| won.items.contains(&lot.topic)).cloned().collect();
+                            }
+                        }
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    /*
+        Bundle of topics (lots): distinct auctions of the publisher,
+        single item ones taking bids without any yet (pending or
+        open at timestamp), not in another bundle.
+    */
+    fn bundles(&self, publisher: &str, topics: &[String], timestamp: i64) -> bool {
+        let distinct = topics.iter().enumerate().all(|(i, lot)| !topics[..i].contains(lot));
+        !topics.is_empty() && distinct && topics.iter().all(|lot| match self.auctions.get(lot) {
+            Some(lot) => {
+                lot.publisher == publisher
+                    && !lot.kind.multi()
+                    && matches!(lot.phase_at(timestamp), AuctionPhase::Pending | AuctionPhase::Open)
+                    && lot.bids.is_empty()
+                    && lot.commits.is_empty()
+                    && lot.bundle.is_none()
+            },
+            None => false
+        })
+    }
+
+    pub fn auction(&self, topic: &str) -> Option<&AuctionRecord> {
+        self.auctions.get(topic)
+    }
+
+    // Topics in phase at now
+    pub fn in_phase(&self, phase: AuctionPhase, now: i64) -> Vec<&AuctionRecord> {
+        self.auctions.values()
+            .filter(|record| record.phase_at(now) == phase)
+            .collect()
+    }
+}
+
+/**
+ * AUCTION ENGINE
+**/
+impl Blockchain {
+    // Engine of the ledger state at our tip (kept by the snapshot once pruned)
+    pub fn auction_engine(&self) -> AuctionEngine {
+        self.with_tip_state(|state| state.engine.clone()).unwrap_or_default()
+    }
+
+    pub fn auction(&self, topic: &str) -> Option<AuctionRecord> {
+        self.with_tip_state(|state| state.engine.auction(topic).cloned()).flatten()
+    }
+}
@@ -0,0 +1,218 @@
+use super::blockchain::{Block, Blockchain};
+use super::mempool::tx_hash;
+use super::bootstrap::Data;
+use super::multiunit::parse_allocation;
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/**
+ * Transaction reference:
+ *  (block id, position of transaction in block)
+**/
+pub type TxRef = (u64, usize);
+
+/**
+ * Transaction entry:
+ *  Transaction returned by explorer queries,
+ *  data is the serialized (json) Data record.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TxEntry {
+    pub block_id: u64,
+    pub block_hash: String,
+    pub timestamp: i64,
+    pub index: usize,
+    pub data: String,
+}
+
+/**
+ * Chain index:
+ *  Lookup tables over the local chain, updated as blocks
+ *  are added (index_block) or removed by a reorg/rollback
+ *  (unindex_block), never rebuilt on every query.
+ *
+ *  - by_hash: block hash -> block id.
+ *  - by_tx: transaction hash -> transaction.
+ *  - by_type: msg_type -> transactions.
+ *  - by_topic: auction topic -> transactions (published/ended).
+ *  - by_address: node address -> transactions (register/publish/win).
+ *  - registered: node address -> (public key, block id) of its registration.
+ *  - publishers: auction topic -> (public key, block id) of its publication.
+**/
+#[derive(Debug, Clone, Default)]
+pub struct ChainIndex {
+    pub by_hash: HashMap<String, u64>,
+    pub by_tx: HashMap<String, TxRef>,
+    pub by_type: HashMap<usize, Vec<TxRef>>,
+    pub by_topic: HashMap<String, Vec<TxRef>>,
+    pub by_address: HashMap<String, Vec<TxRef>>,
+    pub registered: HashMap<String, (String, u64)>,
+    pub publishers: HashMap<String, (String, u64)>,
+}
+
+/*
+    Keys of a transaction (msg_type, topic, addresses), msg formats:
+        0 -> "REGISTER: <addr>"
+        1 -> "PUB_TOPIC: <topic>|PUBLISHER: <addr>|KIND: <kind>|SPEC: <spec>[|SOFT_CLOSE: <rule>]"
+        2 -> "END_TOPIC: <topic>|BID: <bid>|BIDDER: <addr>[|ALLOCATION: <allocation>]"
+        4, 5 -> sealed bid commit/reveal (see sealed.rs)
+        6 -> soft-close extension (see softclose.rs)
+        7 -> "CANCEL_TOPIC: <topic>|PUBLISHER: <addr>"
+        8, 9 -> mint/escrow of accounts (see accounts.rs)
+        10 -> feedback of a settlement (see reputation.rs)
+*/
+pub fn tx_keys(tx: &str) -> Option<(usize, Option<String>, Vec<String>)> {
+    let data: Value = serde_json::from_str(tx).ok()?;
+    let msg_type = data["msg_type"].as_u64()? as usize;
+    let msg = data["msg"].as_str()?;
+
+    let mut topic = None;
+    let mut addresses = Vec::new();
+    for field in msg.split('|') {
+        let (key, value) = match field.split_once(": ") {
+            Some(res) => res,
+            None => continue
+        };
+        let value = value.trim().trim_matches('"').to_string();
+        if value.is_empty() || value == "null" {
+            continue
+        }
+        match key {
+            "PUB_TOPIC" | "END_TOPIC" | "CANCEL_TOPIC" | "COMMIT" | "REVEAL" | "EXTEND" | "ESCROW" | "FEEDBACK" => topic = Some(value),
+            "REGISTER" | "PUBLISHER" | "BIDDER" | "MINT" | "RATER" | "RATED" => addresses.push(value),
+            "ALLOCATION" => {
+                for won in parse_allocation(&value).unwrap_or_default() {
+                    if !addresses.contains(&won.bidder) {
+                        addresses.push(won.bidder);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    Some((msg_type, topic, addresses))
+}
+
+// Fields (key -> value) of a msg
+pub fn msg_fields(msg: &str) -> HashMap<&str, &str> {
+    msg.split('|')
+        .filter_map(|field| field.split_once(": "))
+        .collect()
+}
+
+impl ChainIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn index_block(&mut self, block: &Block) {
+        self.by_hash.insert(block.hash.clone(), block.id);
+        if block.id == 0 {
+            return // genesis holds no transactions
+        }
+        for (index, tx) in block.transactions().iter().enumerate() {
+            let (msg_type, topic, addresses) = match tx_keys(tx) {
+                Some(keys) => keys,
+                None => continue
+            };
+            let txref = (block.id, index);
+            let author = Data::from_json(tx).map(|data| data.author().to_string()).unwrap_or_default();
+            self.by_tx.insert(tx_hash(tx), txref);
+            self.by_type.entry(msg_type).or_default().push(txref);
+            if let Some(topic) = topic {
+                if msg_type == 1 {
+                    self.publishers.insert(topic.clone(), (author.clone(), block.id));
+                }
+                self.by_topic.entry(topic).or_default().push(txref);
+            }
+            for address in addresses {
+                if msg_type == 0 {
+                    self.registered.entry(address.clone()).or_insert_with(|| (author.clone(), block.id));
+                }
+                self.by_address.entry(address).or_default().push(txref);
+            }
+        }
+    }
+
+    pub fn unindex_block(&mut self, block: &Block) {
+        self.by_hash.remove(&block.hash);
+        self.by_tx.retain(|_, (block_id, _)| *block_id != block.id);
+        self.registered.retain(|_, (_, block_id)| *block_id != block.id);
+        self.publishers.retain(|_, (_, block_id)| *block_id != block.id);
+        for refs in self.by_type.values_mut()
+            .chain(self.by_topic.values_mut())
+            .chain(self.by_address.values_mut()) {
+            refs.retain(|(block_id, _)| *block_id != block.id);
+        }
+        self.by_type.retain(|_, refs| !refs.is_empty());
+        self.by_topic.retain(|_, refs| !refs.is_empty());
+        self.by_address.retain(|_, refs| !refs.is_empty());
+    }
+}
+
+/**
+ * EXPLORER QUERIES
+**/
+impl Blockchain {
+    pub fn block_by_id(&self, id: u64) -> Option<&Block> {
+        self.blocks.get(id as usize).filter(|block| block.id == id)
+    }
+
+    pub fn block_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.index.by_hash.get(hash).and_then(|id| self.block_by_id(*id))
+    }
+
+    pub fn tx_by_hash(&self, hash: &str) -> Option<TxEntry> {
+        let txref = self.index.by_tx.get(hash)?;
+        self.tx_entries(&[*txref]).pop()
+    }
+
+    pub fn txs_by_type(&self, msg_type: usize) -> Vec<TxEntry> {
+        self.tx_entries(self.index.by_type.get(&msg_type).map_or(&[], Vec::as_slice))
+    }
+
+    // Every event (publish/end) of an auction topic
+    pub fn topic_events(&self, topic: &str) -> Vec<TxEntry> {
+        self.tx_entries(self.index.by_topic.get(topic).map_or(&[], Vec::as_slice))
+    }
+
+    // Every transaction mentioning a node address
+    pub fn address_activity(&self, addr: &str) -> Vec<TxEntry> {
+        self.tx_entries(self.index.by_address.get(addr).map_or(&[], Vec::as_slice))
+    }
+
+    /*
+        Paginated iteration:
+            Page 0 holds the newest per_page blocks (tip first),
+            an empty page means there are no older blocks.
+    */
+    pub fn blocks_page(&self, page: usize, per_page: usize) -> Vec<&Block> {
+        self.blocks.iter()
+            .rev()
+            .skip(page.saturating_mul(per_page))
+            .take(per_page)
+            .collect()
+    }
+
+    fn tx_entries(&self, refs: &[TxRef]) -> Vec<TxEntry> {
+        let mut res = Vec::new();
+        for (block_id, index) in refs {
+            let block = match self.block_by_id(*block_id) {
+                Some(block) => block,
+                None => continue
+            };
+            if let Some(data) = block.transactions().get(*index) {
+                res.push(TxEntry {
+                    block_id: block.id,
+                    block_hash: block.hash.clone(),
+                    timestamp: block.timestamp,
+                    index: *index,
+                    data: data.clone(),
+                });
+            }
+        }
+        res
+    }
+}
@@ -0,0 +1,118 @@
+use super::blockchain::Blockchain;
+use super::identity::{Identity, verify};
+
+use serde::{Serialize, Deserialize};
+
+// Blocks on top of a block before it is considered final
+pub const CONFIRMATION_DEPTH: u64 = 6;
+
+/**
+ * Hard checkpoint:
+ *  Hash of the block at height, signed by a bootstrap
+ *  node. Chains without this block are rejected, thus
+ *  no reorg goes deeper than the latest checkpoint.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub hash: String,
+    pub signer: String,
+    pub signature: String,
+}
+
+impl Checkpoint {
+    pub fn new(height: u64, hash: String, identity: &Identity) -> Self {
+        let signature = identity.sign(checkpoint_msg(height, &hash).as_bytes());
+        Self { height, hash, signer: identity.public_key(), signature }
+    }
+
+    pub fn verify(&self) -> bool {
+        verify(&self.signer, checkpoint_msg(self.height, &self.hash).as_bytes(), &self.signature)
+    }
+}
+
+fn checkpoint_msg(height: u64, hash: &str) -> String {
+    format!("CHECKPOINT: {}|HASH: {}", height, hash)
+}
+
+/**
+ * Finality:
+ *  - confirmations: depth after which a block is final.
+ *  - checkpoint: latest accepted hard checkpoint.
+ *  - signers: public keys trusted to sign checkpoints.
+**/
+#[derive(Debug, Clone)]
+pub struct Finality {
+    pub confirmations: u64,
+    pub checkpoint: Option<Checkpoint>,
+    pub signers: Vec<String>,
+}
+
+impl Default for Finality {
+    fn default() -> Self {
+        Self { confirmations: CONFIRMATION_DEPTH, checkpoint: None, signers: Vec::new() }
+    }
+}
+
+impl Finality {
+    // Blocks up to this height are final (confirmed or checkpointed)
+    pub fn finalized_height(&self, tip_height: u64) -> u64 {
+        let checkpoint = self.checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.height);
+        tip_height.saturating_sub(self.confirmations).max(checkpoint)
+    }
+
+    /*
+        Accept checkpoint:
+            Signed by a trusted key, higher than our latest
+            one and matching our block (hash) at its height.
+    */
+    pub fn accept(&mut self, checkpoint: Checkpoint, hash_at_height: Option<&str>) -> bool {
+        let higher = self.checkpoint.as_ref().is_none_or(|latest| checkpoint.height > latest.height);
+        if !higher
+            || !self.signers.contains(&checkpoint.signer)
+            || !checkpoint.verify()
+            || hash_at_height != Some(checkpoint.hash.as_str()) {
+            return false
+        }
+        self.checkpoint = Some(checkpoint);
+        true
+    }
+
+    // Chain (hash at checkpoint height) keeps our checkpointed block
+    pub fn allows(&self, hash_at_height: impl Fn(u64) -> Option<String>) -> bool {
+        match &self.checkpoint {
+            Some(checkpoint) => hash_at_height(checkpoint.height).as_deref() == Some(checkpoint.hash.as_str()),
+            None => true
+        }
+    }
+}
+
+/**
+ * FINALITY
+**/
+impl Blockchain {
+    pub fn finalized_height(&self) -> u64 {
+        self.finality.finalized_height(self.tip().height)
+    }
+
+    // Block (by id and hash) is in our chain and final
+    pub fn is_final(&self, id: u64, hash: &str) -> bool {
+        id <= self.finalized_height()
+            && self.block_by_id(id).map(|block| block.hash.as_str()) == Some(hash)
+    }
+
+    pub fn add_checkpoint(&mut self, checkpoint: Checkpoint) -> bool {
+        let hash = self.block_by_id(checkpoint.height).map(|block| block.hash.clone());
+        self.finality.accept(checkpoint, hash.as_deref())
+    }
+
+    // Checkpoint of our confirmed height, if above the latest checkpoint
+    pub fn checkpoint_due(&self, identity: &Identity) -> Option<Checkpoint> {
+        let height = self.tip().height.checked_sub(self.finality.confirmations)?;
+        if self.finality.checkpoint.as_ref().is_some_and(|latest| latest.height >= height) {
+            return None
+        }
+        let block = self.block_by_id(height)?;
+        Some(Checkpoint::new(height, block.hash.clone(), identity))
+    }
+}
@@ -0,0 +1,227 @@
+use super::blockchain::Blockchain;
+use super::bootstrap::Data;
+use super::engine::{AuctionEngine, AuctionPhase, AuctionRecord, LedgerEvent};
+use super::explorer::{TxEntry, msg_fields};
+use super::sealed::{REVEAL_MSG_TYPE, sealed_outcome};
+
+use serde::{Serialize, Deserialize};
+
+/**
+ * Bid entry:
+ *  Bid recorded on chain (escrow of an open auction, reveal
+ *  of a sealed one) with its block reference, accepted if
+ *  the auction took it (see AuctionEngine, sealed_outcome).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BidEntry {
+    pub topic: String,
+    pub bidder: String,
+    pub amount: usize,
+    pub timestamp: i64,
+    pub block_id: u64,
+    pub block_hash: String,
+    pub accepted: bool,
+}
+
+/**
+ * Bid status (of a bidder in an auction):
+ *  - Leading, Outbid: highest accepted bid is (not) ours.
+ *  - Won, Lost: settled with (without) us as the winner.
+ *  - Cancelled: withdrawn by the seller, escrow refunded.
+ *  - Rejected: none of our bids was accepted.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BidStatus {
+    Leading,
+    Outbid,
+    Won,
+    Lost,
+    Cancelled,
+    Rejected,
+}
+
+// Highest bid of a bidder in an auction, with its current status
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MyBid {
+    pub bid: BidEntry,
+    pub phase: AuctionPhase,
+    pub status: BidStatus,
+}
+
+// Auction of a seller, highest is its highest accepted bid
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MyAuction {
+    pub topic: String,
+    pub phase: AuctionPhase,
+    pub end: i64,
+    pub bids: usize,
+    pub highest: Option<BidEntry>,
+    pub outcome: Option<(usize, String)>,
+}
+
+// Auction record replayed from the entries of its topic
+pub fn auction_record(topic: &str, entries: &[TxEntry]) -> Option<AuctionRecord> {
+    let events: Vec<(i64, String)> = entries.iter()
+        .map(|entry| (entry.timestamp, entry.data.clone()))
+        .collect();
+    AuctionEngine::replay(&events).auction(topic).cloned()
+}
+
+/*
+    Bids of a topic in chain order, from the entries of its
+    topic: escrows are accepted if the engine took them at
+    their block, reveals if the sealed outcome counts them.
+*/
+pub fn bid_history(topic: &str, entries: &[TxEntry]) -> Vec<BidEntry> {
+    let events: Vec<(i64, String)> = entries.iter()
+        .map(|entry| (entry.timestamp, entry.data.clone()))
+        .collect();
+    let revealed = sealed_outcome(topic, &events).map_or(Vec::new(), |outcome| outcome.revealed);
+    let mut engine = AuctionEngine::new();
+    let mut res = Vec::new();
+    for entry in entries {
+        let taken = engine.auction(topic).map_or(0, |record| record.bids.len());
+        engine.apply(entry.timestamp, &entry.data);
+        let (bidder, amount, accepted) = match LedgerEvent::parse(&entry.data) {
+            Some((x, LedgerEvent::Escrow { bid })) if x == topic => {
+                let accepted = engine.auction(topic).is_some_and(|record| record.bids.len() > taken);
+                (bid.sender_addr, bid.data, accepted)
+            },
+            _ => match reveal(topic, &entry.data) {
+                Some((bidder, amount)) => {
+                    let accepted = revealed.iter().any(|bid| bid.sender_addr == bidder && bid.data == amount);
+                    (bidder, amount, accepted)
+                },
+                None => continue
+            }
+        };
+        res.push(BidEntry {
+            topic: topic.to_string(),
+            bidder,
+            amount,
+            timestamp: entry.timestamp,
+            block_id: entry.block_id,
+            block_hash: entry.block_hash.clone(),
+            accepted,
+        });
+    }
+    res
+}
+
+// (bidder, bid) of a reveal of topic
+fn reveal(topic: &str, tx: &str) -> Option<(String, usize)> {
+    let data = Data::from_json(tx)?;
+    let record = msg_fields(data.msg());
+    if data.msg_type() != REVEAL_MSG_TYPE || record.get("REVEAL") != Some(&topic) {
+        return None
+    }
+    Some((record.get("BIDDER")?.to_string(), record.get("BID")?.parse().ok()?))
+}
+
+// Highest accepted bid, earliest one on ties
+fn highest(history: &[BidEntry]) -> Option<&BidEntry> {
+    history.iter().filter(|bid| bid.accepted).rev().max_by_key(|bid| bid.amount)
+}
+
+pub fn bid_status(record: &AuctionRecord, history: &[BidEntry], bidder: &str, now: i64) -> BidStatus {
+    match record.phase_at(now) {
+        AuctionPhase::Cancelled => BidStatus::Cancelled,
+        AuctionPhase::Settled => match &record.outcome {
+            Some((_, winner)) if winner == bidder => BidStatus::Won,
+            _ => BidStatus::Lost
+        },
+        _ if !history.iter().any(|bid| bid.bidder == bidder && bid.accepted) => BidStatus::Rejected,
+        _ => match highest(history) {
+            Some(bid) if bid.bidder == bidder => BidStatus::Leading,
+            _ => BidStatus::Outbid
+        }
+    }
+}
+
+/*
+    Views of an address from its activity (entries mentioning
+    it), topic_entries gives the entries of a topic:
+        my_bids: our highest bid of every topic we bid on.
+        my_auctions: every topic we published.
+*/
+pub fn my_bids(addr: &str, activity: &[TxEntry], topic_entries: impl Fn(&str) -> Vec<TxEntry>, now: i64) -> Vec<MyBid> {
+    let mut res = Vec::new();
+    for topic in topics(activity, addr, &["ESCROW", "REVEAL", "COMMIT"], "BIDDER") {
+        let entries = topic_entries(&topic);
+        let record = match auction_record(&topic, &entries) {
+            Some(record) => record,
+            None => continue
+        };
+        let history = bid_history(&topic, &entries);
+        let bid = history.iter()
+            .filter(|bid| bid.bidder == addr)
+            .max_by_key(|bid| (bid.accepted, bid.amount));
+        if let Some(bid) = bid {
+            res.push(MyBid {
+                bid: bid.clone(),
+                phase: record.phase_at(now),
+                status: bid_status(&record, &history, addr, now),
+            });
+        }
+    }
+    res
+}
+
+pub fn my_auctions(addr: &str, activity: &[TxEntry], topic_entries: impl Fn(&str) -> Vec<TxEntry>, now: i64) -> Vec<MyAuction> {
+    let mut res = Vec::new();
+    for topic in topics(activity, addr, &["PUB_TOPIC"], "PUBLISHER") {
+        let entries = topic_entries(&topic);
+        let record = match auction_record(&topic, &entries) {
+            Some(record) if record.publisher == addr => record,
+            _ => continue
+        };
+        let history = bid_history(&topic, &entries);
+        res.push(MyAuction {
+            topic,
+            phase: record.phase_at(now),
+            end: record.end,
+            bids: history.iter().filter(|bid| bid.accepted).count(),
+            highest: highest(&history).cloned(),
+            outcome: record.outcome_at(now),
+        });
+    }
+    res
+}
+
+// Topics (first seen order) of activity where role (key) is addr
+fn topics(activity: &[TxEntry], addr: &str, keys: &[&str], role: &str) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    for entry in activity {
+        let data = match Data::from_json(&entry.data) {
+            Some(data) => data,
+            None => continue
+        };
+        let record = msg_fields(data.msg());
+        if record.get(role).map(|x| x.trim_matches('"')) != Some(addr) {
+            continue
+        }
+        if let Some(topic) = keys.iter().find_map(|key| record.get(key)).map(|x| x.trim().trim_matches('"')) {
+            if !res.iter().any(|x| x == topic) {
+                res.push(topic.to_string());
+            }
+        }
+    }
+    res
+}
+
+/**
+ * BID HISTORY
+**/
+impl Blockchain {
+    pub fn bid_history(&self, topic: &str) -> Vec<BidEntry> {
+        bid_history(topic, &self.topic_events(topic))
+    }
+
+    pub fn my_bids(&self, addr: &str, now: i64) -> Vec<MyBid> {
+        my_bids(addr, &self.address_activity(addr), |topic| self.topic_events(topic), now)
+    }
+
+    pub fn my_auctions(&self, addr: &str, now: i64) -> Vec<MyAuction> {
+        my_auctions(addr, &self.address_activity(addr), |topic| self.topic_events(topic), now)
+    }
+}
@@ -0,0 +1,58 @@
+use crypto::ed25519;
+use std::fmt::{Debug, Formatter, Error};
+
+/**
+ * Identity:
+ *  Ed25519 keypair of a node, public key (hex) identifies
+ *  the node in the ledger and is used to verify signatures.
+**/
+#[derive(Clone)]
+pub struct Identity {
+    pub public: [u8; 32],
+    secret: [u8; 64],
+}
+
+impl Default for Identity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Identity {
+    pub fn new() -> Self {
+        let seed: [u8; 32] = rand::random();
+        Self::from_seed(&seed)
+    }
+
+    pub fn from_seed(seed: &[u8]) -> Self {
+        let (secret, public) = ed25519::keypair(seed);
+        Self { public, secret }
+    }
+
+    pub fn public_key(&self) -> String {
+        hex::encode(self.public)
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> String {
+        hex::encode(ed25519::signature(msg, &self.secret))
+    }
+}
+
+// Verify signature (hex) of msg with public key (hex)
+pub fn verify(public_key: &str, msg: &[u8], signature: &str) -> bool {
+    let public = match hex::decode(public_key) {
+        Ok(public) if public.len() == 32 => public,
+        _ => return false
+    };
+    let signature = match hex::decode(signature) {
+        Ok(signature) if signature.len() == 64 => signature,
+        _ => return false
+    };
+    ed25519::verify(msg, &public, &signature)
+}
+
+impl Debug for Identity {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "Identity({})", self.public_key())
+    }
+}
@@ -2,18 +2,30 @@ use super::rpc::{
     Rpc, RpcRequestWithMeta, RpcMessage, RpcPayload, 
     KademliaRequest, KademliaResponse, 
     QueryValueResult, 
-    full_rpc_proc
+    full_rpc_proc, fit_payload
 };
 use super::node::{Node, Key, Distance, NodeWithDistance};
 use super::{K_PARAM, N_KBUCKETS, KEY_LEN, ALPHA, TREPLICATE};
-use super::blockchain::{Blockchain, Block};
+use super::blockchain::{Blockchain, Block, BlockHeader, ChainTip, MAX_HEADERS, MAX_BLOCKS, parse_transactions};
+use super::mempool::{Mempool, Transaction};
+use super::miner::Miner;
+use super::identity::Identity;
+use super::consensus::ConsensusEngine;
+use super::snapshot::Snapshot;
+use super::finality::Checkpoint;
+use super::light::{ProofQuery, TxProof};
+use super::notify::Inbox;
+use super::coordinator::Coordinator;
+use super::search::TopicCache;
 
 use crossbeam_channel;
 use std::thread::{JoinHandle, spawn, sleep};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::collections::{HashMap, BinaryHeap, HashSet};
 use std::str;
 use std::time::Duration;
+use chrono::Local;
 use log::{info};
 
 /**
@@ -45,6 +57,17 @@ pub struct KademliaInstance {
     pub hashmap: Arc<Mutex<HashMap<String, String>>>,
     pub node: Node,
     pub blockchain: Arc<Mutex<Blockchain>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+    pub miner: Miner,
+    pub identity: Identity,
+    // Light clients keep headers only, block announcements are ignored
+    pub headers_only: Arc<AtomicBool>,
+    // Auction events pushed to us (see notify.rs)
+    pub inbox: Inbox,
+    // Bids of topics we coordinate (see coordinator.rs)
+    pub coordinator: Coordinator,
+    // Auction engine of our tip, for SearchTopics (see search.rs)
+    pub topic_cache: TopicCache,
 }
 
 impl Bucket {
@@ -203,6 +226,25 @@ impl RoutingTable {
         res
     }
 
+    // All known nodes (excluding self), used for gossip
+    pub fn get_nodes(&self) -> Vec<Node> {
+        let mut res = Vec::new();
+        for bucket in &self.kbuckets {
+            for node in &bucket.nodes {
+                if node.id != self.node.id {
+                    res.push(node.clone());
+                }
+            }
+        }
+        res
+    }
+
+    // Remove node (departed) from routing table
+    pub fn remove_node(&mut self, key: &Key) {
+        let bucketindex = self.get_bucket_index(key);
+        self.kbuckets[bucketindex].nodes.retain(|node| node.id != *key);
+    }
+
     // Check if node contains key in routing table
     pub fn contains_node(&self, key: &Key) -> bool {
         let bucket = self.get_bucket_nodes(key);
@@ -232,7 +274,14 @@ impl KademliaInstance {
             routingtable: Arc::new(Mutex::new(routingtable)),
             hashmap: Arc::new(Mutex::new(HashMap::new())),
             node: node.clone(),
-            blockchain: Arc::new(Mutex::new(blockchain))
+            blockchain: Arc::new(Mutex::new(blockchain)),
+            mempool: Arc::new(Mutex::new(Mempool::new())),
+            miner: Miner::default(),
+            identity: Identity::new(),
+            headers_only: Arc::new(AtomicBool::new(false)),
+            inbox: Inbox::new(),
+            coordinator: Coordinator::new(),
+            topic_cache: TopicCache::new(),
         };
 
         kad.clone().requests_handler(rpc_receiver);
@@ -419,7 +468,7 @@ impl KademliaInstance {
                     // if result node(s) (closest nodes to qynode)
                     // haven't been searched add them to heap
                     for entry in entries {
-                        if history.insert(entry.clone()) {
+                        if !self.coordinator.suspected(&entry.0.id) && history.insert(entry.clone()) {
                             nodes.push(entry);
                         }
                     }
@@ -484,7 +533,7 @@ impl KademliaInstance {
                             res.push(qynode);
 
                             for entry in entries {
-                                if history.insert(entry.clone()) {
+                                if !self.coordinator.suspected(&entry.0.id) && history.insert(entry.clone()) {
                                     nodes.push(entry);
                                 }
                             }
@@ -553,6 +602,8 @@ impl KademliaInstance {
         }
 
         drop(routingtable);
+        // nodes not answering the coordinator are left out until they do (see coordinator.rs)
+        nodes.retain(|NodeWithDistance(node, _)| !self.coordinator.suspected(&node.id));
         nodes
     }
 
@@ -638,6 +689,327 @@ impl KademliaInstance {
         }
     }
 
+    /**
+     * MEMPOOL
+    **/
+    /*
+        Submit transaction:
+            Add transaction to local mempool and gossip it
+            to every node in our routing table. Nodes receiving
+            a new transaction (hash not seen) gossip it further.
+    */
+    pub fn submit_transaction(&self, tx: Transaction) -> bool {
+        let mut mempool = self.mempool.lock()
+            .expect("Error setting lock in mempool");
+        let new = mempool.add(tx.clone());
+        drop(mempool);
+
+        if new {
+            self.gossip_transaction(tx, None);
+        }
+        new
+    }
+
+    fn gossip_transaction(&self, tx: Transaction, src: Option<String>) {
+        let routingtable = self.routingtable.lock()
+            .expect("Error setting lock in routing table");
+        let nodes = routingtable.get_nodes();
+        drop(routingtable);
+
+        for node in nodes {
+            if Some(node.get_addr()) == src {
+                continue
+            }
+            let kad = self.clone();
+            let tx = tx.clone();
+            spawn(move || {
+                full_rpc_proc(&kad.rpc, KademliaRequest::SubmitTransaction(tx), node);
+            });
+        }
+    }
+
+    // Block template (data) from pending transactions not yet in local chain,
+    // transactions invalid on our tip (e.g. outbid escrow) are left out
+    pub fn block_template(&self) -> Option<String> {
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let blocks = blockchain.blocks.clone();
+        drop(blockchain);
+
+        let mut mempool = self.mempool.lock()
+            .expect("Error setting lock in mempool");
+        mempool.prune(&blocks);
+        let template = mempool.block_template();
+        drop(mempool);
+
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let txs = blockchain.valid_txs(parse_transactions(&template?));
+        drop(blockchain);
+        if txs.is_empty() {
+            return None
+        }
+        Some(serde_json::to_string(&txs).expect("Error serializing block template"))
+    }
+
+    // Consensus engine of local chain (selected at genesis)
+    pub fn consensus(&self) -> Arc<dyn ConsensusEngine> {
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let consensus = blockchain.consensus.clone();
+        drop(blockchain);
+        consensus
+    }
+
+    /*
+        Mine next block:
+            Template is sealed by the consensus engine (mined or
+            signed) on top of our tip without holding the chain
+            lock, if a competing block arrives meanwhile (job
+            aborted or tip changed) or we are not allowed to seal
+            no block is added.
+    */
+    pub fn mine_next(&self, data: String) -> Option<Block> {
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let tip = blockchain.blocks.last().expect("At least one block").clone();
+        let consensus = blockchain.consensus.clone();
+        drop(blockchain);
+
+        let block = consensus.seal(&self.miner, &self.identity, &tip.header(), data)?;
+
+        let mut blockchain = self.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let res = blockchain.add_block(block.clone());
+        drop(blockchain);
+
+        if res {
+            return Some(block)
+        }
+        None
+    }
+
+    /**
+     * BLOCK ANNOUNCEMENT & HEADERS-FIRST SYNC
+    **/
+    /*
+        Announce block:
+            Send header of a new block to every node in our
+            routing table (except src), nodes missing the block
+            sync from us and announce it further.
+    */
+    pub fn announce_block(&self, header: BlockHeader, src: Option<String>) {
+        let routingtable = self.routingtable.lock()
+            .expect("Error setting lock in routing table");
+        let nodes = routingtable.get_nodes();
+        drop(routingtable);
+
+        for node in nodes {
+            if Some(node.get_addr()) == src {
+                continue
+            }
+            let kad = self.clone();
+            let header = header.clone();
+            spawn(move || {
+                full_rpc_proc(&kad.rpc, KademliaRequest::AnnounceBlock(header), node);
+            });
+        }
+    }
+
+    // Query node for its chain tip commitment
+    pub fn query_tip(&self, qynode: Node) -> Option<ChainTip> {
+        let res = full_rpc_proc(&self.rpc, KademliaRequest::GetTip, qynode);
+
+        if let Some(KademliaResponse::GetTip(tip)) = res {
+            Some(tip)
+        } else {
+            None
+        }
+    }
+
+    // Query node for headers after our locator (common ancestor)
+    pub fn query_headers(&self, qynode: Node, locator: Vec<String>) -> Option<Vec<BlockHeader>> {
+        let res = full_rpc_proc(&self.rpc, KademliaRequest::GetHeaders(locator), qynode);
+
+        if let Some(KademliaResponse::GetHeaders(headers)) = res {
+            Some(headers)
+        } else {
+            None
+        }
+    }
+
+    /*
+        Pin the genesis of the network of bootnode (the node we
+        join), thus our chain only adopts that genesis (and its
+        consensus engine), never one pushed by another peer.
+    */
+    pub fn pin_genesis(&self, bootnode: Node) -> bool {
+        let genesis = match self.query_headers(bootnode, Vec::new()).and_then(|headers| headers.into_iter().next()) {
+            Some(header) if header.id == 0 => header,
+            _ => return false
+        };
+        let mut blockchain = self.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        blockchain.genesis_pin = Some(genesis.hash);
+        drop(blockchain);
+        true
+    }
+
+    // Query node for block bodies
+    pub fn query_blocks(&self, qynode: Node, hashes: Vec<String>) -> Option<Vec<Block>> {
+        let res = full_rpc_proc(&self.rpc, KademliaRequest::GetBlocks(hashes), qynode);
+
+        if let Some(KademliaResponse::GetBlocks(blocks)) = res {
+            Some(blocks)
+        } else {
+            None
+        }
+    }
+
+    // Query node for its snapshot (and commitment transaction)
+    pub fn query_snapshot(&self, qynode: Node) -> Option<(Snapshot, String)> {
+        let res = full_rpc_proc(&self.rpc, KademliaRequest::GetSnapshot, qynode);
+
+        if let Some(KademliaResponse::GetSnapshot(snapshot)) = res {
+            snapshot
+        } else {
+            None
+        }
+    }
+
+    // Query node for its latest hard checkpoint
+    pub fn query_checkpoint(&self, qynode: Node) -> Option<Checkpoint> {
+        let res = full_rpc_proc(&self.rpc, KademliaRequest::GetCheckpoint, qynode);
+
+        if let Some(KademliaResponse::GetCheckpoint(checkpoint)) = res {
+            checkpoint
+        } else {
+            None
+        }
+    }
+
+    // Query full node for merkle proofs (light clients)
+    pub fn query_proofs(&self, qynode: Node, query: ProofQuery) -> Option<Vec<TxProof>> {
+        let res = full_rpc_proc(&self.rpc, KademliaRequest::GetTxProofs(query), qynode);
+
+        if let Some(KademliaResponse::GetTxProofs(proofs)) = res {
+            Some(proofs)
+        } else {
+            None
+        }
+    }
+
+    // Query full node for the funds of a bidder on a topic (light clients)
+    pub fn query_funds(&self, qynode: Node, topic: &str, bidder: &str) -> Option<u64> {
+        let res = full_rpc_proc(&self.rpc, KademliaRequest::GetFunds(topic.to_string(), bidder.to_string()), qynode);
+
+        if let Some(KademliaResponse::GetFunds(funds)) = res {
+            funds
+        } else {
+            None
+        }
+    }
+
+    /*
+        Sync from node:
+            Fetch headers after our locator, then the bodies
+            of those blocks, and apply them (possibly a reorg).
+            Repeated until node has no more headers for us.
+            If pruned blocks are received (fast bootstrap) the
+            snapshot behind them is fetched and installed first
+            (committed by a snapshot signer), bodies above it
+            are never taken pruned.
+            Returns the blocks added to the local chain.
+    */
+    pub fn sync_from(&self, qynode: Node) -> Vec<Block> {
+        let mut res: Vec<Block> = Vec::new();
+
+        loop {
+            let blockchain = self.blockchain.lock()
+                .expect("Error setting lock in local blockchain");
+            let locator = blockchain.locator();
+            drop(blockchain);
+
+            let headers = match self.query_headers(qynode.clone(), locator) {
+                Some(headers) => headers,
+                None => break
+            };
+            if headers.is_empty() {
+                break
+            }
+
+            // bodies in chunks, a response capped by size is resumed after its last block
+            let hashes: Vec<String> = headers.iter().map(|header| header.hash.clone()).collect();
+            let mut blocks: Vec<Block> = Vec::new();
+            while blocks.len() < hashes.len() {
+                let chunk = hashes.iter().skip(blocks.len()).take(MAX_BLOCKS).cloned().collect();
+                match self.query_blocks(qynode.clone(), chunk) {
+                    Some(chunk_blocks) if !chunk_blocks.is_empty() => blocks.extend(chunk_blocks),
+                    _ => break
+                }
+            }
+
+            let blockchain = self.blockchain.lock()
+                .expect("Error setting lock in local blockchain");
+            let missing = blocks.iter().any(|block| block.data.is_empty() && !blockchain.accepts_pruned(block));
+            drop(blockchain);
+            if missing {
+                if let Some((snapshot, commitment)) = self.query_snapshot(qynode.clone()) {
+                    let mut blockchain = self.blockchain.lock()
+                        .expect("Error setting lock in local blockchain");
+                    blockchain.install_snapshot(snapshot, &commitment);
+                    drop(blockchain);
+                }
+            }
+
+            let mut blockchain = self.blockchain.lock()
+                .expect("Error setting lock in local blockchain");
+            let applied = blockchain.apply_blocks(blocks);
+            drop(blockchain);
+
+            if applied.is_empty() {
+                break
+            }
+            let mut mempool = self.mempool.lock()
+                .expect("Error setting lock in mempool");
+            mempool.prune(&applied);
+            drop(mempool);
+
+            if let Some(block) = applied.last() {
+                self.miner.abort(block.id);
+            }
+            res.extend(applied);
+            if headers.len() < MAX_HEADERS {
+                break
+            }
+        }
+
+        res
+    }
+
+    /*
+        Sync by tip:
+            Tips are compared first, the divergent suffix is
+            only fetched (headers-first) if the remote tip is
+            unknown to us and carries more work than ours.
+    */
+    pub fn sync_tip(&self, qynode: Node) -> Vec<Block> {
+        let remote = match self.query_tip(qynode.clone()) {
+            Some(tip) => tip,
+            None => return Vec::new()
+        };
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let local = blockchain.tip();
+        let known = blockchain.contains_hash(&remote.hash);
+        drop(blockchain);
+
+        if known || remote.work <= local.work {
+            return Vec::new()
+        }
+        self.sync_from(qynode)
+    }
+
     /** 
      * Requests handler & Response constructor
     */
@@ -668,18 +1040,20 @@ impl KademliaInstance {
             .expect("Error setting lock in routing table");
 
         let addr: Vec<&str> = request.src.split(":").collect();
-        routingtable.update_routing_table(
-            Node::new(
-                addr[0].to_string(), 
-                addr[1].parse::<u16>()
-                    .expect("Error parsing port to u16"),
-            )
+        let src_node = Node::new(
+            addr[0].to_string(), 
+            addr[1].parse::<u16>()
+                .expect("Error parsing port to u16"),
         );
+        routingtable.update_routing_table(src_node.clone());
         drop(routingtable);
 
         match request.payload {
             KademliaRequest::Ping => (KademliaResponse::Ping, request),
             KademliaRequest::Store(ref key, ref value) => {
+                if !self.accepts_store(key, value, &src_node) {
+                    return (KademliaResponse::PingUnableProcReq, request)
+                }
                 let mut hashmap = self.hashmap.lock()
                     .expect("");
                 hashmap.insert(key.to_string(), value.to_string());
@@ -722,10 +1096,118 @@ impl KademliaInstance {
                 let res = blockchain.add_block(block.clone());
                 drop(blockchain);
                 if res {
+                    let mut mempool = self.mempool.lock()
+                        .expect("Error setting lock in mempool");
+                    mempool.prune(std::slice::from_ref(block));
+                    drop(mempool);
+                    self.miner.abort(block.id);
+                    self.announce_block(block.header(), Some(request.src.clone()));
                     return (KademliaResponse::Ping, request)
                 }
                 (KademliaResponse::PingUnableProcReq, request)
             },
+            KademliaRequest::SubmitTransaction(ref tx) => {
+                let mut mempool = self.mempool.lock()
+                    .expect("Error setting lock in mempool");
+                let new = mempool.add(tx.clone());
+                drop(mempool);
+                if new {
+                    self.gossip_transaction(tx.clone(), Some(request.src.clone()));
+                    return (KademliaResponse::Ping, request)
+                }
+                (KademliaResponse::PingUnableProcReq, request)
+            },
+            KademliaRequest::AnnounceBlock(ref header) => {
+                let blockchain = self.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                let known = blockchain.contains_hash(&header.hash);
+                drop(blockchain);
+                if !known && !self.headers_only.load(Ordering::Relaxed) {
+                    let kad = self.clone();
+                    let src = request.src.clone();
+                    spawn(move || {
+                        if let Some(block) = kad.sync_from(src_node).last() {
+                            kad.announce_block(block.header(), Some(src));
+                        }
+                    });
+                }
+                (KademliaResponse::Ping, request)
+            },
+            KademliaRequest::GetTip => {
+                let blockchain = self.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                let tip = blockchain.tip();
+                drop(blockchain);
+                (KademliaResponse::GetTip(tip), request)
+            },
+            KademliaRequest::GetHeaders(ref locator) => {
+                let blockchain = self.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                let headers = blockchain.headers_after(locator);
+                drop(blockchain);
+                (KademliaResponse::GetHeaders(headers), request)
+            },
+            KademliaRequest::GetBlocks(ref hashes) => {
+                let blockchain = self.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                let blocks = fit_payload(blockchain.get_blocks(hashes));
+                drop(blockchain);
+                (KademliaResponse::GetBlocks(blocks), request)
+            },
+            KademliaRequest::GetSnapshot => {
+                let blockchain = self.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                let snapshot = blockchain.snapshot.clone().zip(blockchain.snapshot_commitment());
+                drop(blockchain);
+                (KademliaResponse::GetSnapshot(snapshot), request)
+            },
+            KademliaRequest::GetCheckpoint => {
+                let blockchain = self.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                let checkpoint = blockchain.finality.checkpoint.clone();
+                drop(blockchain);
+                (KademliaResponse::GetCheckpoint(checkpoint), request)
+            },
+            KademliaRequest::GetTxProofs(ref query) => {
+                let blockchain = self.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                let proofs = blockchain.tx_proofs(query);
+                drop(blockchain);
+                (KademliaResponse::GetTxProofs(proofs), request)
+            },
+            KademliaRequest::GetFunds(ref topic, ref bidder) => {
+                let blockchain = self.blockchain.lock()
+                    .expect("Error setting lock in local blockchain");
+                let funds = blockchain.with_tip_state(|state| state.funds(topic, bidder));
+                drop(blockchain);
+                (KademliaResponse::GetFunds(funds), request)
+            },
+            KademliaRequest::Notify(ref notification) => {
+                // acknowledged even if seen (or forged), the sender stops retrying
+                if self.authentic(notification) {
+                    self.inbox.deliver(notification.clone());
+                }
+                (KademliaResponse::Ping, request)
+            },
+            KademliaRequest::SearchTopics(ref query) => {
+                let entries = fit_payload(self.search_topics(query, Local::now().timestamp()));
+                (KademliaResponse::SearchTopics(entries), request)
+            },
+            KademliaRequest::SubmitBid(ref bid) => {
+                let receipt = self.coordinate_bid(bid, &request.src);
+                (KademliaResponse::SubmitBid(receipt), request)
+            },
+            KademliaRequest::UpdateTopic(ref key, ref update) => {
+                let receipt = self.coordinate_update(key, update, &request.src);
+                (KademliaResponse::UpdateTopic(receipt), request)
+            },
+            KademliaRequest::Depart(ref node) => {
+                // only a node itself announces its departure
+                if node.get_addr() == request.src {
+                    self.depart(node);
+                }
+                (KademliaResponse::Ping, request)
+            },
             KademliaRequest::QueryLocalBlockChain => {
                 let blockchain = self.blockchain.lock()
                     .expect("Error setting lock in local blockchain");
@@ -735,6 +1217,7 @@ impl KademliaInstance {
             },
 
             KademliaRequest::NodeJoin(ref node) => {
+                self.coordinator.rejoin(&node.id);
                 let nodes: Vec<Node> = self.find_node(&node.id).iter().map(|nwd| nwd.0.clone()).collect();
                 let mut routingtable = self.routingtable.lock()
                     .expect("Error setting lock in routing table");
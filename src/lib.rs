@@ -5,6 +5,27 @@ pub mod kademlia;
 pub mod blockchain;
 pub mod bootstrap;
 pub mod pubsub;
+pub mod mempool;
+pub mod miner;
+pub mod identity;
+pub mod consensus;
+pub mod explorer;
+pub mod snapshot;
+pub mod merkle;
+pub mod light;
+pub mod finality;
+pub mod auction;
+pub mod sealed;
+pub mod softclose;
+pub mod cancel;
+pub mod accounts;
+pub mod engine;
+pub mod notify;
+pub mod search;
+pub mod history;
+pub mod coordinator;
+pub mod multiunit;
+pub mod reputation;
 
 /**
  * BASED ON: KademliaBriefOverview.pdf 
@@ -32,11 +53,33 @@ pub const NODETIMEOUT: u64 = 1;
 #[cfg(test)]
 mod tests {
     use super::node::{Node, NodeWithDistance, Distance, Key};
-    use super::rpc::{Rpc, KademliaRequest};
+    use super::rpc::{Rpc, KademliaRequest, KademliaResponse, MAX_PAYLOAD, timed_rpc_proc};
     use super::kademlia::{KademliaInstance, RoutingTable, Bucket};
-    use super::blockchain::{Block};
-    use super::bootstrap::{Bootstrap, AppNode, App};
-    use super::pubsub::{PubSubInstance};
+    use super::blockchain::{Block, Blockchain, calc_hash, MAX_FUTURE_SECS};
+    use super::bootstrap::{Bootstrap, AppNode, App, Data};
+    use super::pubsub::{PubSubInstance, PubSubRecord, SUBSCRIPTION_LEASE, PUBSUB_VERSION, MAX_MISSED};
+    use super::mempool::{Mempool, Transaction, SEEN_TTL, tx_hash};
+    use super::miner::Miner;
+    use super::identity::Identity;
+    use super::consensus::{engine_from_genesis, POA_FALLBACK_SECS};
+    use super::merkle::{merkle_root, merkle_path, merkle_mutated, verify_path};
+    use super::light::{LightClient, ProofQuery};
+    use super::finality::Checkpoint;
+    use super::auction::{AuctionKind, AuctionSpec, Bid, local};
+    use super::sealed::{COMMIT_MSG_TYPE, REVEAL_MSG_TYPE, REVEAL_WINDOW, commit_msg, reveal_msg, commitment, sealed_outcome};
+    use super::softclose::{SoftClose, EXTEND_MSG_TYPE, MAX_EXTENSIONS, extend_msg, effective_end};
+    use super::cancel::{CANCEL_MSG_TYPE, cancel_msg};
+    use super::snapshot::LedgerState;
+    use super::accounts::{FAUCET_AMOUNT, MINT_MSG_TYPE, ESCROW_MSG_TYPE, mint_msg, escrow_msg, bid_escrow_msg};
+    use super::consensus::{ConsensusEngine, ProofOfWork, ProofOfAuthority};
+    use super::engine::{AuctionEngine, AuctionPhase};
+    use super::notify::{AuctionEvent, Notification, Inbox, INBOX_SEEN, INBOX_CAPACITY};
+    use super::search::{TopicQuery, keywords};
+    use super::history::{BidStatus, bid_history, bid_status, auction_record};
+    use super::explorer::TxEntry;
+    use super::coordinator::{BidRequest, BidReceipt, TopicUpdate, UpdateReceipt, MAX_TIMEOUTS};
+    use super::multiunit::{Allocation, Pricing, allocation_msg};
+    use super::reputation::{BidPolicy, Reputation, FEEDBACK_MSG_TYPE, feedback_msg};
     use super::aux;
     use super::{N_KBUCKETS, KEY_LEN, NODETIMEOUT};
     use log::{info};
@@ -44,7 +87,15 @@ mod tests {
     use std::thread::{sleep, spawn};
     use std::sync::mpsc::{channel, Sender};
 
-    use base64::{decode};
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use rand::{random_range, random_bool};
+
+    // Data record signed by a fixed test identity
+    fn signed(msg: &str, msg_type: usize) -> String {
+        Data::new(String::from(msg), msg_type, Some(format!("{}", chrono::Local::now() + chrono::Duration::hours(1))))
+            .sign(&Identity::from_seed(&[7; 32]))
+            .to_json()
+    }
 
     #[test]
     fn node_with_dist_test() {
@@ -250,6 +301,531 @@ mod tests {
         println!("res12: {:?}", res12);
     }
 
+    #[test]
+    fn mempool_test() {
+        let mut mempool = Mempool::new();
+        let tx1 = Transaction::new(signed("REGISTER: tx1", 0));
+        let tx2 = Transaction::new(signed("REGISTER: tx2", 0));
+
+        assert!(mempool.add(tx1.clone()));
+        assert!(mempool.add(tx2.clone()));
+        // unsigned transaction is dropped
+        assert!(!mempool.add(Transaction::new(String::from("tx3"))));
+        // duplicate (by hash) is dropped
+        assert!(!mempool.add(tx1.clone()));
+        assert_eq!(mempool.len(), 2);
+
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let block = Block::new(&blockchain.blocks[0], mempool.block_template().unwrap());
+        assert_eq!(block.transactions().len(), 2);
+
+        mempool.prune(&[block]);
+        assert!(mempool.is_empty());
+        assert!(mempool.contains(&tx1.hash));
+        assert_eq!(mempool.block_template(), None);
+
+        // mined hashes expire, a repeated record is accepted again
+        assert!(!mempool.add(tx1.clone()));
+        mempool.expire(chrono::Utc::now().timestamp() + SEEN_TTL);
+        assert!(!mempool.contains(&tx1.hash));
+        assert!(mempool.add(tx1.clone()));
+    }
+
+    #[test]
+    fn submit_transaction_test() {
+        let node1 = Node::new(aux::get_ip().unwrap(), 1360);
+        let node2 = Node::new(aux::get_ip().unwrap(), 1361);
+        let node3 = Node::new(aux::get_ip().unwrap(), 1362);
+
+        let kad3 = KademliaInstance::new(node3.addr.clone(), node3.port, None);
+        let kad2 = KademliaInstance::new(node2.addr.clone(), node2.port, Some(node3.clone()));
+        let kad1 = KademliaInstance::new(node1.addr.clone(), node1.port, Some(node2.clone()));
+
+        // kad1 only knows kad2, kad2 gossips to kad3
+        let tx = Transaction::new(signed("REGISTER: test_tx", 0));
+        assert!(kad1.submit_transaction(tx.clone()));
+        assert!(!kad1.submit_transaction(tx.clone()));
+
+        sleep(Duration::from_secs(NODETIMEOUT));
+
+        assert!(kad2.mempool.lock().unwrap().contains(&tx.hash));
+        assert!(kad3.mempool.lock().unwrap().contains(&tx.hash));
+        assert!(kad3.block_template().unwrap().contains("test_tx"));
+    }
+
+    #[test]
+    fn headers_sync_test() {
+        let node1 = Node::new(aux::get_ip().unwrap(), 1363);
+        let node2 = Node::new(aux::get_ip().unwrap(), 1364);
+
+        let kad1 = KademliaInstance::new(node1.addr.clone(), node1.port, None);
+        let kad2 = KademliaInstance::new(node2.addr.clone(), node2.port, Some(node1.clone()));
+
+        // bodies of these blocks do not fit a single GetBlocks response
+        let mut blockchain = kad1.blockchain.lock().unwrap();
+        for i in 0..3 {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            let data = signed(&format!("REGISTER: block {} {}", i, "x".repeat(MAX_PAYLOAD / 3)), 0);
+            assert!(blockchain.add_block(Block::new(&prev, data)));
+        }
+        let locator = blockchain.locator();
+        let hashes: Vec<String> = blockchain.blocks.iter().skip(1).map(|block| block.hash.clone()).collect();
+        drop(blockchain);
+        assert_eq!(kad2.query_blocks(node1.clone(), hashes).unwrap().len(), 2);
+
+        // kad2 only has genesis, common ancestor is found from locator
+        let headers = kad2.query_headers(node1.clone(), vec![locator.last().unwrap().clone()]).unwrap();
+        assert_eq!(headers.len(), 3);
+
+        let synced = kad2.sync_from(node1.clone());
+        assert_eq!(synced.len(), 3);
+        assert!(kad2.sync_from(node1.clone()).is_empty());
+
+        // announce new block, kad2 fetches it from kad1
+        let mut blockchain = kad1.blockchain.lock().unwrap();
+        let prev = blockchain.blocks.last().unwrap().clone();
+        let block = Block::new(&prev, signed("REGISTER: block 3", 0));
+        blockchain.add_block(block.clone());
+        drop(blockchain);
+        kad1.announce_block(block.header(), None);
+
+        sleep(Duration::from_secs(NODETIMEOUT));
+
+        let blockchain = kad2.blockchain.lock().unwrap();
+        assert_eq!(blockchain.blocks.len(), 5);
+        assert_eq!(blockchain.blocks.last().unwrap().hash, block.hash);
+    }
+
+    #[test]
+    fn chain_tip_sync_test() {
+        let node1 = Node::new(aux::get_ip().unwrap(), 1371);
+        let node2 = Node::new(aux::get_ip().unwrap(), 1372);
+
+        let kad1 = KademliaInstance::new(node1.addr.clone(), node1.port, None);
+        let kad2 = KademliaInstance::new(node2.addr.clone(), node2.port, Some(node1.clone()));
+
+        let mut blockchain = kad1.blockchain.lock().unwrap();
+        for i in 0..3 {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, signed(&format!("REGISTER: tip {}", i), 0)));
+        }
+        let tip = blockchain.tip();
+        assert_eq!((tip.height, tip.work), (3, 4));
+        assert_eq!(tip.hash, blockchain.blocks.last().unwrap().hash);
+        blockchain.remove_last_block();
+        assert_eq!(blockchain.tip().work, 3);
+        let prev = blockchain.blocks.last().unwrap().clone();
+        blockchain.add_block(Block::new(&prev, signed("REGISTER: tip 3", 0)));
+        let tip = blockchain.tip();
+        drop(blockchain);
+
+        // only the divergent suffix is fetched, equal tips transfer nothing
+        assert_eq!(kad2.query_tip(node1.clone()), Some(tip.clone()));
+        assert_eq!(kad2.sync_tip(node1.clone()).len(), 3);
+        assert_eq!(kad2.blockchain.lock().unwrap().tip(), tip);
+        assert!(kad2.sync_tip(node1.clone()).is_empty());
+
+        // remote tip with less work is ignored
+        let mut blockchain = kad2.blockchain.lock().unwrap();
+        let prev = blockchain.blocks.last().unwrap().clone();
+        blockchain.add_block(Block::new(&prev, signed("REGISTER: tip 4", 0)));
+        drop(blockchain);
+        assert!(kad2.sync_tip(node1.clone()).is_empty());
+        assert_eq!(kad1.sync_tip(node2.clone()).len(), 1);
+    }
+
+    #[test]
+    fn merkle_test() {
+        let txs: Vec<String> = (0..5).map(|i| format!("tx {}", i)).collect();
+        let root = merkle_root(&txs);
+        for (index, tx) in txs.iter().enumerate() {
+            let path = merkle_path(&txs, index).unwrap();
+            assert_eq!(path.len(), 3);
+            assert!(verify_path(tx, &path, &root));
+            assert!(!verify_path("forged", &path, &root));
+        }
+        assert!(merkle_path(&txs, 5).is_none());
+        assert_ne!(merkle_root(&txs[..1]), tx_hash("tx 0"));
+
+        // duplicated last tx shares the root of the odd list, but is mutated
+        let duplicated = vec![txs[0].clone(), txs[1].clone(), txs[2].clone(), txs[2].clone()];
+        assert_eq!(merkle_root(&duplicated), merkle_root(&txs[..3]));
+        assert!(!merkle_mutated(&txs));
+        assert!(merkle_mutated(&duplicated));
+
+        // leaf is not taken for an internal node
+        let root2 = merkle_root(&txs[..2]);
+        assert!(!verify_path(&root2, &Vec::new(), &root2));
+    }
+
+    #[test]
+    fn light_client_test() {
+        let node1 = Node::new(aux::get_ip().unwrap(), 1367);
+        let node2 = Node::new(aux::get_ip().unwrap(), 1368);
+
+        let kad1 = KademliaInstance::new(node1.addr.clone(), node1.port, None);
+        let light = LightClient::new(KademliaInstance::new(node2.addr.clone(), node2.port, Some(node1.clone())));
+
+        let publish = signed("PUB_TOPIC: car|PUBLISHER: 127.0.0.1:1", 1);
+        let register = signed("REGISTER: 127.0.0.1:1", 0);
+        let mut blockchain = kad1.blockchain.lock().unwrap();
+        for data in [register.clone(), serde_json::to_string(&vec![register, publish.clone()]).unwrap()] {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data));
+        }
+        drop(blockchain);
+
+        // headers only (genesis body selects engine)
+        assert_eq!(light.sync(node1.clone()), 2);
+        assert_eq!(light.tip().unwrap().id, 2);
+        assert_eq!(light.sync(node1.clone()), 0);
+
+        let proofs = light.query_proofs(node1.clone(), ProofQuery::Topic(String::from("car"), (0, 0)));
+        assert_eq!(proofs.len(), 1);
+        assert_eq!(proofs[0].tx, publish);
+        assert!(light.query_proofs(node1.clone(), ProofQuery::Topic(String::from("car"), (2, 1))).is_empty());
+        assert_eq!(light.pull_proofs(node1.clone(), ProofQuery::Address(String::from("127.0.0.1:1"), (0, 0))).len(), 3);
+        assert_eq!(ProofQuery::Address(String::from("127.0.0.1:1"), (2, 1)).after((2, 1)), None);
+        assert_eq!(ProofQuery::Tx(tx_hash(&publish)).after((2, 1)), None);
+        let proofs = light.query_proofs(node1.clone(), ProofQuery::Tx(tx_hash(&publish)));
+        assert_eq!(proofs.len(), 1);
+        let mut cursor = (0, 0);
+        assert_eq!(light.pull_txs(node1.clone(), 0, &mut cursor).len(), 2);
+        assert_eq!(cursor, (2, 0));
+
+        // tampered proof is rejected
+        let mut forged = proofs[0].clone();
+        forged.tx = String::from("forged");
+        assert!(!light.chain.lock().unwrap().verify_proof(&forged));
+
+        // block mined on tip header is accepted by full node
+        let block = light.mine_next(signed("REGISTER: light block", 0)).unwrap();
+        assert!(kad1.blockchain.lock().unwrap().add_block(block));
+        assert_eq!(light.sync(node1.clone()), 1);
+    }
+
+    #[test]
+    fn apply_blocks_test() {
+        let mut local = Blockchain::new();
+        local.genesis();
+        let mut remote = local.clone();
+
+        let genesis = local.blocks[0].clone();
+        local.add_block(Block::new(&genesis, signed("REGISTER: local", 0)));
+        for i in 0..2 {
+            let prev = remote.blocks.last().unwrap().clone();
+            remote.add_block(Block::new(&prev, signed(&format!("REGISTER: remote {}", i), 0)));
+        }
+
+        // fork at genesis, remote suffix is longer (reorg)
+        let headers = remote.headers_after(&local.locator());
+        assert_eq!(headers.len(), 2);
+        let hashes: Vec<String> = headers.iter().map(|h| h.hash.clone()).collect();
+        let applied = local.apply_blocks(remote.get_blocks(&hashes));
+        assert_eq!(applied.len(), 2);
+        assert_eq!(local.string(), remote.string());
+
+        // shorter suffix is rejected
+        let fork = Block::new(&genesis, signed("REGISTER: fork", 0));
+        assert!(local.apply_blocks(vec![fork]).is_empty());
+    }
+
+    #[test]
+    fn miner_test() {
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let genesis = blockchain.blocks[0].clone();
+
+        let miner = Miner::new(2);
+        let block = miner.mine(&genesis.header(), signed("REGISTER: miner test", 0)).unwrap();
+        assert!(blockchain.add_block(block));
+        assert!(!miner.is_mining());
+        assert!(miner.hash_rate() > 0.0);
+
+        // stale job (competing block at same height) is aborted, the next height is mined
+        miner.abort(1);
+        assert!(miner.mine(&genesis.header(), String::from("competing")).is_none());
+        assert!(!miner.is_mining());
+        let prev = blockchain.blocks.last().unwrap().clone();
+        assert!(miner.mine(&prev.header(), signed("REGISTER: miner test 2", 0)).is_some());
+    }
+
+    #[test]
+    fn consensus_test() {
+        let miner = Miner::new(1);
+        let validators = vec![Identity::from_seed(&[1; 32]), Identity::from_seed(&[2; 32])];
+        let keys: Vec<String> = validators.iter().map(|v| v.public_key()).collect();
+
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis_poa(keys);
+        assert_eq!(blockchain.consensus.name(), "poa");
+        assert_eq!(engine_from_genesis(&blockchain.blocks[0]).name(), "poa");
+
+        // round-robin: block 1 sealed by validators[1] (resealed a minute ago)
+        let reseal = |block: Block, timestamp: i64, validator: &Identity| {
+            let hash = hex::encode(calc_hash(block.id, timestamp, &block.prev_hash, &block.merkle_root, block.nonce));
+            Block { timestamp, signature: validator.sign(hash.as_bytes()), hash, ..block }
+        };
+        let genesis = blockchain.blocks[0].clone();
+        let consensus = blockchain.consensus.clone();
+        assert!(consensus.in_turn(&validators[1], 1) && !consensus.in_turn(&validators[0], 1));
+        assert!(consensus.seal(&miner, &Identity::from_seed(&[3; 32]), &genesis.header(), signed("REGISTER: poa", 0)).is_none());
+        let block = consensus.seal(&miner, &validators[1], &genesis.header(), signed("REGISTER: poa", 0)).unwrap();
+        let block = reseal(block.clone(), block.timestamp - 60, &validators[1]);
+        assert!(consensus.verify_seal(&block.header()));
+
+        // tampered signature is rejected
+        let mut forged = block.clone();
+        forged.signature = Identity::from_seed(&[3; 32]).sign(forged.hash.as_bytes());
+        assert!(!blockchain.add_block(forged));
+
+        // timestamp after its parent and not ahead of our clock
+        assert!(!blockchain.add_block(reseal(block.clone(), genesis.timestamp, &validators[1])));
+        assert!(!blockchain.add_block(reseal(block.clone(), chrono::Utc::now().timestamp() + MAX_FUTURE_SECS + 5, &validators[1])));
+        assert!(blockchain.add_block(block.clone()));
+
+        // out of turn only after the fallback delay, the in-turn fork weighs more
+        let template = signed("REGISTER: poa 2", 0);
+        let late = consensus.seal(&miner, &validators[1], &block.header(), template.clone()).unwrap();
+        let early = reseal(late.clone(), block.timestamp + POA_FALLBACK_SECS - 1, &validators[1]);
+        let mut fork = blockchain.clone();
+        assert!(!fork.add_block(early));
+        assert!(fork.add_block(late));
+        let in_turn = consensus.seal(&miner, &validators[0], &block.header(), template).unwrap();
+        assert!(blockchain.add_block(in_turn.clone()));
+        assert!(fork.apply_blocks(vec![in_turn.clone()]).len() == 1);
+        assert_eq!(fork.tip(), blockchain.tip());
+
+        // forward-dating a block (within the clock skew) does not skip the schedule
+        let out_of_turn = consensus.seal(&miner, &validators[0], &in_turn.header(), signed("REGISTER: poa 3", 0)).unwrap();
+        let forward = reseal(out_of_turn, in_turn.timestamp + MAX_FUTURE_SECS, &validators[0]);
+        assert!(consensus.verify_seal(&forward.header()) && !consensus.verify_turn(&forward.header(), &in_turn.header()));
+        assert!(!blockchain.clone().add_block(forward));
+
+        // node with default (pow) genesis only adopts the pinned (poa) network genesis
+        let mut local = Blockchain::new();
+        local.genesis();
+        assert_eq!(local.consensus.name(), "pow");
+        assert!(local.apply_blocks(blockchain.blocks.clone()).is_empty());
+        local.genesis_pin = Some(genesis.hash.clone());
+        assert_eq!(local.apply_blocks(blockchain.blocks.clone()).len(), 3);
+        assert_eq!(local.consensus.name(), "poa");
+        assert_eq!(local.string(), blockchain.string());
+    }
+
+    #[test]
+    fn explorer_test() {
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+
+        let other = Identity::from_seed(&[8; 32]);
+        let register = signed("REGISTER: 127.0.0.1:1", 0);
+        let bidder = Data::new(String::from("REGISTER: 127.0.0.1:2"), 0, None).sign(&other).to_json();
+        let publish = signed("PUB_TOPIC: car|PUBLISHER: 127.0.0.1:1", 1);
+        let escrow = Data::new(escrow_msg("car", "127.0.0.1:2", 100), ESCROW_MSG_TYPE, None).sign(&other).to_json();
+        let end = signed("END_TOPIC: car|BID: 100|BIDDER: \"127.0.0.1:2\"", 2);
+
+        let genesis = blockchain.blocks[0].clone();
+        let block1 = Block::new(&genesis, serde_json::to_string(&vec![register, bidder, publish, escrow]).unwrap());
+        assert!(blockchain.add_block(block1.clone()));
+        let block2 = Block::new(&block1, end);
+        assert!(blockchain.add_block(block2.clone()));
+
+        assert_eq!(blockchain.block_by_id(1).unwrap().hash, block1.hash);
+        assert_eq!(blockchain.block_by_hash(&block2.hash).unwrap().id, 2);
+        assert_eq!(blockchain.txs_by_type(0).len(), 2);
+        assert_eq!(blockchain.topic_events("car").len(), 3);
+        assert_eq!(blockchain.address_activity("127.0.0.1:1").len(), 2);
+        assert_eq!(blockchain.address_activity("127.0.0.1:2").last().unwrap().block_id, 2);
+
+        let page: Vec<u64> = blockchain.blocks_page(0, 2).iter().map(|b| b.id).collect();
+        assert_eq!(page, vec![2, 1]);
+        assert_eq!(blockchain.blocks_page(1, 2).len(), 1);
+
+        // rollback, index is updated
+        blockchain.remove_last_block();
+        assert!(blockchain.block_by_hash(&block2.hash).is_none());
+        assert_eq!(blockchain.topic_events("car").len(), 2);
+        assert_eq!(blockchain.address_activity("127.0.0.1:2").len(), 2);
+    }
+
+    #[test]
+    fn snapshot_test() {
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let signer = Identity::from_seed(&[7; 32]);
+        blockchain.finality.signers = vec![signer.public_key()];
+
+        let bidder = Identity::from_seed(&[8; 32]);
+        let register = Data::new(String::from("REGISTER: 127.0.0.1:2"), 0, None).sign(&bidder).to_json();
+        let escrow = Data::new(escrow_msg("car", "127.0.0.1:2", 100), ESCROW_MSG_TYPE, None).sign(&bidder).to_json();
+        let txs = vec![
+            serde_json::to_string(&vec![signed("REGISTER: 127.0.0.1:1", 0), register]).unwrap(),
+            signed("PUB_TOPIC: car|PUBLISHER: 127.0.0.1:1", 1),
+            serde_json::to_string(&vec![signed("PUB_TOPIC: bike|PUBLISHER: 127.0.0.1:1", 1), escrow]).unwrap(),
+            signed("END_TOPIC: car|BID: 100|BIDDER: 127.0.0.1:2", 2),
+        ];
+        for tx in txs {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            assert!(blockchain.add_block(Block::new(&prev, tx)));
+        }
+
+        // snapshot of tip (height 4) committed in block 5
+        assert!(blockchain.snapshot_due(10).is_none());
+        let snapshot = blockchain.snapshot_due(4).unwrap();
+        assert_eq!(snapshot.height, 4);
+        assert!(snapshot.state.registered.contains_key("127.0.0.1:1"));
+        assert_eq!(snapshot.state.auctions.keys().collect::<Vec<_>>(), vec!["bike"]);
+        let tip = blockchain.blocks.last().unwrap().clone();
+        assert!(!blockchain.add_block(Block::new(&tip, snapshot.commitment(&Identity::from_seed(&[8; 32])))));
+        let commitment = snapshot.commitment(&signer);
+        assert!(blockchain.add_block(Block::new(&tip, commitment.clone())));
+        assert!(blockchain.snapshot_due(4).is_none());
+
+        assert_eq!(blockchain.prune(), 4);
+        assert_eq!(blockchain.snapshot, Some(snapshot.clone()));
+        assert!(blockchain.blocks[1..=4].iter().all(|block| block.pruned && block.data.is_empty()));
+        assert!(blockchain.topic_events("car").is_empty());
+        assert_eq!(blockchain.prune(), 0);
+
+        assert_eq!(blockchain.snapshot_commitment(), Some(commitment.clone()));
+
+        // pruned flag is not taken from the wire
+        let received: Vec<Block> = blockchain.blocks[1..].iter()
+            .map(|block| serde_json::from_str(&serde_json::to_string(block).unwrap()).unwrap())
+            .collect();
+        assert!(received.iter().all(|block| !block.pruned));
+
+        // new node takes pruned bodies only below a snapshot committed by a signer
+        let mut local = Blockchain::new();
+        local.genesis();
+        local.finality.signers = vec![signer.public_key()];
+        assert!(local.apply_blocks(received.clone()).is_empty());
+        let mut forged = snapshot.clone();
+        forged.state.registered.insert(String::from("127.0.0.1:3"), String::new());
+        assert!(!local.install_snapshot(forged.clone(), &commitment));
+        assert!(!local.install_snapshot(snapshot.clone(), &snapshot.commitment(&Identity::from_seed(&[8; 32]))));
+        assert!(local.snapshot.is_none());
+        assert!(local.install_snapshot(snapshot.clone(), &commitment));
+        assert_eq!(local.apply_blocks(received).len(), 5);
+        assert!(local.verify_snapshot(&snapshot));
+        assert!(!local.verify_snapshot(&forged));
+    }
+
+    #[test]
+    fn signed_tx_test() {
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let other = Identity::from_seed(&[8; 32]);
+        let add = |blockchain: &mut Blockchain, data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+
+        // unsigned and forged (author replaced) transactions are rejected
+        assert!(!add(&mut blockchain, Data::new(String::from("REGISTER: 127.0.0.1:1"), 0, None).to_json()));
+        let mut forged: serde_json::Value = serde_json::from_str(&signed("REGISTER: 127.0.0.1:1", 0)).unwrap();
+        forged["author"] = serde_json::json!(other.public_key());
+        assert!(!add(&mut blockchain, forged.to_string()));
+
+        // address is bound to the first registering key
+        assert!(add(&mut blockchain, signed("REGISTER: 127.0.0.1:1", 0)));
+        let register = Data::new(String::from("REGISTER: 127.0.0.1:1"), 0, None).sign(&other).to_json();
+        assert!(!add(&mut blockchain, register));
+
+        // only the registered key publishes for an address, only the publisher ends a topic
+        let publish = Data::new(String::from("PUB_TOPIC: car|PUBLISHER: 127.0.0.1:1"), 1, None).sign(&other).to_json();
+        assert!(!add(&mut blockchain, publish));
+        assert!(add(&mut blockchain, signed("PUB_TOPIC: car|PUBLISHER: 127.0.0.1:1", 1)));
+        let end = Data::new(String::from("END_TOPIC: car|BID: 100|BIDDER: 127.0.0.1:2"), 2, None).sign(&other).to_json();
+        assert!(!add(&mut blockchain, end));
+        // END_TOPIC must record the outcome of the escrowed bids (none)
+        assert!(!add(&mut blockchain, signed("END_TOPIC: car|BID: 100|BIDDER: 127.0.0.1:2", 2)));
+        assert!(add(&mut blockchain, signed("END_TOPIC: car|BID: null|BIDDER: null", 2)));
+        assert_eq!(blockchain.blocks.len(), 4);
+    }
+
+    #[test]
+    fn finality_test() {
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let mut fork = blockchain.clone();
+        let boot = Identity::from_seed(&[9; 32]);
+        blockchain.finality.confirmations = 2;
+        blockchain.finality.signers = vec![boot.public_key()];
+        for i in 0..4 {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, signed(&format!("REGISTER: final {}", i), 0)));
+        }
+        assert_eq!(blockchain.finalized_height(), 2);
+        let block2 = blockchain.blocks[2].clone();
+        let block3 = blockchain.blocks[3].clone();
+        assert!(blockchain.is_final(2, &block2.hash));
+        assert!(!blockchain.is_final(3, &block3.hash));
+
+        // checkpoint of an untrusted signer or an unknown block is rejected
+        let checkpoint = blockchain.checkpoint_due(&boot).unwrap();
+        assert_eq!(checkpoint.height, 2);
+        assert!(!blockchain.add_checkpoint(Checkpoint::new(2, block2.hash.clone(), &Identity::from_seed(&[8; 32]))));
+        assert!(!blockchain.add_checkpoint(Checkpoint::new(2, String::from("unknown"), &boot)));
+        assert!(blockchain.add_checkpoint(checkpoint));
+        assert!(blockchain.checkpoint_due(&boot).is_none());
+
+        // longer fork dropping the checkpointed block is rejected
+        for i in 0..6 {
+            let prev = fork.blocks.last().unwrap().clone();
+            fork.add_block(Block::new(&prev, signed(&format!("REGISTER: fork {}", i), 0)));
+        }
+        assert!(blockchain.apply_blocks(fork.blocks[1..].to_vec()).is_empty());
+        assert_eq!(blockchain.blocks[3].hash, block3.hash);
+
+        // checkpointed height stays final for a deeper confirmation depth
+        blockchain.finality.confirmations = 10;
+        assert_eq!(blockchain.finalized_height(), 2);
+    }
+
+    #[test]
+    fn poa_network_test() {
+        let boot = Bootstrap::new_poa();
+        Bootstrap::full_bk_sync(boot.clone());
+
+        // REGISTER blocks of bootstrap nodes sealed by validators
+        let bootchain = boot.nodes[0].kademlia.blockchain.lock().unwrap().clone();
+        assert_eq!(bootchain.consensus.name(), "poa");
+        assert_eq!(bootchain.blocks[1].transactions().len(), 4);
+
+        let appnode0 = App::new(aux::get_ip().unwrap(), 1365, boot.nodes[0].node.clone());
+        let appnode1 = App::new(aux::get_ip().unwrap(), 1366, boot.nodes[1].node.clone());
+        assert!(appnode0.publish(String::from("poa")));
+
+        sleep(Duration::from_secs(NODETIMEOUT * 6));
+        let topics = appnode1.topics.lock().unwrap().clone();
+        assert!(topics.iter().any(|(topic, _, _)| topic == "poa"));
+        let appchain = appnode1.appnode.kademlia.blockchain.lock().unwrap().clone();
+        assert_eq!(appchain.consensus.name(), "poa");
+    }
+
+    #[test]
+    fn light_app_test() {
+        let boot = Bootstrap::new();
+        Bootstrap::full_bk_sync(boot.clone());
+
+        let appnode0 = App::with_signers(aux::get_ip().unwrap(), 1369, boot.nodes[0].node.clone(), boot.signers(), false);
+        let appnode1 = App::with_signers(aux::get_ip().unwrap(), 1370, boot.nodes[1].node.clone(), boot.signers(), true);
+        assert!(appnode0.publish(String::from("light")));
+        assert!(appnode1.publish(String::from("light_pub")));
+
+        sleep(Duration::from_secs(NODETIMEOUT * 6));
+        let topics = appnode1.topics.lock().unwrap().clone();
+        assert!(topics.iter().any(|(topic, _, _)| topic == "light"));
+        let topics = appnode0.topics.lock().unwrap().clone();
+        assert!(topics.iter().any(|(topic, _, _)| topic == "light_pub"));
+
+        // light app holds no bodies
+        assert_eq!(appnode1.appnode.kademlia.blockchain.lock().unwrap().blocks.len(), 1);
+    }
+
     // NOTE: appnode and join network (bootnode0) have the same global (updated) blockchain,
     //       while bootnode1/2/3 still have the local chain.
     //
@@ -314,6 +890,1049 @@ mod tests {
         // boot.nodes[2].kademlia.print_blockchain();
     }
 
+    #[test]
+    fn auction_kind_test() {
+        let bid = |data: usize, addr: &str| serde_json::json!({"data": data, "sender_addr": addr}).to_string();
+        let auction = |kind: AuctionKind| {
+            let mut pubsub = PubSubInstance::new(None, String::from("127.0.0.1:1"), None, None);
+            pubsub.set_ttl(chrono::Local::now() + chrono::Duration::minutes(1));
+            pubsub.set_kind(kind);
+            pubsub
+        };
+        let close = |pubsub: &mut PubSubInstance| pubsub.set_ttl(chrono::Local::now() - chrono::Duration::minutes(1));
+
+        // english: reserve and minimum increment
+        let mut english = auction(AuctionKind::English { reserve: 50, increment: 10 });
+        assert_eq!(english.add_msg(bid(40, "a"), chrono::Local::now()), 1);
+        assert_eq!(english.add_msg(bid(50, "a"), chrono::Local::now()), 0);
+        assert_eq!(english.add_msg(bid(55, "b"), chrono::Local::now()), 1);
+        assert_eq!(english.add_msg(bid(60, "b"), chrono::Local::now()), 0);
+        assert_eq!(english.as_json()["winner"], serde_json::Value::Null);
+        close(&mut english);
+        assert_eq!(english.as_json()["price"], 60);
+        assert_eq!(english.as_json()["winner"], "b");
+
+        // dutch: first bid at or above the clock (when it was made) wins
+        let opened = chrono::Local::now() - chrono::Duration::seconds(30);
+        let dutch = auction(AuctionKind::Dutch { start: 100, floor: 20, decrement: 1, opened: opened.timestamp() });
+        assert!(dutch.as_json()["clock_price"].as_u64().unwrap() <= 70);
+        assert_eq!(dutch.add_msg(bid(75, "a"), opened), 1);
+        assert_eq!(dutch.add_msg(bid(60, "a"), chrono::Local::now()), 1);
+        assert_eq!(dutch.add_msg(bid(75, "a"), chrono::Local::now()), 0);
+        assert_eq!(dutch.add_msg(bid(90, "b"), chrono::Local::now()), 1);
+
+        // sealed: no cleartext bids, one bid per bidder (see sealed_bid_test)
+        let first = AuctionKind::FirstPrice { reserve: 10 };
+        let second = AuctionKind::SecondPrice { reserve: 10 };
+        assert_eq!(auction(first.clone()).add_msg(bid(80, "a"), chrono::Local::now()), 1);
+        let mut bids: Vec<Bid> = Vec::new();
+        for (data, addr) in [(80, "a"), (100, "b"), (30, "c"), (120, "a"), (5, "d")] {
+            let bid = Bid::new(data, String::from(addr));
+            if second.verify_bid(&bids, &bid, chrono::Local::now()) {
+                bids.push(bid);
+            }
+        }
+        assert_eq!(bids.len(), 3);
+        assert_eq!(first.outcome(&bids), Some((100, String::from("b"))));
+        assert_eq!(second.outcome(&bids), Some((80, String::from("b"))));
+
+        // kind survives the DHT encoding
+        let appnode = AppNode::new(aux::get_ip().unwrap(), 1373, None);
+        appnode.publish_auction(String::from("vickrey"), AuctionSpec::lasting("vickrey", 1), AuctionKind::SecondPrice { reserve: 10 }, None);
+        assert_eq!(appnode.get_pubsub_json(String::from("vickrey"))["kind"], serde_json::json!(AuctionKind::SecondPrice { reserve: 10 }));
+    }
+
+    #[test]
+    fn sealed_bid_test() {
+        let identity = Identity::from_seed(&[7; 32]);
+        let kind = AuctionKind::SecondPrice { reserve: 10 };
+        let deadline = chrono::Local::now() + chrono::Duration::hours(1);
+        let publish = Data::new(format!("PUB_TOPIC: car|PUBLISHER: 127.0.0.1:1|KIND: {}", kind.to_json()), 1, Some(format!("{}", deadline)))
+            .sign(&identity)
+            .to_json();
+        let commit = |bidder: &str, bid: usize, salt: &str| {
+            Data::new(commit_msg("car", bidder, &commitment("car", bid, salt, bidder)), COMMIT_MSG_TYPE, None).sign(&identity).to_json()
+        };
+        let reveal = |bidder: &str, bid: usize, salt: &str| {
+            Data::new(reveal_msg("car", bidder, bid, salt), REVEAL_MSG_TYPE, None).sign(&identity).to_json()
+        };
+
+        // commits by the deadline, reveals within the window
+        let open = deadline.timestamp() - 10;
+        let window = deadline.timestamp() + 10;
+        let events = vec![
+            (open - 10, publish.clone()),
+            (open, commit("a", 80, "s1")),
+            (open, commit("b", 100, "s2")),
+            (open, commit("c", 30, "s3")),
+            (open, commit("e", 120, "s5")),
+            (window, commit("d", 500, "s4")),
+            (open, reveal("a", 80, "s1")),
+            (window, reveal("b", 100, "wrong")),
+            (window, reveal("b", 100, "s2")),
+            (window, reveal("a", 80, "s1")),
+            (deadline.timestamp() + REVEAL_WINDOW + 1, reveal("e", 120, "s5")),
+        ];
+        assert_eq!(sealed_outcome("bike", &events), None);
+        let outcome = sealed_outcome("car", &events).unwrap();
+        assert_eq!(outcome.revealed.len(), 2);
+        assert_eq!(outcome.winner, Some((80, String::from("b"))));
+        assert_eq!(outcome.forfeited, vec![(String::from("c"), 10), (String::from("e"), 10)]);
+
+        // commits are signed by the bidder key, on a published topic
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let mut add = |data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        assert!(add(signed("REGISTER: 127.0.0.1:1", 0)));
+        assert!(!add(commit("127.0.0.1:1", 50, "salt")));
+        assert!(add(publish));
+        assert!(!add(commit("127.0.0.1:2", 50, "salt")));
+        assert!(add(commit("127.0.0.1:1", 50, "salt")));
+        let outcome = blockchain.sealed_outcome("car").unwrap();
+        assert_eq!(outcome.forfeited, vec![(String::from("127.0.0.1:1"), 10)]);
+        assert_eq!(outcome.winner, None);
+        assert_eq!(blockchain.balance("127.0.0.1:1"), Some(FAUCET_AMOUNT - 10));
+
+        // commits lock the reserve, reveals the bid, unrevealed deposits go to the seller
+        let policy = blockchain.mint_policy();
+        let mut state = LedgerState::default();
+        for (seed, addr) in [(7, "127.0.0.1:1"), (1, "a"), (2, "b"), (3, "c")] {
+            let register = Data::new(format!("REGISTER: {}", addr), 0, None).sign(&Identity::from_seed(&[seed; 32])).to_json();
+            assert!(state.apply_tx(open - 20, &register, &policy));
+        }
+        for (timestamp, tx) in &events[..4] {
+            assert!(state.apply_tx(*timestamp, tx, &policy));
+        }
+        assert!(state.apply_tx(window, &reveal("b", 100, "s2"), &policy));
+        assert!(state.apply_tx(window, &reveal("a", 80, "s1"), &policy));
+        let balances = |state: &LedgerState| ["127.0.0.1:1", "a", "b", "c"].map(|addr| state.balance(addr));
+        assert_eq!(balances(&state), [1000, 920, 900, 990]);
+        let end = Data::new(String::from("END_TOPIC: car|BID: 80|BIDDER: b"), 2, None).sign(&identity).to_json();
+        assert!(!state.apply_tx(window, &end, &policy));
+        assert!(state.apply_tx(deadline.timestamp() + REVEAL_WINDOW + 1, &end, &policy));
+        assert_eq!(balances(&state), [1090, 1000, 920, 990]);
+    }
+
+    #[test]
+    fn soft_close_test() {
+        let identity = Identity::from_seed(&[7; 32]);
+        let rule = SoftClose::new(30, 60).unwrap();
+        let deadline = chrono::Local::now() + chrono::Duration::hours(1);
+        let publish = |rule: Option<SoftClose>| {
+            let mut msg = format!("PUB_TOPIC: car|PUBLISHER: 127.0.0.1:1|KIND: {}", AuctionKind::default().to_json());
+            if let Some(rule) = rule {
+                msg.push_str(&format!("|SOFT_CLOSE: {}", rule.to_json()));
+            }
+            Data::new(msg, 1, Some(format!("{}", deadline))).sign(&identity).to_json()
+        };
+        let extend = |bidder: &str| Data::new(extend_msg("car", bidder), EXTEND_MSG_TYPE, None).sign(&identity).to_json();
+        let escrow = |bidder: &str, amount: u64| Data::new(escrow_msg("car", bidder, amount), ESCROW_MSG_TYPE, None).to_json();
+        assert!(SoftClose::new(0, 60).is_none());
+        assert!(SoftClose::from_json("{\"window\":30,\"extension\":-1}").is_none());
+
+        // escrows in the window extend the end (once), the window follows the extended end
+        let end = deadline.timestamp();
+        let events = vec![
+            (end - 3600, publish(Some(rule))),
+            (end - 120, escrow("a", 10)),
+            (end - 119, extend("a")),
+            (end - 10, escrow("b", 20)),
+            (end - 9, extend("b")),
+            (end + 50, escrow("a", 30)),
+            (end + 61, extend("a")),
+            (end + 62, extend("a")),
+            (end + 200, extend("b")),
+        ];
+        assert_eq!(effective_end("bike", &events), None);
+        assert_eq!(effective_end("car", &events).unwrap().timestamp(), end + 120);
+        let mut fixed = events.clone();
+        fixed[0] = (end - 3600, publish(None));
+        assert_eq!(effective_end("car", &fixed).unwrap().timestamp(), end);
+
+        // bidding wars end after MAX_EXTENSIONS
+        let mut engine = AuctionEngine::replay(&events[..1]);
+        for amount in 1..=MAX_EXTENSIONS as u64 + 2 {
+            let late = engine.auction("car").unwrap().end - 1;
+            assert!(engine.apply(late, &escrow("a", amount)));
+            assert_eq!(engine.apply(late, &extend("a")), amount <= MAX_EXTENSIONS as u64);
+        }
+        assert_eq!(engine.auction("car").unwrap().end, end + MAX_EXTENSIONS as i64 * 60);
+
+        // extensions are signed by the bidder key, on a published topic
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let mut add = |data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        assert!(add(signed("REGISTER: 127.0.0.1:1", 0)));
+        assert!(!add(extend("127.0.0.1:1")));
+        assert!(add(publish(Some(rule))));
+        assert!(!add(extend("127.0.0.1:2")));
+        assert!(!add(extend("127.0.0.1:1")));
+        assert_eq!(blockchain.effective_end("car").unwrap().timestamp(), end);
+
+        // rule survives the DHT encoding, ttl is only ever extended (to the second)
+        let appnode = AppNode::new(aux::get_ip().unwrap(), 1374, None);
+        appnode.publish_auction(String::from("car"), AuctionSpec::new("car", deadline), AuctionKind::default(), Some(rule));
+        assert_eq!(appnode.get_pubsub_json(String::from("car"))["soft_close"], serde_json::json!(rule));
+        assert!(!appnode.set_ttl(String::from("car"), deadline - chrono::Duration::minutes(1)));
+        assert!(appnode.set_ttl(String::from("car"), rule.extend(deadline)));
+        assert_eq!(appnode.get_pubsub_json(String::from("car"))["ttl"], format!("{}", local(rule.extend(deadline).timestamp())));
+    }
+
+    #[test]
+    fn auction_spec_test() {
+        let bid = |data: usize, addr: &str| serde_json::json!({"data": data, "sender_addr": addr}).to_string();
+        let mut spec = AuctionSpec::lasting("Bike", 1);
+        spec.description = String::from("Road bike; size M | barely used");
+        spec.starting_price = 100;
+        spec.reserve = 150;
+        spec.min_increment = 20;
+        spec.image_hash = Some(String::from("ab12"));
+        assert!(spec.valid());
+        assert_eq!(AuctionSpec::decode(&spec.encode()), Some(spec.clone()));
+        assert!(!spec.encode().contains(['|', ';', ' ']));
+
+        // spec prices the kind
+        assert_eq!(spec.price(AuctionKind::default()), AuctionKind::English { reserve: 100, increment: 20 });
+        assert_eq!(spec.price(AuctionKind::dutch(500, 0, 2)), AuctionKind::Dutch { start: 150, floor: 150, decrement: 2, opened: spec.start_time });
+        assert_eq!(spec.price(AuctionKind::SecondPrice { reserve: 0 }), AuctionKind::SecondPrice { reserve: 150 });
+
+        // starting price, increment and reserve (item unsold below it)
+        let mut pubsub = PubSubInstance::new(None, String::from("127.0.0.1:1"), None, None);
+        pubsub.set_ttl(spec.end());
+        pubsub.set_kind(spec.price(AuctionKind::default()));
+        pubsub.set_spec(Some(spec.clone()));
+        assert_eq!(pubsub.add_msg(bid(90, "a"), chrono::Local::now()), 1);
+        assert_eq!(pubsub.add_msg(bid(100, "a"), chrono::Local::now()), 0);
+        assert_eq!(pubsub.add_msg(bid(110, "b"), chrono::Local::now()), 1);
+        assert_eq!(pubsub.add_msg(bid(120, "b"), chrono::Local::now()), 0);
+        pubsub.set_ttl(chrono::Local::now() - chrono::Duration::minutes(1));
+        assert_eq!(pubsub.as_json()["winner"], serde_json::Value::Null);
+        assert_eq!(spec.sold(Some((150, String::from("b")))), Some((150, String::from("b"))));
+
+        // no bids before the listing starts
+        let mut later = spec.clone();
+        later.start_time += 30;
+        pubsub.set_ttl(later.end());
+        pubsub.set_spec(Some(later.clone()));
+        assert_eq!(pubsub.add_msg(bid(500, "c"), chrono::Local::now()), 1);
+        later.end_time = later.start_time;
+        assert!(!later.valid());
+
+        // spec survives the DHT encoding
+        let appnode = AppNode::new(aux::get_ip().unwrap(), 1375, None);
+        appnode.publish_auction(String::from("bike"), spec.clone(), spec.price(AuctionKind::default()), None);
+        let json = appnode.get_pubsub_json(String::from("bike"));
+        assert_eq!(json["spec"], serde_json::json!(spec));
+        assert_eq!(json["ttl"], format!("{}", spec.end()));
+    }
+
+    #[test]
+    fn cancel_close_test() {
+        let bid = |data: usize, addr: &str| serde_json::json!({"data": data, "sender_addr": addr}).to_string();
+        let appnode = AppNode::new(aux::get_ip().unwrap(), 1376, None);
+        let publisher = appnode.node.get_addr();
+        let topics = [String::from("lamp"), String::from("desk")];
+        let ttl = chrono::Local::now() + chrono::Duration::minutes(1);
+        let mine = |msg: String, msg_type: usize, exp_time: Option<String>| {
+            let data = Data::new(msg, msg_type, exp_time).sign(&appnode.kademlia.identity).to_json();
+            let mut blockchain = appnode.kademlia.blockchain.lock().unwrap();
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        assert!(mine(format!("REGISTER: {}", publisher), 0, None));
+        for topic in &topics {
+            appnode.publish(topic.clone(), ttl);
+            assert!(mine(format!("PUB_TOPIC: {}|PUBLISHER: {}", topic, publisher), 1, Some(format!("{}", ttl))));
+            assert!(appnode.add_msg(topic.clone(), bid(10, &publisher), None));
+        }
+
+        // cancelled once its CANCEL_TOPIC is mined: no further bids, no outcome
+        assert!(!appnode.cancel(topics[0].clone()));
+        assert!(mine(cancel_msg(&topics[0], &publisher), CANCEL_MSG_TYPE, None));
+        assert!(appnode.cancel(topics[0].clone()));
+        assert!(!appnode.cancel(topics[0].clone()));
+        assert!(!appnode.add_msg(topics[0].clone(), bid(20, &publisher), None));
+        let json = appnode.get_pubsub_json(topics[0].clone());
+        assert_eq!(json["cancelled"], true);
+        assert_eq!(json["winner"], serde_json::Value::Null);
+
+        // closed early once its END_TOPIC is mined: outcome at the current bid
+        assert!(!appnode.close(topics[1].clone(), chrono::Local::now()));
+        assert!(mine(String::from("END_TOPIC: desk|BID: null|BIDDER: null"), 2, None));
+        assert!(appnode.close(topics[1].clone(), chrono::Local::now()));
+        assert!(!appnode.add_msg(topics[1].clone(), bid(20, &publisher), None));
+        let json = appnode.get_pubsub_json(topics[1].clone());
+        assert_eq!(json["cancelled"], false);
+        assert_eq!(json["price"], 10);
+        assert_eq!(json["winner"], publisher);
+
+        // cancellations are signed by the topic publisher
+        let identity = Identity::from_seed(&[7; 32]);
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let mut add = |data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        let cancel = |identity: &Identity| Data::new(cancel_msg("lamp", "127.0.0.1:1"), CANCEL_MSG_TYPE, None).sign(identity).to_json();
+        assert!(add(signed("REGISTER: 127.0.0.1:1", 0)));
+        assert!(!add(cancel(&identity)));
+        assert!(add(signed("PUB_TOPIC: lamp|PUBLISHER: 127.0.0.1:1", 1)));
+        assert!(!add(cancel(&Identity::from_seed(&[8; 32]))));
+        assert!(add(cancel(&identity)));
+
+        let mut state = LedgerState::default();
+        for block in &blockchain.blocks {
+            state.apply_block(block, &blockchain.mint_policy());
+        }
+        assert!(state.auctions.is_empty());
+    }
+
+    #[test]
+    fn accounts_test() {
+        let keys: Vec<Identity> = (7..11).map(|seed| Identity::from_seed(&[seed; 32])).collect();
+        let tx = |msg: String, msg_type: usize, signer: usize| {
+            Data::new(msg, msg_type, Some(format!("{}", chrono::Local::now() + chrono::Duration::hours(1)))).sign(&keys[signer]).to_json()
+        };
+        let escrow = |topic: &str, bidder: usize, amount: u64| {
+            tx(escrow_msg(topic, &format!("127.0.0.1:{}", bidder), amount), ESCROW_MSG_TYPE, bidder - 1)
+        };
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let add = |blockchain: &mut Blockchain, data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        for addr in 1..4 {
+            assert!(add(&mut blockchain, tx(format!("REGISTER: 127.0.0.1:{}", addr), 0, addr - 1)));
+        }
+        assert!(add(&mut blockchain, tx(String::from("PUB_TOPIC: car|PUBLISHER: 127.0.0.1:1"), 1, 0)));
+        let balances = |blockchain: &Blockchain| (1..4).map(|addr| blockchain.balance(&format!("127.0.0.1:{}", addr)).unwrap()).collect::<Vec<u64>>();
+
+        // faucet once per key at registration, proof-of-work has no minters
+        assert_eq!(balances(&blockchain), vec![FAUCET_AMOUNT; 3]);
+        assert!(add(&mut blockchain, tx(String::from("REGISTER: 127.0.0.1:5"), 0, 0)));
+        assert_eq!(blockchain.balance("127.0.0.1:5"), Some(0));
+        assert!(!add(&mut blockchain, tx(mint_msg("127.0.0.1:2", 10), MINT_MSG_TYPE, 0)));
+        assert!(ProofOfWork.mint_policy().minters.is_empty());
+        assert!(ProofOfAuthority::new(vec![keys[0].public_key()]).mint_policy().may_mint(&keys[0].public_key()));
+
+        // escrow signed by the bidder, covered by its funds and above the topic escrow
+        assert!(!add(&mut blockchain, escrow("car", 2, FAUCET_AMOUNT + 1)));
+        assert!(!add(&mut blockchain, tx(escrow_msg("car", "127.0.0.1:2", 300), ESCROW_MSG_TYPE, 0)));
+        assert!(add(&mut blockchain, escrow("car", 2, 300)));
+        assert!(!add(&mut blockchain, escrow("car", 3, 300)));
+        assert_eq!(balances(&blockchain), vec![1000, 700, 1000]);
+
+        // outbid bidder is refunded, own escrow counts when raising
+        assert!(add(&mut blockchain, escrow("car", 3, 400)));
+        assert_eq!(balances(&blockchain), vec![1000, 1000, 600]);
+        assert!(add(&mut blockchain, escrow("car", 2, 1000)));
+        assert!(!add(&mut blockchain, escrow("car", 2, 1001)));
+        assert_eq!(balances(&blockchain), vec![1000, 0, 1000]);
+
+        // block templates leave out invalid transactions
+        let template = vec![escrow("car", 3, 900), tx(String::from("REGISTER: 127.0.0.1:4"), 0, 3)];
+        assert_eq!(blockchain.valid_txs(template).len(), 1);
+
+        // settlement pays the seller, no escrow once ended
+        assert!(add(&mut blockchain, tx(String::from("END_TOPIC: car|BID: 1000|BIDDER: \"127.0.0.1:2\""), 2, 0)));
+        assert_eq!(balances(&blockchain), vec![2000, 0, 1000]);
+        assert!(!add(&mut blockchain, escrow("car", 3, 1001)));
+
+        // cancellation refunds the escrow
+        assert!(add(&mut blockchain, tx(String::from("PUB_TOPIC: bike|PUBLISHER: 127.0.0.1:1"), 1, 0)));
+        assert!(add(&mut blockchain, escrow("bike", 3, 500)));
+        assert_eq!(balances(&blockchain), vec![2000, 0, 500]);
+
+        // settlement draws on the winner escrow only, up to its units
+        let settle = |blockchain: &Blockchain, winner: &str| {
+            let mut state = blockchain.state_at(blockchain.tip().height).unwrap();
+            state.engine.auctions.get_mut("bike").unwrap().outcome = Some((900, String::from(winner)));
+            state.settle("bike");
+            (1..4).map(|addr| state.balance(&format!("127.0.0.1:{}", addr))).collect::<Vec<u64>>()
+        };
+        assert_eq!(settle(&blockchain, "127.0.0.1:3"), vec![2500, 0, 500]);
+        assert_eq!(settle(&blockchain, "127.0.0.1:2"), vec![2000, 0, 1000]);
+        assert!(add(&mut blockchain, tx(cancel_msg("bike", "127.0.0.1:1"), CANCEL_MSG_TYPE, 0)));
+        assert_eq!(balances(&blockchain), vec![2000, 0, 1000]);
+
+        // tip state kept as blocks are added matches a replay of the chain
+        let tip = blockchain.tip().height;
+        let kept = blockchain.state_at(tip);
+        assert!(blockchain.tip_state.is_some());
+        blockchain.tip_state = None;
+        assert_eq!(blockchain.state_at(tip), kept);
+    }
+
+    #[test]
+    fn auction_engine_test() {
+        let now = chrono::Local::now().timestamp();
+        let at = |secs: i64| chrono::Local::now() + chrono::Duration::seconds(secs);
+        let publish = |topic: &str, start: i64| {
+            let mut spec = AuctionSpec::new(topic, at(100));
+            spec.start_time = now + start;
+            spec.end_time = now + 100;
+            let msg = format!("PUB_TOPIC: {}|PUBLISHER: 127.0.0.1:1|KIND: {}|SPEC: {}|SOFT_CLOSE: {}",
+                topic, spec.price(AuctionKind::default()).to_json(), spec.encode(), SoftClose::new(30, 60).unwrap().to_json());
+            Data::new(msg, 1, Some(format!("{}", spec.end()))).to_json()
+        };
+        let tx = |msg: String, msg_type: usize| Data::new(msg, msg_type, None).to_json();
+        let escrow = |bidder: &str, amount: u64| tx(escrow_msg("car", bidder, amount), ESCROW_MSG_TYPE);
+        let events = vec![
+            (now, publish("car", 10)),
+            (now + 5, escrow("127.0.0.1:2", 50)),
+            (now + 20, escrow("127.0.0.1:2", 50)),
+            (now + 21, escrow("127.0.0.1:3", 50)),
+            (now + 75, escrow("127.0.0.1:3", 60)),
+            (now + 80, tx(extend_msg("car", "127.0.0.1:3"), EXTEND_MSG_TYPE)),
+        ];
+
+        // pending until its start, bids only taken while open
+        let engine = AuctionEngine::replay(&events[..1]);
+        assert_eq!(engine.auction("car").unwrap().phase_at(now), AuctionPhase::Pending);
+        assert_eq!(engine.auction("car").unwrap().phase_at(now + 10), AuctionPhase::Open);
+        let engine = AuctionEngine::replay(&events);
+        let auction = engine.auction("car").unwrap();
+        assert_eq!(auction.bids, vec![Bid::new(50, String::from("127.0.0.1:2")), Bid::new(60, String::from("127.0.0.1:3"))]);
+
+        // extended end, outcome once closing
+        assert_eq!(auction.end, now + 160);
+        assert_eq!(auction.phase_at(now + 150), AuctionPhase::Open);
+        assert_eq!(auction.outcome_at(now + 150), None);
+        assert_eq!(auction.phase_at(now + 161), AuctionPhase::Closing);
+        assert_eq!(auction.outcome_at(now + 161), Some((60, String::from("127.0.0.1:3"))));
+
+        // no escrow once closed, END_TOPIC must record the outcome of the bids
+        let mut engine = AuctionEngine::replay(&events);
+        assert!(!engine.apply(now + 170, &escrow("127.0.0.1:2", 70)));
+        assert!(!engine.apply(now + 175, &tx(String::from("END_TOPIC: car|BID: 50|BIDDER: \"127.0.0.1:2\""), 2)));
+
+        // no cancellation once its end passed
+        assert!(!engine.apply(now + 172, &tx(cancel_msg("car", "127.0.0.1:1"), CANCEL_MSG_TYPE)));
+        assert_eq!(engine.auction("car").unwrap().phase, AuctionPhase::Closing);
+
+        // settled by END_TOPIC, terminal phases ignore further events
+        let mut events = events;
+        events.push((now + 170, escrow("127.0.0.1:2", 70)));
+        events.push((now + 175, tx(String::from("END_TOPIC: car|BID: 60|BIDDER: \"127.0.0.1:3\""), 2)));
+        events.push((now + 176, tx(cancel_msg("car", "127.0.0.1:1"), CANCEL_MSG_TYPE)));
+        let engine = AuctionEngine::replay(&events);
+        let auction = engine.auction("car").unwrap();
+        assert_eq!(auction.bids.len(), 2);
+        assert_eq!(auction.phase, AuctionPhase::Settled);
+        let json = auction.as_json(now + 200);
+        assert_eq!(json["price"], 60);
+        assert_eq!(json["winner"], "127.0.0.1:3");
+
+        // replayed from the ledger
+        let identity = Identity::from_seed(&[7; 32]);
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let add = |blockchain: &mut Blockchain, data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        let sign = |tx: String| Data::from_json(&tx).unwrap().sign(&identity).to_json();
+        assert!(add(&mut blockchain, signed("REGISTER: 127.0.0.1:1", 0)));
+        assert!(add(&mut blockchain, sign(publish("bike", 0))));
+        assert_eq!(blockchain.auction("bike").unwrap().phase_at(now), AuctionPhase::Open);
+        assert!(add(&mut blockchain, sign(tx(cancel_msg("bike", "127.0.0.1:1"), CANCEL_MSG_TYPE))));
+        let json = blockchain.auction("bike").unwrap().as_json(now);
+        assert_eq!(json["cancelled"], true);
+        assert_eq!(json["winner"], serde_json::Value::Null);
+        assert_eq!(blockchain.auction_engine().in_phase(AuctionPhase::Cancelled, now).len(), 1);
+    }
+
+    #[test]
+    fn notify_test() {
+        let bid = |data: usize, addr: &str| serde_json::json!({"data": data, "sender_addr": addr}).to_string();
+        let seller = AppNode::new(aux::get_ip().unwrap(), 1377, None);
+        let bidder = AppNode::new(aux::get_ip().unwrap(), 1378, Some(seller.node.clone()));
+        let (seller_addr, bidder_addr) = (seller.node.get_addr(), bidder.node.get_addr());
+        let (seller_events, bidder_events) = (seller.kademlia.inbox.events(), bidder.kademlia.inbox.events());
+        let wait = Duration::from_secs(NODETIMEOUT * 5);
+
+        // accepted bids are pushed to the publisher and subscribers
+        seller.publish(String::from("vase"), chrono::Local::now() + chrono::Duration::minutes(1));
+        assert!(bidder.subscribe(String::from("vase")));
+        assert!(bidder.add_msg(String::from("vase"), bid(10, &bidder_addr), None));
+        let notification = seller_events.recv_timeout(wait).unwrap();
+        assert_eq!(notification.event, AuctionEvent::Bid { bid: 10, bidder: bidder_addr.clone(), outbid: None });
+        assert!(!notification.outbids(&seller_addr));
+
+        // outbid subscriber is told so
+        assert!(seller.add_msg(String::from("vase"), bid(20, &seller_addr), None));
+        let notification = bidder_events.recv_timeout(wait).unwrap();
+        assert_eq!(notification.topic, "vase");
+        assert!(notification.outbids(&bidder_addr));
+
+        // retried notifications are delivered once, unsigned or forged ones never
+        let ended = Notification::new("vase", AuctionEvent::Ended { price: Some(20), winner: Some(seller_addr.clone()) });
+        assert!(seller.kademlia.notify(bidder.node.clone(), ended.clone()));
+        let mut forged = ended.clone().sign(&Identity::from_seed(&[1; 32]), &seller_addr);
+        forged.event = AuctionEvent::Cancelled;
+        assert!(!forged.verify());
+        assert!(seller.kademlia.notify(bidder.node.clone(), forged));
+        let ended = ended.sign(&seller.kademlia.identity, &seller_addr);
+        assert!(seller.kademlia.notify(bidder.node.clone(), ended.clone()));
+        assert!(seller.kademlia.notify(bidder.node.clone(), ended.clone()));
+        assert_eq!(bidder_events.recv_timeout(wait).unwrap(), ended);
+        assert!(bidder_events.recv_timeout(wait).is_err());
+
+        // inbox remembers the last INBOX_SEEN ids, channels only get later notifications
+        let inbox = Inbox::new();
+        assert!(inbox.deliver(ended.clone()));
+        let events = inbox.events();
+        assert!(!inbox.deliver(ended.clone()));
+        for bid in 0..INBOX_SEEN {
+            inbox.deliver(Notification::new("vase", AuctionEvent::Bid { bid, bidder: bidder_addr.clone(), outbid: None }));
+        }
+        assert!(inbox.deliver(ended));
+        assert_eq!(events.len(), INBOX_CAPACITY);
+        drop(events);
+        assert!(inbox.deliver(Notification::new("vase", AuctionEvent::Cancelled)));
+    }
+
+    #[test]
+    fn lease_test() {
+        let now = chrono::Local::now();
+        let mut pubsub = PubSubInstance::new(None, String::from("127.0.0.1:1"), None, None);
+        pubsub.set_ttl(now + chrono::Duration::minutes(1));
+
+        // leases expire unless renewed, subs without one get a fresh lease
+        pubsub.add_sub(String::from("127.0.0.1:2"));
+        pubsub.substack.lock().unwrap().push(String::from("127.0.0.1:3"));
+        assert!(pubsub.prune_leases(now).is_empty());
+        pubsub.set_lease(String::from("127.0.0.1:3"), now.timestamp() + 2 * SUBSCRIPTION_LEASE);
+        let expired = pubsub.prune_leases(now + chrono::Duration::seconds(SUBSCRIPTION_LEASE + 1));
+        assert_eq!(expired, vec![String::from("127.0.0.1:2")]);
+        assert!(!pubsub.verify_addr(String::from("127.0.0.1:2")));
+        assert!(pubsub.remove_sub("127.0.0.1:3"));
+        assert!(!pubsub.remove_sub("127.0.0.1:3"));
+
+        // subscribers are dropped after MAX_MISSED notifications missed in a row
+        pubsub.add_sub(String::from("127.0.0.1:4"));
+        for _ in 1..MAX_MISSED {
+            assert!(!pubsub.miss("127.0.0.1:4"));
+        }
+        assert!(pubsub.reach("127.0.0.1:4"));
+        assert!(!pubsub.reach("127.0.0.1:4"));
+        for _ in 1..MAX_MISSED {
+            assert!(!pubsub.miss("127.0.0.1:4"));
+        }
+        assert_eq!(pubsub.missed("127.0.0.1:4"), MAX_MISSED - 1);
+        assert!(pubsub.miss("127.0.0.1:4"));
+        assert!(!pubsub.verify_addr(String::from("127.0.0.1:4")));
+        assert!(!pubsub.miss("127.0.0.1:1"));
+
+        let seller = AppNode::new(aux::get_ip().unwrap(), 1379, None);
+        let bidder = AppNode::new(aux::get_ip().unwrap(), 1380, Some(seller.node.clone()));
+        let topic = String::from("clock");
+        seller.publish(topic.clone(), now + chrono::Duration::minutes(1));
+
+        // unsubscribe
+        assert!(bidder.subscribe(topic.clone()));
+        assert!(bidder.renew(topic.clone()));
+        assert_eq!(bidder.get_pubsub_json(topic.clone())["subscribed"], true);
+        assert!(bidder.unsubscribe(topic.clone()));
+        assert!(!bidder.renew(topic.clone()));
+        assert_eq!(bidder.get_pubsub_json(topic.clone())["subscribed"], false);
+
+        // unreachable subscribers are kept until they miss MAX_MISSED notifications
+        let dead = format!("{}:1381", aux::get_ip().unwrap());
+        let mut pubsub = PubSubInstance::new(None, seller.node.get_addr(), None, None);
+        pubsub.set_ttl(now + chrono::Duration::minutes(1));
+        pubsub.add_sub(dead.clone());
+        assert_eq!(seller.kademlia.submit_update(&topic, TopicUpdate::Publish(pubsub.to_string())), Some(UpdateReceipt::Applied));
+        let stored = || PubSubInstance::decode(&seller.kademlia.get(topic.clone()).unwrap()).unwrap();
+        let bid = serde_json::json!({"data": 10, "sender_addr": seller.node.get_addr()}).to_string();
+        assert!(seller.add_msg(topic.clone(), bid, None));
+        sleep(Duration::from_secs(NODETIMEOUT * 10));
+        assert!(stored().verify_addr(dead.clone()));
+        assert_eq!(stored().missed(&dead), 1);
+        for _ in 1..MAX_MISSED {
+            assert!(seller.notify(topic.clone(), AuctionEvent::Cancelled));
+            sleep(Duration::from_secs(NODETIMEOUT * 10));
+        }
+        assert!(!stored().verify_addr(dead));
+    }
+
+    #[test]
+    fn search_test() {
+        let now = chrono::Local::now();
+        let identity = Identity::from_seed(&[7; 32]);
+        let kad1 = KademliaInstance::new(aux::get_ip().unwrap(), 1382, None);
+        let kad2 = KademliaInstance::new(aux::get_ip().unwrap(), 1383, Some(kad1.node.clone()));
+        let listings = [("red-bike", "bike vintage", 100, 200), ("blue-bike", "bike", 300, 300), ("lamp", "home", 50, 60)];
+
+        let mut blockchain = kad1.blockchain.lock().unwrap();
+        let add = |blockchain: &mut Blockchain, data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        assert!(add(&mut blockchain, signed("REGISTER: 127.0.0.1:1", 0)));
+        for (topic, tags, price, secs) in listings {
+            let mut spec = AuctionSpec::new(topic, now + chrono::Duration::seconds(secs));
+            spec.starting_price = price;
+            spec.tags = tags.split(' ').map(String::from).collect();
+            let msg = format!("PUB_TOPIC: {}|PUBLISHER: 127.0.0.1:1|KIND: {}|SPEC: {}", topic, spec.price(AuctionKind::default()).to_json(), spec.encode());
+            let data = Data::new(msg, 1, Some(format!("{}", spec.end()))).sign(&identity).to_json();
+            assert!(add(&mut blockchain, data));
+            kad1.index_keywords(topic, &keywords(topic, Some(&spec)));
+        }
+
+        // ledger index by prefix, tag, seller, price and end time (replayed once per tip)
+        let index = blockchain.topic_index(now.timestamp());
+        assert_eq!(kad1.topic_cache.index(&blockchain, now.timestamp()), index);
+        assert!(add(&mut blockchain, signed("REGISTER: 127.0.0.1:2", 0)));
+        assert_eq!(kad1.topic_cache.index(&blockchain, now.timestamp()), index);
+        drop(blockchain);
+        let found = |query: TopicQuery| index.search(&query, None).into_iter().map(|entry| entry.topic).collect::<Vec<String>>();
+        assert_eq!(found(TopicQuery { prefix: Some(String::from("RED")), ..Default::default() }), vec!["red-bike"]);
+        assert_eq!(found(TopicQuery { tag: Some(String::from("bike")), max_price: Some(200), ..Default::default() }), vec!["red-bike"]);
+        assert_eq!(found(TopicQuery { min_price: Some(100), ..Default::default() }), vec!["red-bike", "blue-bike"]);
+        assert_eq!(found(TopicQuery { ends_before: Some(now.timestamp() + 100), ..Default::default() }), vec!["lamp"]);
+        assert!(found(TopicQuery { seller: Some(String::from("127.0.0.1:2")), ..Default::default() }).is_empty());
+        assert_eq!(keywords("red-bike", None), vec!["red", "bike"]);
+
+        // keyword queries answered by another node (DHT keyword index)
+        assert_eq!(kad2.keyword_topics("vintage"), vec!["red-bike"]);
+        let query = TopicQuery { keyword: Some(String::from("Bike")), ..Default::default() };
+        let entries = kad2.query_topics(kad1.node.clone(), query).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.topic.as_str()).collect::<Vec<&str>>(), vec!["red-bike", "blue-bike"]);
+        assert_eq!(entries[1].price, 300);
+        assert_eq!(entries[0].phase, AuctionPhase::Open);
+
+        // keyword lists are merged by their coordinator, never overwritten
+        let kad = kad2.clone();
+        let indexing = spawn(move || kad.index_keywords("desk", &[String::from("home")]));
+        kad1.index_keywords("chair", &[String::from("home")]);
+        indexing.join().unwrap();
+        let mut topics = kad2.keyword_topics("home");
+        topics.sort();
+        assert_eq!(topics, vec!["chair", "desk", "lamp"]);
+    }
+
+    #[test]
+    fn pubsub_record_test() {
+        let text = || -> String {
+            (0..random_range(1..12)).map(|_| ['a', 'Z', '7', ' ', ';', '|', ':', '"'][random_range(0..8)]).collect()
+        };
+        let kinds = [AuctionKind::default(), AuctionKind::dutch(100, 10, 1), AuctionKind::FirstPrice { reserve: 5 }, AuctionKind::SecondPrice { reserve: 5 }];
+
+        // round trip of random records, separators in bids and names included
+        for _ in 0..200 {
+            let publisher = format!("pub{}", text());
+            let mut subs: Vec<String> = (0..random_range(0..4)).map(|i| format!("{}{}", i, text())).collect();
+            subs.retain(|sub| *sub != publisher);
+            let msgs = (0..random_range(0..5))
+                .map(|_| serde_json::json!({"data": random_range(0..1000), "sender_addr": text()}).to_string())
+                .collect();
+            let mut spec = AuctionSpec::lasting(&text(), 1);
+            spec.tags = vec![text()];
+            let record = PubSubRecord {
+                version: PUBSUB_VERSION,
+                id: text(),
+                publisher,
+                leases: subs.iter().map(|sub| (sub.clone(), random_range(0..i64::MAX))).collect(),
+                missed: subs.iter().map(|sub| (sub.clone(), random_range(0..MAX_MISSED))).collect(),
+                subs,
+                msgs,
+                ttl: random_bool(0.8).then(|| format!("{}", chrono::Local::now() + chrono::Duration::seconds(random_range(-100..100)))),
+                kind: kinds[random_range(0..4)].clone(),
+                soft_close: random_bool(0.5).then(|| SoftClose::new(random_range(1..60), random_range(1..60))).flatten(),
+                spec: random_bool(0.5).then_some(spec),
+                cancelled: random_bool(0.5),
+            };
+            let pubsub = PubSubInstance::from_record(record.clone()).unwrap();
+            assert_eq!(PubSubInstance::decode(&pubsub.to_string()).unwrap().to_record(), record);
+        }
+
+        // schema: versions, bids and subscribers are validated
+        let pubsub = PubSubInstance::new(None, String::from("127.0.0.1:1"), None, None);
+        let record = pubsub.to_record();
+        assert!(PubSubInstance::decode(&serde_json::to_string(&record).unwrap()).is_some());
+        let invalid = [
+            PubSubRecord { version: PUBSUB_VERSION + 1, ..record.clone() },
+            PubSubRecord { msgs: vec![String::from("bid 10")], ..record.clone() },
+            PubSubRecord { subs: vec![String::new()], ..record.clone() },
+            PubSubRecord { subs: vec![String::from("127.0.0.1:1")], ..record.clone() },
+            PubSubRecord { ttl: Some(String::from("tomorrow")), ..record.clone() },
+            PubSubRecord { missed: [(String::from("127.0.0.1:2"), 1)].into(), ..record.clone() },
+        ];
+        for record in invalid {
+            assert!(PubSubInstance::decode(&serde_json::to_string(&record).unwrap()).is_none());
+        }
+        assert!(PubSubInstance::decode("not a record").is_none());
+
+        // legacy values are migrated, without empty or broken entries
+        let ttl = format!("{}", chrono::Local::now() + chrono::Duration::minutes(1));
+        let bid = serde_json::json!({"data": 10, "sender_addr": "127.0.0.1:2"}).to_string();
+        let legacy = STANDARD.encode(format!("ab12;127.0.0.1:1;;{} bid;{}", bid, ttl));
+        let pubsub = PubSubInstance::decode(&legacy).unwrap();
+        assert_eq!(pubsub.to_record().version, PUBSUB_VERSION);
+        assert!(pubsub.substack.lock().unwrap().is_empty());
+        assert_eq!(pubsub.bids(), vec![Bid::new(10, String::from("127.0.0.1:2"))]);
+        assert!(pubsub.verify_pubsub());
+
+        let legacy = STANDARD.encode(format!("ab12;127.0.0.1:1;127.0.0.1:3 127.0.0.1:3;;{};{};NONE;NONE;CANCELLED;127.0.0.1:3@7", ttl, AuctionKind::FirstPrice { reserve: 5 }.to_json()));
+        let record = PubSubInstance::decode(&legacy).unwrap().to_record();
+        assert_eq!(record.subs, vec![String::from("127.0.0.1:3")]);
+        assert_eq!(record.leases.get("127.0.0.1:3"), Some(&7));
+        assert_eq!(record.kind, AuctionKind::FirstPrice { reserve: 5 });
+        assert!(record.cancelled);
+    }
+
+    #[test]
+    fn bid_history_test() {
+        let now = chrono::Local::now().timestamp();
+        let keys: Vec<Identity> = (7..10).map(|seed| Identity::from_seed(&[seed; 32])).collect();
+        let tx = |msg: String, msg_type: usize, signer: usize| {
+            Data::new(msg, msg_type, Some(format!("{}", chrono::Local::now() + chrono::Duration::hours(1)))).sign(&keys[signer]).to_json()
+        };
+        let publish = |topic: &str| {
+            let mut spec = AuctionSpec::new(topic, chrono::Local::now() + chrono::Duration::seconds(100));
+            spec.start_time = now;
+            spec.end_time = now + 100;
+            let msg = format!("PUB_TOPIC: {}|PUBLISHER: 127.0.0.1:1|KIND: {}|SPEC: {}",
+                topic, spec.price(AuctionKind::default()).to_json(), spec.encode());
+            Data::new(msg, 1, Some(format!("{}", spec.end()))).sign(&keys[0]).to_json()
+        };
+        let escrow = |topic: &str, bidder: usize, amount: u64| {
+            tx(escrow_msg(topic, &format!("127.0.0.1:{}", bidder), amount), ESCROW_MSG_TYPE, bidder - 1)
+        };
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let add = |blockchain: &mut Blockchain, data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        for addr in 1..4 {
+            assert!(add(&mut blockchain, tx(format!("REGISTER: 127.0.0.1:{}", addr), 0, addr - 1)));
+        }
+        assert!(add(&mut blockchain, publish("car")));
+        assert!(add(&mut blockchain, escrow("car", 2, 300)));
+        assert!(add(&mut blockchain, escrow("car", 3, 400)));
+
+        // bids in chain order with their block references
+        let history = blockchain.bid_history("car");
+        assert_eq!(history.iter().map(|bid| (bid.bidder.as_str(), bid.amount)).collect::<Vec<_>>(),
+            vec![("127.0.0.1:2", 300), ("127.0.0.1:3", 400)]);
+        for bid in &history {
+            assert!(bid.accepted);
+            assert_eq!(blockchain.block_by_id(bid.block_id).unwrap().hash, bid.block_hash);
+        }
+        assert!(history[0].block_id < history[1].block_id);
+
+        // bidders see their status, the seller its auction
+        let now = chrono::Local::now().timestamp();
+        let status = |blockchain: &Blockchain, bidder: &str| {
+            blockchain.my_bids(bidder, now).into_iter().map(|bid| (bid.bid.topic, bid.status)).collect::<Vec<_>>()
+        };
+        assert_eq!(status(&blockchain, "127.0.0.1:2"), vec![(String::from("car"), BidStatus::Outbid)]);
+        assert_eq!(status(&blockchain, "127.0.0.1:3"), vec![(String::from("car"), BidStatus::Leading)]);
+        let auctions = blockchain.my_auctions("127.0.0.1:1", now);
+        assert_eq!(auctions.len(), 1);
+        assert_eq!((auctions[0].bids, auctions[0].phase), (2, AuctionPhase::Open));
+        assert_eq!(auctions[0].highest.as_ref().unwrap().bidder, "127.0.0.1:3");
+        assert!(blockchain.my_auctions("127.0.0.1:2", now).is_empty());
+
+        // settled and cancelled auctions
+        assert!(add(&mut blockchain, tx(String::from("END_TOPIC: car|BID: 400|BIDDER: \"127.0.0.1:3\""), 2, 0)));
+        assert!(add(&mut blockchain, publish("bike")));
+        assert!(add(&mut blockchain, escrow("bike", 2, 500)));
+        assert!(add(&mut blockchain, tx(cancel_msg("bike", "127.0.0.1:1"), CANCEL_MSG_TYPE, 0)));
+        assert_eq!(status(&blockchain, "127.0.0.1:2"),
+            vec![(String::from("car"), BidStatus::Lost), (String::from("bike"), BidStatus::Cancelled)]);
+        assert_eq!(status(&blockchain, "127.0.0.1:3"), vec![(String::from("car"), BidStatus::Won)]);
+        let auctions = blockchain.my_auctions("127.0.0.1:1", now);
+        assert_eq!(auctions[0].outcome, Some((400, String::from("127.0.0.1:3"))));
+        assert_eq!((auctions[1].topic.as_str(), auctions[1].phase), ("bike", AuctionPhase::Cancelled));
+
+        // bids the auction did not take are kept, but rejected
+        let entries: Vec<TxEntry> = [publish("desk"), escrow("desk", 2, 50), escrow("desk", 3, 50)].into_iter()
+            .enumerate()
+            .map(|(index, data)| TxEntry { block_id: 1, block_hash: String::new(), timestamp: now, index, data })
+            .collect();
+        let history = bid_history("desk", &entries);
+        assert_eq!(history.iter().map(|bid| bid.accepted).collect::<Vec<_>>(), vec![true, false]);
+        let record = auction_record("desk", &entries).unwrap();
+        assert_eq!(bid_status(&record, &history, "127.0.0.1:3", now), BidStatus::Rejected);
+    }
+
+    #[test]
+    fn coordinator_test() {
+        let bid = |data: usize, addr: &str| serde_json::json!({"data": data, "sender_addr": addr}).to_string();
+        let ttl = chrono::Local::now() + chrono::Duration::minutes(1);
+        let seller = AppNode::new(aux::get_ip().unwrap(), 1384, None);
+        let bidders: Vec<AppNode> = (1385..1387).map(|port| AppNode::new(aux::get_ip().unwrap(), port, Some(seller.node.clone()))).collect();
+        let addrs: Vec<String> = bidders.iter().map(|bidder| bidder.node.get_addr()).collect();
+        let request = |topic: &str, bidder: usize, data: usize, seq: usize| {
+            BidRequest { topic: topic.to_string(), bidder: addrs[bidder].clone(), msg: bid(data, &addrs[bidder]), seq, escrow: None }
+        };
+        let nodes_distance = |topic: &str| [&seller.node, &bidders[0].node, &bidders[1].node].iter()
+            .map(|node| Distance::new(&node.id, &Key::new(topic.to_string())))
+            .min()
+            .unwrap();
+        let seq = |topic: &str| PubSubInstance::decode(&seller.kademlia.get(topic.to_string()).unwrap()).unwrap().seq();
+        seller.publish(String::from("lot"), ttl);
+        for bidder in &bidders {
+            assert!(bidder.subscribe(String::from("lot")));
+        }
+
+        // sequenced bids, bids made on an old sequence are stale
+        assert_eq!(bidders[0].kademlia.submit_bid(request("lot", 0, 10, 0)), Some(BidReceipt::Accepted { seq: 1, outbid: None }));
+        assert_eq!(bidders[1].kademlia.submit_bid(request("lot", 1, 20, 0)), Some(BidReceipt::Stale { seq: 1 }));
+        assert_eq!(bidders[1].kademlia.submit_bid(request("lot", 1, 20, 1)), Some(BidReceipt::Accepted { seq: 2, outbid: Some(addrs[0].clone()) }));
+        assert_eq!(bidders[0].kademlia.submit_bid(request("lot", 0, 15, 2)), Some(BidReceipt::Rejected));
+
+        // bids are only taken from their bidder, departures from the departing node
+        assert_eq!(bidders[0].kademlia.submit_bid(request("lot", 1, 40, 2)), Some(BidReceipt::Rejected));
+        let mut forged = request("lot", 0, 40, 2);
+        forged.msg = bid(40, &addrs[1]);
+        assert_eq!(bidders[0].kademlia.submit_bid(forged), Some(BidReceipt::Rejected));
+        timed_rpc_proc(&bidders[0].kademlia.rpc, KademliaRequest::Depart(seller.node.clone()), bidders[1].node.clone(), Duration::from_secs(2));
+        assert!(!bidders[1].kademlia.coordinator.departed(&seller.node.id));
+
+        // raw stores of a topic instance are only taken from its coordinator
+        let closest = seller.kademlia.coordinator_of("lot").unwrap();
+        let sender = [&seller, &bidders[0], &bidders[1]].into_iter().find(|appnode| appnode.node != closest).unwrap();
+        let value = sender.kademlia.get(String::from("lot")).unwrap();
+        let res = timed_rpc_proc(&sender.kademlia.rpc, KademliaRequest::Store(String::from("lot"), value), closest, Duration::from_secs(2));
+        assert!(matches!(res, Some(KademliaResponse::PingUnableProcReq)));
+
+        // updates are only taken from the publisher, subscriptions from their subscriber
+        let update = |appnode: &AppNode, update: TopicUpdate| appnode.kademlia.submit_update("lot", update);
+        assert_eq!(update(&bidders[0], TopicUpdate::Unsubscribe(addrs[1].clone())), Some(UpdateReceipt::Rejected));
+        assert_eq!(update(&bidders[0], TopicUpdate::Missed(addrs[1].clone())), Some(UpdateReceipt::Rejected));
+        assert_eq!(update(&bidders[0], TopicUpdate::Extend(ttl.timestamp() + 60)), Some(UpdateReceipt::Rejected));
+        assert_eq!(update(&seller, TopicUpdate::Cancel), Some(UpdateReceipt::Rejected));
+        assert_eq!(update(&seller, TopicUpdate::Extend(ttl.timestamp() + 60)), Some(UpdateReceipt::Applied));
+
+        // concurrent bids are never lost
+        let threads: Vec<_> = bidders.iter().enumerate().map(|(i, bidder)| {
+            let (bidder, msg) = (bidder.clone(), bid(30 + 10 * i, &addrs[i]));
+            spawn(move || bidder.add_msg(String::from("lot"), msg, None))
+        }).collect();
+        let accepted = threads.into_iter().map(|thread| thread.join().unwrap()).filter(|ok| *ok).count();
+        assert!(accepted >= 1);
+        assert_eq!(seq("lot"), 2 + accepted);
+
+        // unreachable coordinator is skipped (the next closest takes over), departed after MAX_TIMEOUTS
+        let dead = Node::new(aux::get_ip().unwrap(), 1387);
+        let topic = (0..).map(|i| format!("lot{}", i))
+            .find(|topic| Distance::new(&dead.id, &Key::new(topic.clone())) < nodes_distance(topic))
+            .unwrap();
+        seller.publish(topic.clone(), ttl);
+        assert!(bidders[0].subscribe(topic.clone()));
+        bidders[0].kademlia.routingtable.lock().unwrap().update_routing_table(dead.clone());
+        assert_eq!(bidders[0].kademlia.coordinator_of(&topic), Some(dead.clone()));
+        assert_eq!(bidders[0].kademlia.submit_bid(request(&topic, 0, 10, 0)), Some(BidReceipt::Accepted { seq: 1, outbid: None }));
+        assert!(!bidders[0].kademlia.coordinator.departed(&dead.id));
+        for seq in 1..MAX_TIMEOUTS {
+            assert!(matches!(bidders[0].kademlia.submit_bid(request(&topic, 0, 10 + seq, seq)), Some(BidReceipt::Accepted { .. })));
+        }
+        assert!(bidders[0].kademlia.coordinator.departed(&dead.id));
+        assert!(!bidders[0].kademlia.routingtable.lock().unwrap().contains_node(&dead.id));
+
+        // resigned coordinator hands its topics over
+        let coordinator = seller.kademlia.coordinator_of("lot").unwrap();
+        [&seller, &bidders[0], &bidders[1]].iter().find(|appnode| appnode.node == coordinator).unwrap().shutdown();
+        let (i, bidder) = bidders.iter().enumerate().find(|(_, bidder)| bidder.node != coordinator).unwrap();
+        let receipt = bidder.kademlia.submit_bid(request("lot", i, 100, seq("lot")));
+        assert!(matches!(receipt, Some(BidReceipt::Accepted { .. })));
+        assert!(seller.kademlia.coordinator_of("lot").is_some_and(|node| node != coordinator));
+    }
+
+    #[test]
+    fn multiunit_test() {
+        let now = chrono::Local::now();
+        let bid = |data: usize, addr: &str, quantity: usize, items: &[&str]| {
+            let mut bid = Bid::new(data, String::from(addr));
+            bid.quantity = quantity;
+            bid.items = items.iter().map(|item| item.to_string()).collect();
+            bid
+        };
+        let won = |allocation: Vec<Allocation>| allocation.into_iter().map(|won| (won.bidder, won.quantity, won.price)).collect::<Vec<_>>();
+
+        // units by unit price, the last one filled in part, revised bids replace earlier ones
+        let uniform = AuctionKind::MultiUnit { units: 3, reserve: 10, pricing: Pricing::Uniform };
+        let bids = vec![bid(12, "a", 2, &[]), bid(15, "b", 2, &[]), bid(12, "c", 1, &[]), bid(20, "a", 2, &[])];
+        assert_eq!(won(uniform.allocate(&bids)), vec![(String::from("a"), 2, 30), (String::from("b"), 1, 15)]);
+        let discriminatory = AuctionKind::MultiUnit { units: 3, reserve: 10, pricing: Pricing::Discriminatory };
+        assert_eq!(won(discriminatory.allocate(&bids)), vec![(String::from("a"), 2, 40), (String::from("b"), 1, 15)]);
+        assert_eq!(uniform.outcome(&bids), Some((45, String::from("a"))));
+        assert!(uniform.verify_bid(&bids, &bid(10, "d", 3, &[]), now));
+        for invalid in [bid(9, "d", 1, &[]), bid(10, "d", 0, &[]), bid(10, "d", 4, &[]), bid(10, "d", 1, &["x"])] {
+            assert!(!uniform.verify_bid(&bids, &invalid, now));
+        }
+        assert!(!AuctionKind::default().verify_bid(&[], &bid(10, "d", 2, &[]), now));
+
+        // disjoint bundles of the highest revenue, each winner pays its bid
+        let topics: Vec<String> = ["chair", "table", "lamp"].iter().map(|topic| topic.to_string()).collect();
+        let bundle = AuctionKind::Bundle { topics, reserve: 5 };
+        let bids = vec![
+            bid(50, "a", 1, &["chair", "table"]),
+            bid(30, "b", 1, &["chair"]),
+            bid(30, "c", 1, &["table"]),
+            bid(10, "d", 1, &["lamp"]),
+            bid(45, "e", 1, &["chair", "lamp"]),
+        ];
+        let allocation = bundle.allocate(&bids);
+        assert_eq!(allocation.iter().map(|won| (won.bidder.as_str(), won.price)).collect::<Vec<_>>(), vec![("e", 45), ("c", 30)]);
+        assert_eq!(allocation[0].items, vec!["chair", "lamp"]);
+        for invalid in [bid(50, "f", 1, &["sofa"]), bid(9, "f", 1, &["chair", "table"]), bid(10, "f", 1, &["chair", "chair"]), bid(10, "f", 1, &[])] {
+            assert!(!bundle.verify_bid(&bids, &invalid, now));
+        }
+
+        // allocation of the escrowed bids recorded by END_TOPIC, winners pay from their deposit
+        let keys: Vec<Identity> = (7..10).map(|seed| Identity::from_seed(&[seed; 32])).collect();
+        let tx = |msg: String, msg_type: usize, signer: usize| {
+            Data::new(msg, msg_type, Some(format!("{}", now + chrono::Duration::minutes(1)))).sign(&keys[signer]).to_json()
+        };
+        let escrow = |topic: &str, bid: &Bid| {
+            let signer = bid.sender_addr.trim_start_matches("127.0.0.1:").parse::<usize>().unwrap() - 1;
+            tx(bid_escrow_msg(topic, bid), ESCROW_MSG_TYPE, signer)
+        };
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let add = |blockchain: &mut Blockchain, data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        let balances = |blockchain: &Blockchain| (1..4).map(|addr| blockchain.balance(&format!("127.0.0.1:{}", addr)).unwrap()).collect::<Vec<u64>>();
+        for addr in 1..4 {
+            assert!(add(&mut blockchain, tx(format!("REGISTER: 127.0.0.1:{}", addr), 0, addr - 1)));
+        }
+        assert!(add(&mut blockchain, tx(format!("PUB_TOPIC: chairs|PUBLISHER: 127.0.0.1:1|KIND: {}", uniform.to_json()), 1, 0)));
+        assert!(!add(&mut blockchain, escrow("chairs", &bid(15, "127.0.0.1:2", 2000, &[]))));
+        assert!(add(&mut blockchain, escrow("chairs", &bid(15, "127.0.0.1:2", 2, &[]))));
+        assert!(add(&mut blockchain, escrow("chairs", &bid(12, "127.0.0.1:3", 2, &[]))));
+        assert!(!add(&mut blockchain, escrow("chairs", &bid(600, "127.0.0.1:3", 2, &[]))));
+        assert_eq!(balances(&blockchain), vec![FAUCET_AMOUNT, FAUCET_AMOUNT - 30, FAUCET_AMOUNT - 24]);
+        let allocation = vec![
+            Allocation { bidder: String::from("127.0.0.1:2"), quantity: 2, items: Vec::new(), price: 24 },
+            Allocation { bidder: String::from("127.0.0.1:3"), quantity: 1, items: Vec::new(), price: 12 },
+        ];
+        assert!(!add(&mut blockchain, tx(String::from("END_TOPIC: chairs|BID: 36|BIDDER: \"127.0.0.1:2\""), 2, 0)));
+        let forged = vec![Allocation { bidder: String::from("127.0.0.1:3"), quantity: 3, items: Vec::new(), price: 36 }];
+        let end = format!("END_TOPIC: chairs|BID: 36|BIDDER: \"127.0.0.1:3\"|ALLOCATION: {}", allocation_msg(&forged));
+        assert!(!add(&mut blockchain, tx(end, 2, 0)));
+        assert!(!allocation_msg(&allocation).contains(['|', ':', ' ']));
+        let end = format!("END_TOPIC: chairs|BID: 36|BIDDER: \"127.0.0.1:2\"|ALLOCATION: {}", allocation_msg(&allocation));
+        assert!(add(&mut blockchain, tx(end, 2, 0)));
+        assert_eq!(balances(&blockchain), vec![FAUCET_AMOUNT + 36, FAUCET_AMOUNT - 24, FAUCET_AMOUNT - 12]);
+        let auction = blockchain.auction("chairs").unwrap();
+        assert_eq!((auction.phase, auction.allocation.clone()), (AuctionPhase::Settled, allocation));
+        assert_eq!(auction.as_json(now.timestamp())["allocation"][1]["bidder"], "127.0.0.1:3");
+        assert_eq!(blockchain.address_activity("127.0.0.1:3").len(), 3);
+
+        // bundles of topics of the publisher, taking their bids and ending them
+        for topic in ["chair", "table"] {
+            assert!(add(&mut blockchain, tx(format!("PUB_TOPIC: {}|PUBLISHER: 127.0.0.1:1", topic), 1, 0)));
+        }
+        assert!(add(&mut blockchain, tx(String::from("PUB_TOPIC: lamp|PUBLISHER: 127.0.0.1:2"), 1, 1)));
+        let set = |topics: &[&str]| AuctionKind::Bundle { topics: topics.iter().map(|topic| topic.to_string()).collect(), reserve: 5 };
+        for invalid in [set(&["chair", "lamp"]), set(&["chair", "sofa"]), set(&["chair", "chair"]), set(&[])] {
+            assert!(!add(&mut blockchain, tx(format!("PUB_TOPIC: set|PUBLISHER: 127.0.0.1:1|KIND: {}", invalid.to_json()), 1, 0)));
+        }
+        assert!(add(&mut blockchain, tx(format!("PUB_TOPIC: set|PUBLISHER: 127.0.0.1:1|KIND: {}", set(&["chair", "table"]).to_json()), 1, 0)));
+        assert!(!add(&mut blockchain, tx(format!("PUB_TOPIC: set2|PUBLISHER: 127.0.0.1:1|KIND: {}", set(&["table"]).to_json()), 1, 0)));
+        assert!(!add(&mut blockchain, escrow("chair", &Bid::new(50, String::from("127.0.0.1:2")))));
+        assert!(add(&mut blockchain, escrow("set", &bid(50, "127.0.0.1:2", 1, &["chair", "table"]))));
+        assert!(add(&mut blockchain, escrow("set", &bid(30, "127.0.0.1:3", 1, &["chair"]))));
+        let auction = blockchain.auction("set").unwrap();
+        let allocation = auction.expected_allocation();
+        assert_eq!(allocation.iter().map(|won| (won.bidder.as_str(), won.price)).collect::<Vec<_>>(), vec![("127.0.0.1:2", 50)]);
+        assert!(!add(&mut blockchain, tx(String::from("END_TOPIC: chair|BID: null|BIDDER: null"), 2, 0)));
+        let end = format!("END_TOPIC: set|BID: 50|BIDDER: \"127.0.0.1:2\"|ALLOCATION: {}", allocation_msg(&allocation));
+        assert!(add(&mut blockchain, tx(end, 2, 0)));
+        assert_eq!(balances(&blockchain), vec![FAUCET_AMOUNT + 86, FAUCET_AMOUNT - 74, FAUCET_AMOUNT - 12]);
+        for topic in ["chair", "table"] {
+            let lot = blockchain.auction(topic).unwrap();
+            assert_eq!((lot.phase, lot.bundle.as_deref()), (AuctionPhase::Settled, Some("set")));
+            assert_eq!(lot.allocation, allocation);
+        }
+    }
+
+    #[test]
+    fn reputation_test() {
+        let keys: Vec<Identity> = (7..11).map(|seed| Identity::from_seed(&[seed; 32])).collect();
+        let addr = |n: usize| format!("127.0.0.1:{}", n);
+        let tx = |msg: String, msg_type: usize, signer: usize| {
+            Data::new(msg, msg_type, Some(format!("{}", chrono::Local::now() + chrono::Duration::hours(1)))).sign(&keys[signer - 1]).to_json()
+        };
+        let feedback = |topic: &str, rater: usize, rated: usize, positive: bool| {
+            tx(feedback_msg(topic, &addr(rater), &addr(rated), positive), FEEDBACK_MSG_TYPE, rater)
+        };
+        let mut blockchain = Blockchain::new();
+        blockchain.genesis();
+        let add = |blockchain: &mut Blockchain, data: String| {
+            let prev = blockchain.blocks.last().unwrap().clone();
+            blockchain.add_block(Block::new(&prev, data))
+        };
+        for n in 1..5 {
+            assert!(add(&mut blockchain, tx(format!("REGISTER: {}", addr(n)), 0, n)));
+        }
+
+        // paid settlement is a trade of both, rated once by each counterparty
+        assert!(add(&mut blockchain, tx(format!("PUB_TOPIC: car|PUBLISHER: {}", addr(1)), 1, 1)));
+        assert!(!add(&mut blockchain, feedback("car", 1, 2, true)));
+        assert!(add(&mut blockchain, tx(escrow_msg("car", &addr(2), 300), ESCROW_MSG_TYPE, 2)));
+        assert!(add(&mut blockchain, tx(format!("END_TOPIC: car|BID: 300|BIDDER: \"{}\"", addr(2)), 2, 1)));
+        assert!(!add(&mut blockchain, feedback("car", 3, 1, false)));
+        assert!(!add(&mut blockchain, tx(feedback_msg("car", &addr(1), &addr(2), true), FEEDBACK_MSG_TYPE, 2)));
+        assert!(!add(&mut blockchain, tx(format!("FEEDBACK: car|RATER: {}|RATED: {}|RATING: 2", addr(1), addr(2)), FEEDBACK_MSG_TYPE, 1)));
+        assert!(add(&mut blockchain, feedback("car", 1, 2, true)));
+        assert!(!add(&mut blockchain, feedback("car", 1, 2, false)));
+        assert!(add(&mut blockchain, feedback("car", 2, 1, false)));
+        assert_eq!(blockchain.reputation(&addr(1)), Some(Reputation { trades: 1, unpaid: 0, positive: 0, negative: 1 }));
+        assert_eq!(blockchain.reputation(&addr(2)).map(|reputation| reputation.score()), Some(1));
+
+        // settlements follow the escrowed bids, a winner without one is no trade
+        assert!(add(&mut blockchain, tx(format!("PUB_TOPIC: bike|PUBLISHER: {}", addr(1)), 1, 1)));
+        assert!(!add(&mut blockchain, tx(format!("END_TOPIC: bike|BID: 5000|BIDDER: \"{}\"", addr(3)), 2, 1)));
+        assert!(add(&mut blockchain, tx(String::from("END_TOPIC: bike|BID: null|BIDDER: null"), 2, 1)));
+        assert_eq!(blockchain.reputation(&addr(3)), Some(Reputation::default()));
+        assert_eq!(blockchain.balance(&addr(3)), Some(FAUCET_AMOUNT));
+
+        // reputation of the registered key, self and free settlements are no trade
+        assert!(add(&mut blockchain, tx(format!("REGISTER: {}", addr(5)), 0, 2)));
+        assert_eq!(blockchain.reputation(&addr(5)), blockchain.reputation(&addr(2)));
+        assert!(add(&mut blockchain, tx(format!("REGISTER: {}", addr(6)), 0, 1)));
+        assert!(add(&mut blockchain, tx(format!("PUB_TOPIC: lamp|PUBLISHER: {}", addr(1)), 1, 1)));
+        assert!(add(&mut blockchain, tx(format!("PUB_TOPIC: pen|PUBLISHER: {}", addr(1)), 1, 1)));
+        assert!(add(&mut blockchain, tx(escrow_msg("lamp", &addr(6), 0), ESCROW_MSG_TYPE, 1)));
+        assert!(add(&mut blockchain, tx(escrow_msg("pen", &addr(3), 0), ESCROW_MSG_TYPE, 3)));
+        assert!(add(&mut blockchain, tx(format!("END_TOPIC: lamp|BID: 0|BIDDER: \"{}\"", addr(6)), 2, 1)));
+        assert!(add(&mut blockchain, tx(format!("END_TOPIC: pen|BID: 0|BIDDER: \"{}\"", addr(3)), 2, 1)));
+        assert!(!add(&mut blockchain, feedback("pen", 1, 3, true)));
+        assert_eq!(blockchain.reputation(&addr(1)).map(|reputation| reputation.trades), Some(1));
+        assert_eq!(blockchain.reputation(&addr(3)), Some(Reputation::default()));
+        let mut state = blockchain.state_at(blockchain.tip().height).unwrap();
+        state.record_trade("lamp", &addr(1), &addr(6), 100, 100);
+        state.record_trade("lamp", &addr(1), &addr(3), 100, 0);
+        assert_eq!(state.reputation(&addr(1)).trades, 1);
+        assert_eq!(state.reputation(&addr(3)), Reputation { trades: 0, unpaid: 1, positive: 0, negative: 0 });
+
+        // bid policy of the spec enforced on escrows
+        let policy = BidPolicy { min_score: Some(0), min_trades: 1, max_unpaid: None };
+        assert!(!policy.allows(&Reputation::default()));
+        assert!(BidPolicy { min_trades: 0, ..policy.clone() }.allows(&Reputation::default()));
+        let mut spec = AuctionSpec::new("vase", chrono::Local::now() + chrono::Duration::minutes(15));
+        spec.policy = policy;
+        assert!(add(&mut blockchain, tx(format!("PUB_TOPIC: vase|PUBLISHER: {}|SPEC: {}", addr(1), spec.encode()), 1, 1)));
+        assert!(!add(&mut blockchain, tx(escrow_msg("vase", &addr(4), 100), ESCROW_MSG_TYPE, 4)));
+        assert!(add(&mut blockchain, tx(escrow_msg("vase", &addr(2), 200), ESCROW_MSG_TYPE, 2)));
+    }
+
     #[test]
     fn pubsub_test() {
         let boot = Bootstrap::new();
@@ -403,7 +2022,7 @@ mod tests {
         let get_appnode = appnode.kademlia.get(String::from("test")).unwrap();
         println!("AppNode GET: {:?}", decode_data(get_appnode));
 
-        let addmsg_appnode = appnode.add_msg(String::from("test"), String::from("testmsg"));
+        let addmsg_appnode = appnode.add_msg(String::from("test"), String::from("testmsg"), None);
         println!("AppNode SendMsg test: {}", addmsg_appnode);
         let get_appnode = appnode.kademlia.get(String::from("test")).unwrap();
         println!("AppNode GET: {:?}", decode_data(get_appnode));
@@ -446,13 +2065,13 @@ mod tests {
         appnode0.subscribe(String::from("test"));
         appnode2.subscribe(String::from("test"));
 
-        let _addmsg_appnode0 = appnode0.add_msg(String::from("test"), String::from("testmsg:APPNODE0"));
+        let _addmsg_appnode0 = appnode0.add_msg(String::from("test"), String::from("testmsg:APPNODE0"), None);
         //println!("AppNode SendMsg test: {}", addmsg_appnode0);
 
-        let _addmsg_appnode1 = appnode1.add_msg(String::from("test"), String::from("testmsg:APPNODE1"));
+        let _addmsg_appnode1 = appnode1.add_msg(String::from("test"), String::from("testmsg:APPNODE1"), None);
         //println!("AppNode SendMsg test: {}", addmsg_appnode1);
 
-        let _addmsg_appnode2 = appnode2.add_msg(String::from("test"), String::from("testmsg:APPNODE2"));
+        let _addmsg_appnode2 = appnode2.add_msg(String::from("test"), String::from("testmsg:APPNODE2"), None);
         //println!("AppNode SendMsg test: {}", addmsg_appnode2);
 
         println!();
@@ -723,7 +2342,7 @@ mod tests {
     }    
 
     fn decode_data(data: String) -> String {
-        String::from_utf8(decode(data.to_string()).expect("Error decoding data"))
-                .expect("Error converting data to string")
+        let pubsub = PubSubInstance::decode(&data).expect("Error decoding data");
+        format!("{:?}", pubsub.to_record())
     }
 }
\ No newline at end of file
@@ -0,0 +1,316 @@
+use super::blockchain::{Blockchain, Block, BlockHeader, calc_hash, locator_indexes, valid_timestamp, MAX_HEADERS};
+use super::consensus::{ConsensusEngine, ProofOfWork, engine_from_genesis};
+use super::finality::{Checkpoint, Finality};
+use super::explorer::{TxEntry, TxRef};
+use super::kademlia::KademliaInstance;
+use super::merkle::{MerklePath, merkle_path, verify_path};
+use super::node::Node;
+use super::rpc::fit_payload;
+
+use serde::{Serialize, Deserialize};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+
+/**
+ * Proof query:
+ *  - Tx: transaction by hash.
+ *  - Topic: events (publish/end) of an auction topic after a cursor.
+ *  - Type: transactions of a msg_type after a cursor.
+ *  - Address: transactions mentioning a node address after a cursor.
+ *
+ *  Answers are capped by size, paginated queries are resumed
+ *  after the last proof (see LightClient::pull_proofs).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ProofQuery {
+    Tx(String),
+    Topic(String, TxRef),
+    Type(usize, TxRef),
+    Address(String, TxRef),
+}
+
+impl ProofQuery {
+    pub fn cursor(&self) -> Option<TxRef> {
+        match self {
+            ProofQuery::Tx(_) => None,
+            ProofQuery::Topic(_, cursor) | ProofQuery::Type(_, cursor) | ProofQuery::Address(_, cursor) => Some(*cursor)
+        }
+    }
+
+    // Next page (after cursor), None if not paginated or cursor did not move forward
+    pub fn after(&self, cursor: TxRef) -> Option<Self> {
+        if self.cursor().is_none_or(|from| cursor <= from) {
+            return None
+        }
+        match self {
+            ProofQuery::Tx(_) => None,
+            ProofQuery::Topic(topic, _) => Some(ProofQuery::Topic(topic.clone(), cursor)),
+            ProofQuery::Type(msg_type, _) => Some(ProofQuery::Type(*msg_type, cursor)),
+            ProofQuery::Address(addr, _) => Some(ProofQuery::Address(addr.clone(), cursor)),
+        }
+    }
+}
+
+/**
+ * Transaction proof:
+ *  Transaction with its merkle path to the root of
+ *  block (block_id, block_hash) header.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TxProof {
+    pub block_id: u64,
+    pub block_hash: String,
+    pub index: usize,
+    pub tx: String,
+    pub path: MerklePath,
+}
+
+/**
+ * MERKLE PROOFS (full nodes)
+**/
+impl Blockchain {
+    // Entries answering query (unbounded, proofs are capped by size)
+    pub fn query_entries(&self, query: &ProofQuery) -> Vec<TxEntry> {
+        let entries = match query {
+            ProofQuery::Tx(hash) => return self.tx_by_hash(hash).into_iter().collect(),
+            ProofQuery::Topic(topic, _) => self.topic_events(topic),
+            ProofQuery::Type(msg_type, _) => self.txs_by_type(*msg_type),
+            ProofQuery::Address(addr, _) => self.address_activity(addr),
+        };
+        entries.into_iter()
+            .filter(|entry| query.cursor().is_none_or(|cursor| (entry.block_id, entry.index) > cursor))
+            .collect()
+    }
+
+    // Proofs of the first entries (as many as fit a response)
+    pub fn tx_proofs(&self, query: &ProofQuery) -> Vec<TxProof> {
+        let proofs = self.query_entries(query).into_iter()
+            .filter_map(|entry| {
+                let block = self.block_by_id(entry.block_id)?;
+                Some(TxProof {
+                    block_id: entry.block_id,
+                    block_hash: entry.block_hash.clone(),
+                    index: entry.index,
+                    tx: entry.data,
+                    path: merkle_path(&block.transactions(), entry.index)?,
+                })
+            });
+        fit_payload(proofs)
+    }
+}
+
+/**
+ * Header chain:
+ *  Headers from genesis to tip (no bodies), the consensus
+ *  engine is selected by the genesis block (only body fetched).
+**/
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    pub headers: Vec<BlockHeader>,
+    pub consensus: Arc<dyn ConsensusEngine>,
+    pub finality: Finality,
+}
+
+impl Default for HeaderChain {
+    fn default() -> Self {
+        Self { headers: Vec::new(), consensus: Arc::new(ProofOfWork), finality: Finality::default() }
+    }
+}
+
+impl HeaderChain {
+    pub fn locator(&self) -> Vec<String> {
+        locator_indexes(self.headers.len()).iter()
+            .map(|index| self.headers[*index].hash.clone())
+            .collect()
+    }
+
+    pub fn tip(&self) -> Option<&BlockHeader> {
+        self.headers.last()
+    }
+
+    pub fn is_header_valid(&self, header: &BlockHeader, prev_header: &BlockHeader) -> bool {
+        header.prev_hash == prev_header.hash
+            && header.id == prev_header.id + 1
+            && hex::encode(calc_hash(header.id, header.timestamp, &header.prev_hash, &header.merkle_root, header.nonce)) == header.hash
+            && valid_timestamp(header, prev_header)
+            && self.consensus.verify_seal(header)
+            && self.consensus.verify_turn(header, prev_header)
+    }
+
+    /*
+        Apply headers (suffix of a remote chain):
+            Attached to their parent, replacing our suffix if
+            the result is preferred (same rules as Blockchain).
+            Returns the number of applied headers.
+    */
+    pub fn apply_headers(&mut self, headers: Vec<BlockHeader>) -> usize {
+        let first = match headers.first() {
+            Some(header) => header,
+            None => return 0
+        };
+        let parent = match self.headers.iter().rposition(|header| header.hash == first.prev_hash) {
+            Some(index) => index,
+            None => return 0
+        };
+
+        let mut candidate = self.headers[..=parent].to_vec();
+        for header in &headers {
+            if !self.is_header_valid(header, candidate.last().expect("At least one header")) {
+                return 0
+            }
+            candidate.push(header.clone());
+        }
+        let checkpointed = self.finality.allows(|height| candidate.get(height as usize).map(|header| header.hash.clone()));
+        let work = |headers: &[BlockHeader]| headers.iter().map(|header| self.consensus.work(header)).sum::<u64>();
+        if checkpointed && work(&candidate) > work(&self.headers) {
+            self.headers = candidate;
+            return headers.len()
+        }
+        0
+    }
+
+    pub fn finalized_height(&self) -> u64 {
+        self.finality.finalized_height(self.tip().map_or(0, |header| header.id))
+    }
+
+    pub fn is_final(&self, id: u64, hash: &str) -> bool {
+        id <= self.finalized_height()
+            && self.headers.get(id as usize).map(|header| header.hash.as_str()) == Some(hash)
+    }
+
+    pub fn add_checkpoint(&mut self, checkpoint: Checkpoint) -> bool {
+        let hash = self.headers.get(checkpoint.height as usize).map(|header| header.hash.clone());
+        self.finality.accept(checkpoint, hash.as_deref())
+    }
+
+    // Proof is valid for a header in our chain
+    pub fn verify_proof(&self, proof: &TxProof) -> bool {
+        match self.headers.get(proof.block_id as usize) {
+            Some(header) => header.hash == proof.block_hash
+                && verify_path(&proof.tx, &proof.path, &header.merkle_root),
+            None => false
+        }
+    }
+}
+
+/**
+ * Light client:
+ *  Keeps only block headers (verified seal and links),
+ *  transactions of interest are requested from full nodes
+ *  along with a merkle proof against a verified header.
+**/
+#[derive(Debug, Clone)]
+pub struct LightClient {
+    pub kademlia: KademliaInstance,
+    pub chain: Arc<Mutex<HeaderChain>>,
+}
+
+impl LightClient {
+    pub fn new(kademlia: KademliaInstance) -> Self {
+        kademlia.headers_only.store(true, Ordering::Relaxed);
+        Self { kademlia, chain: Arc::new(Mutex::new(HeaderChain::default())) }
+    }
+
+    pub fn consensus(&self) -> Arc<dyn ConsensusEngine> {
+        let chain = self.chain.lock()
+            .expect("Error setting lock in header chain");
+        let consensus = chain.consensus.clone();
+        drop(chain);
+        consensus
+    }
+
+    pub fn tip(&self) -> Option<BlockHeader> {
+        let chain = self.chain.lock()
+            .expect("Error setting lock in header chain");
+        let tip = chain.tip().cloned();
+        drop(chain);
+        tip
+    }
+
+    /*
+        Sync headers from node:
+            Genesis (header and body) is fetched first, it
+            selects the consensus engine, then headers after
+            our locator until node has no more headers for us.
+            Returns the number of applied headers.
+    */
+    pub fn sync(&self, qynode: Node) -> usize {
+        let mut res = 0;
+        loop {
+            let chain = self.chain.lock()
+                .expect("Error setting lock in header chain");
+            let locator = chain.locator();
+            drop(chain);
+
+            let mut headers = match self.kademlia.query_headers(qynode.clone(), locator) {
+                Some(headers) => headers,
+                None => break
+            };
+            let len = headers.len();
+
+            let mut chain = self.chain.lock()
+                .expect("Error setting lock in header chain");
+            if chain.headers.is_empty() && !headers.is_empty() {
+                let genesis = headers.remove(0);
+                let body = self.kademlia.query_blocks(qynode.clone(), vec![genesis.hash.clone()]);
+                match body.and_then(|mut blocks| blocks.pop()) {
+                    Some(block) if block.id == 0 && block.hash == genesis.hash => {
+                        chain.consensus = engine_from_genesis(&block);
+                        chain.headers.push(genesis);
+                    },
+                    _ => break
+                }
+            }
+            let applied = chain.apply_headers(headers);
+            drop(chain);
+
+            res += applied;
+            if applied == 0 || len < MAX_HEADERS {
+                break
+            }
+        }
+        res
+    }
+
+    // Verified proofs (invalid ones are dropped)
+    pub fn query_proofs(&self, qynode: Node, query: ProofQuery) -> Vec<TxProof> {
+        let proofs = self.kademlia.query_proofs(qynode, query).unwrap_or_default();
+        let chain = self.chain.lock()
+            .expect("Error setting lock in header chain");
+        let res = proofs.into_iter()
+            .filter(|proof| chain.verify_proof(proof))
+            .collect();
+        drop(chain);
+        res
+    }
+
+    // Every (verified) proof answering query, pages are followed until exhausted
+    pub fn pull_proofs(&self, qynode: Node, mut query: ProofQuery) -> Vec<TxProof> {
+        let mut res: Vec<TxProof> = Vec::new();
+        loop {
+            let proofs = self.query_proofs(qynode.clone(), query.clone());
+            let next = proofs.last().and_then(|proof| query.after((proof.block_id, proof.index)));
+            res.extend(proofs);
+            match next {
+                Some(next) => query = next,
+                None => break
+            }
+        }
+        res
+    }
+
+    // Every (verified) transaction of msg_type after cursor, cursor is moved forward
+    pub fn pull_txs(&self, qynode: Node, msg_type: usize, cursor: &mut TxRef) -> Vec<TxProof> {
+        let res = self.pull_proofs(qynode, ProofQuery::Type(msg_type, *cursor));
+        if let Some(proof) = res.last() {
+            *cursor = (proof.block_id, proof.index);
+        }
+        res
+    }
+
+    // Seal block template on top of our tip header (added once a full node accepts it)
+    pub fn mine_next(&self, data: String) -> Option<Block> {
+        let tip = self.tip()?;
+        self.consensus().seal(&self.kademlia.miner, &self.kademlia.identity, &tip, data)
+    }
+}
@@ -1,10 +1,13 @@
 extern crate kad;
 use kad::bootstrap::Bootstrap;
 
-//  RUST_LOG=info cargo r
+//  RUST_LOG=info cargo r [poa]
 fn main() {
     env_logger::init();
-    let boot = Bootstrap::new();
+    let boot = match std::env::args().nth(1).as_deref() {
+        Some("poa") => Bootstrap::new_poa(),
+        _ => Bootstrap::new()
+    };
     Bootstrap::full_bk_sync(boot.clone());
     loop {}
 }
\ No newline at end of file
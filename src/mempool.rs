@@ -0,0 +1,133 @@
+use super::blockchain::Block;
+use super::bootstrap::Data;
+
+use sha2::{Sha256, Digest};
+use serde::{Serialize, Deserialize};
+use chrono::prelude::*;
+use std::collections::HashMap;
+
+// Max number of transactions packed in a single block template
+pub const MAX_BLOCK_TXS: usize = 16;
+
+// Seconds a hash stays seen once mined (or last gossiped to us)
+pub const SEEN_TTL: i64 = 600;
+
+/**
+ * Transaction:
+ *  Ledger entry waiting to be mined, data holds the
+ *  serialized (json) Data record and hash is used
+ *  to deduplicate gossiped transactions.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub hash: String,
+    pub data: String,
+    pub timestamp: i64,
+}
+
+impl Transaction {
+    pub fn new(data: String) -> Self {
+        Self {
+            hash: tx_hash(&data),
+            data,
+            timestamp: Utc::now().timestamp(),
+        }
+    }
+
+    // Hash matches data and data is a signed record
+    pub fn is_valid(&self) -> bool {
+        tx_hash(&self.data) == self.hash
+            && Data::from_json(&self.data).is_some_and(|data| data.verify())
+    }
+}
+
+pub fn tx_hash(data: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/**
+ * Mempool:
+ *  Pending transactions (by hash) of a kademlia instance.
+ *  Seen holds hashes recently accepted or mined (-> time),
+ *  thus a transaction gossiped back to us is dropped. Seen
+ *  entries expire SEEN_TTL after being mined or last seen
+ *  (never while pending), so a repeated record is accepted.
+**/
+#[derive(Debug, Clone, Default)]
+pub struct Mempool {
+    pub pending: HashMap<String, Transaction>,
+    pub seen: HashMap<String, i64>,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self { pending: HashMap::new(), seen: HashMap::new() }
+    }
+
+    // Returns true if transaction is new (should be gossiped)
+    pub fn add(&mut self, tx: Transaction) -> bool {
+        let now = Utc::now().timestamp();
+        self.expire(now);
+        if !tx.is_valid() || self.seen.contains_key(&tx.hash) {
+            return false
+        }
+        self.seen.insert(tx.hash.clone(), now);
+        self.pending.insert(tx.hash.clone(), tx);
+        true
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.seen.contains_key(hash)
+    }
+
+    // Forget seen hashes older than SEEN_TTL (pending ones are kept)
+    pub fn expire(&mut self, now: i64) {
+        let pending = &self.pending;
+        self.seen.retain(|hash, seen| pending.contains_key(hash) || *seen + SEEN_TTL > now);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    // Drop pending transactions already included in the given blocks
+    pub fn prune(&mut self, blocks: &[Block]) {
+        let now = Utc::now().timestamp();
+        for block in blocks {
+            for data in block.transactions() {
+                let hash = tx_hash(&data);
+                self.pending.remove(&hash);
+                self.seen.insert(hash, now);
+            }
+        }
+        self.expire(now);
+    }
+
+    /*
+        Block template:
+            Oldest pending transactions (at most MAX_BLOCK_TXS),
+            serialized as the data of the next block. Ties are
+            ordered by msg_type, thus a REGISTER precedes the
+            PUB_TOPIC of the same node (validated in order).
+    */
+    pub fn block_template(&self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None
+        }
+        let mut txs: Vec<&Transaction> = self.pending.values().collect();
+        txs.sort_by_cached_key(|tx| {
+            let msg_type = Data::from_json(&tx.data).map_or(0, |data| data.msg_type());
+            (tx.timestamp, msg_type, tx.hash.clone())
+        });
+        txs.truncate(MAX_BLOCK_TXS);
+
+        let data: Vec<String> = txs.iter().map(|tx| tx.data.clone()).collect();
+        Some(serde_json::to_string(&data).expect("Error serializing block template"))
+    }
+}
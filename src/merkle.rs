@@ -0,0 +1,97 @@
+use super::mempool::tx_hash;
+
+use sha2::{Digest, Sha256};
+
+/**
+ * Merkle tree:
+ *  Leaves are tagged hashes of the transaction hashes, each
+ *  parent is the tagged hash of its (hex) children concatenated,
+ *  an odd node is paired with itself. Tags keep a leaf from
+ *  being passed as an internal node (and vice versa). The root
+ *  is part of the block header, thus a transaction is proven
+ *  with log2(n) sibling hashes.
+ *  Pairing an odd node with itself lets [a, b, c] and
+ *  [a, b, c, c] share a root (CVE-2012-2459), blocks whose
+ *  tree has an identical pair are rejected (see merkle_mutated).
+**/
+const LEAF_TAG: u8 = 0x00;
+const NODE_TAG: u8 = 0x01;
+
+fn hash_leaf(tx: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_TAG]);
+    hasher.update(tx_hash(tx).as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+fn hash_pair(left: &str, right: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_TAG]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+fn next_level(level: &[String]) -> Vec<String> {
+    level.chunks(2)
+        .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+        .collect()
+}
+
+// Merkle path: (sibling hash, sibling is on the left)
+pub type MerklePath = Vec<(String, bool)>;
+
+pub fn merkle_root(txs: &[String]) -> String {
+    let mut level: Vec<String> = txs.iter().map(|tx| hash_leaf(tx)).collect();
+    if level.is_empty() {
+        return hash_leaf("")
+    }
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.remove(0)
+}
+
+// Some level of the tree holds an identical (duplicated) pair
+pub fn merkle_mutated(txs: &[String]) -> bool {
+    let mut level: Vec<String> = txs.iter().map(|tx| hash_leaf(tx)).collect();
+    while level.len() > 1 {
+        if level.chunks(2).any(|pair| pair.len() == 2 && pair[0] == pair[1]) {
+            return true
+        }
+        level = next_level(&level);
+    }
+    false
+}
+
+pub fn merkle_path(txs: &[String], mut index: usize) -> Option<MerklePath> {
+    if index >= txs.len() {
+        return None
+    }
+    let mut level: Vec<String> = txs.iter().map(|tx| hash_leaf(tx)).collect();
+    let mut path = Vec::new();
+    while level.len() > 1 {
+        let sibling = if index.is_multiple_of(2) {
+            (level.get(index + 1).unwrap_or(&level[index]).clone(), false)
+        } else {
+            (level[index - 1].clone(), true)
+        };
+        path.push(sibling);
+        level = next_level(&level);
+        index /= 2;
+    }
+    Some(path)
+}
+
+// Root obtained by hashing tx up the given path
+pub fn verify_path(tx: &str, path: &MerklePath, root: &str) -> bool {
+    let mut hash = hash_leaf(tx);
+    for (sibling, is_left) in path {
+        hash = if *is_left {
+            hash_pair(sibling, &hash)
+        } else {
+            hash_pair(&hash, sibling)
+        };
+    }
+    hash == root
+}
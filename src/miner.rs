@@ -0,0 +1,158 @@
+use super::blockchain::{Block, BlockHeader, calc_hash, meets_difficulty, next_timestamp, parse_transactions};
+use super::merkle::merkle_root;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread::spawn;
+use std::time::Instant;
+use crossbeam_channel;
+
+// Hashes computed by a worker before updating the job counter
+const HASH_BATCH: u64 = 10000;
+
+// (height, stop flag) of a mining job
+type MiningJob = (u64, Arc<AtomicBool>);
+
+/**
+ * Miner:
+ *  Mines block templates (proof-of-work) on a pool of threads,
+ *  each thread tests nonces offset by its index, stepping by
+ *  the number of threads. Jobs are aborted when a block at
+ *  the same (or higher) height is added to the chain, thus
+ *  the chain lock is never held while hashing.
+ *
+ *  - threads: size of the pool (configurable at runtime).
+ *  - jobs: (height, stop flag) of every job being mined.
+ *  - stale: jobs below this height are aborted (highest abort + 1).
+ *  - last_job: (hashes, elapsed us) of the last finished job,
+ *    each job counts its own hashes (jobs may run concurrently).
+**/
+#[derive(Debug, Clone)]
+pub struct Miner {
+    pub threads: Arc<AtomicUsize>,
+    pub jobs: Arc<Mutex<Vec<MiningJob>>>,
+    pub stale: Arc<AtomicU64>,
+    pub last_job: Arc<Mutex<(u64, u64)>>,
+}
+
+impl Default for Miner {
+    fn default() -> Self {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::new(threads)
+    }
+}
+
+impl Miner {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: Arc::new(AtomicUsize::new(threads.max(1))),
+            jobs: Arc::new(Mutex::new(Vec::new())),
+            stale: Arc::new(AtomicU64::new(0)),
+            last_job: Arc::new(Mutex::new((0, 0))),
+        }
+    }
+
+    pub fn set_threads(&self, threads: usize) {
+        self.threads.store(threads.max(1), Ordering::Relaxed);
+    }
+
+    /*
+        Mine block template:
+            Returns None if job was aborted (competing block),
+            or its height was already aborted, otherwise the
+            mined block.
+    */
+    pub fn mine(&self, prev: &BlockHeader, data: String) -> Option<Block> {
+        let threads = self.threads.load(Ordering::Relaxed);
+        let (id, prev_hash) = (prev.id + 1, prev.hash.clone());
+        let timestamp = next_timestamp(prev);
+        let root = merkle_root(&parse_transactions(&data));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let mut jobs = self.jobs.lock()
+            .expect("Error setting lock in miner jobs");
+        if id < self.stale.load(Ordering::Relaxed) {
+            return None
+        }
+        jobs.push((id, stop.clone()));
+        drop(jobs);
+
+        let hashes = Arc::new(AtomicU64::new(0));
+        let start = Instant::now();
+        let (sender_ch, receiver_ch) = crossbeam_channel::bounded(threads);
+
+        for offset in 0..threads {
+            let sender_ch = sender_ch.clone();
+            let stop = stop.clone();
+            let hashes = hashes.clone();
+            let prev_hash = prev_hash.clone();
+            let root = root.clone();
+            spawn(move || {
+                let mut nonce = offset as u64;
+                let mut count: u64 = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let hash = calc_hash(id, timestamp, &prev_hash, &root, nonce);
+                    count += 1;
+                    if meets_difficulty(&hash) {
+                        stop.store(true, Ordering::Relaxed);
+                        let _ = sender_ch.send((nonce, hex::encode(&hash)));
+                        break
+                    }
+                    if count.is_multiple_of(HASH_BATCH) {
+                        hashes.fetch_add(HASH_BATCH, Ordering::Relaxed);
+                    }
+                    nonce += threads as u64;
+                }
+                hashes.fetch_add(count % HASH_BATCH, Ordering::Relaxed);
+            });
+        }
+        drop(sender_ch);
+
+        // Err -> every worker stopped without a result (aborted)
+        let res = receiver_ch.recv().ok();
+        // wait for every worker to count its hashes
+        for _ in receiver_ch.iter() {}
+        let mut last_job = self.last_job.lock()
+            .expect("Error setting lock in miner stats");
+        *last_job = (hashes.load(Ordering::Relaxed), start.elapsed().as_micros() as u64);
+        drop(last_job);
+
+        let mut jobs = self.jobs.lock()
+            .expect("Error setting lock in miner jobs");
+        jobs.retain(|(_, job_stop)| !Arc::ptr_eq(job_stop, &stop));
+        drop(jobs);
+
+        res.map(|(nonce, hash)| Block { id, hash, prev_hash, timestamp, data, merkle_root: root, nonce, signature: String::new(), pruned: false })
+    }
+
+    // Abort jobs mining at height (or lower), since their parent is stale
+    pub fn abort(&self, height: u64) {
+        let jobs = self.jobs.lock()
+            .expect("Error setting lock in miner jobs");
+        self.stale.fetch_max(height + 1, Ordering::Relaxed);
+        for (job_height, stop) in jobs.iter() {
+            if *job_height <= height {
+                stop.store(true, Ordering::Relaxed);
+            }
+        }
+        drop(jobs)
+    }
+
+    pub fn is_mining(&self) -> bool {
+        let jobs = self.jobs.lock()
+            .expect("Error setting lock in miner jobs");
+        !jobs.is_empty()
+    }
+
+    // Hash rate (H/s) of the last job
+    pub fn hash_rate(&self) -> f64 {
+        let last_job = self.last_job.lock()
+            .expect("Error setting lock in miner stats");
+        let (hashes, elapsed_us) = *last_job;
+        drop(last_job);
+        if elapsed_us == 0 {
+            return 0.0
+        }
+        hashes as f64 * 1_000_000.0 / elapsed_us as f64
+    }
+}
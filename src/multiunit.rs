@@ -0,0 +1,157 @@
+use super::auction::Bid;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Serialize, Deserialize};
+use std::cmp::Reverse;
+
+// Bundle bids (highest first) searched by winner determination
+pub const MAX_BUNDLE_BIDS: usize = 16;
+
+/**
+ * Pricing of a multi-unit auction:
+ *  - Uniform: every winner pays the lowest accepted unit price.
+ *  - Discriminatory: every winner pays its own unit price.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pricing {
+    #[default]
+    Uniform,
+    Discriminatory,
+}
+
+/**
+ * Allocation (winner determination, recorded by END_TOPIC):
+ *  Units (quantity) or lots (items, topics of a bundle) won
+ *  by bidder, price is what it pays for all of them.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    pub bidder: String,
+    pub quantity: usize,
+    #[serde(default)]
+    pub items: Vec<String>,
+    pub price: usize,
+}
+
+// Base64 of its json, kept clear of the msg separators (see AuctionSpec::encode)
+pub fn allocation_msg(allocation: &[Allocation]) -> String {
+    STANDARD.encode(serde_json::to_string(allocation).unwrap())
+}
+
+pub fn parse_allocation(value: &str) -> Option<Vec<Allocation>> {
+    serde_json::from_slice(&STANDARD.decode(value).ok()?).ok()
+}
+
+// (revenue, first winner) of an allocation
+pub fn allocation_outcome(allocation: &[Allocation]) -> Option<(usize, String)> {
+    let first = allocation.first()?;
+    Some((allocation.iter().map(|won| won.price).sum(), first.bidder.clone()))
+}
+
+/*
+    Standing bids: a new bid of a bidder replaces its previous
+    one (of the same items for bundles), ordered by the time of
+    their last bid (earliest wins ties).
+*/
+fn standing(bids: &[Bid]) -> Vec<&Bid> {
+    let key = |bid: &Bid| {
+        let mut items = bid.items.clone();
+        items.sort();
+        (bid.sender_addr.clone(), items)
+    };
+    let mut res: Vec<&Bid> = Vec::new();
+    for bid in bids {
+        res.retain(|prev| key(prev) != key(bid));
+        res.push(bid);
+    }
+    res
+}
+
+/*
+    Units a bidder may owe: the total of its standing bids
+    (locked by its escrows, see LedgerState::lock).
+*/
+pub fn committed(bids: &[Bid], bidder: &str) -> u64 {
+    standing(bids).into_iter()
+        .filter(|bid| bid.sender_addr == bidder)
+        .map(|bid| bid.total() as u64)
+        .sum()
+}
+
+// Bid on a set of (distinct) bundled topics, at least reserve per topic
+pub fn verify_bundle(topics: &[String], reserve: usize, bid: &Bid) -> bool {
+    let distinct = bid.items.iter().enumerate().all(|(i, item)| !bid.items[..i].contains(item));
+    bid.quantity == 1
+        && !bid.items.is_empty()
+        && distinct
+        && bid.items.iter().all(|item| topics.contains(item))
+        && bid.data >= reserve.saturating_mul(bid.items.len())
+}
+
+/*
+    Multi-unit winner determination:
+        Standing bids by unit price (highest first) are filled
+        until units run out, the last one may be filled in part.
+*/
+pub fn allocate_units(units: usize, pricing: Pricing, bids: &[Bid]) -> Vec<Allocation> {
+    let mut ranked = standing(bids);
+    ranked.sort_by_key(|bid| Reverse(bid.data));
+    let mut left = units;
+    let mut res: Vec<Allocation> = Vec::new();
+    for bid in ranked {
+        if left == 0 {
+            break
+        }
+        let quantity = bid.quantity.min(left);
+        left -= quantity;
+        res.push(Allocation { bidder: bid.sender_addr.clone(), quantity, items: Vec::new(), price: bid.data });
+    }
+    let clearing = res.last().map_or(0, |won| won.price);
+    for won in res.iter_mut() {
+        let unit = match pricing {
+            Pricing::Uniform => clearing,
+            Pricing::Discriminatory => won.price,
+        };
+        won.price = unit.saturating_mul(won.quantity);
+    }
+    res
+}
+
+/*
+    Bundle winner determination:
+        Disjoint standing bids of the highest revenue, found by
+        a search over the MAX_BUNDLE_BIDS highest bids (pruned
+        once the remaining bids can not beat the best set).
+        Every winner pays its bid, ties go to higher ranked bids.
+*/
+pub fn allocate_bundles(bids: &[Bid]) -> Vec<Allocation> {
+    let mut ranked = standing(bids);
+    ranked.sort_by_key(|bid| Reverse(bid.data));
+    ranked.truncate(MAX_BUNDLE_BIDS);
+    let mut best = (0, Vec::new());
+    search(&ranked, 0, &mut Vec::new(), 0, &mut best);
+    best.1.into_iter()
+        .map(|i| {
+            let bid = ranked[i];
+            Allocation { bidder: bid.sender_addr.clone(), quantity: 1, items: bid.items.clone(), price: bid.data }
+        })
+        .collect()
+}
+
+fn search(bids: &[&Bid], index: usize, chosen: &mut Vec<usize>, revenue: usize, best: &mut (usize, Vec<usize>)) {
+    if revenue > best.0 {
+        *best = (revenue, chosen.clone());
+    }
+    let left: usize = bids[index..].iter().map(|bid| bid.data).sum();
+    if revenue + left <= best.0 {
+        return
+    }
+    for i in index..bids.len() {
+        let taken = chosen.iter().any(|j| bids[*j].items.iter().any(|item| bids[i].items.contains(item)));
+        if !taken {
+            chosen.push(i);
+            search(bids, i + 1, chosen, revenue + bids[i].data, best);
+            chosen.pop();
+        }
+    }
+}
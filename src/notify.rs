@@ -0,0 +1,204 @@
+use super::kademlia::KademliaInstance;
+use super::identity::{Identity, verify};
+use super::node::Node;
+use super::rpc::{timed_rpc_proc, KademliaRequest, KademliaResponse};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+// Attempts to deliver a notification before the subscriber is given up on
+pub const NOTIFY_RETRIES: usize = 3;
+
+// Seconds to wait for a subscriber to acknowledge a notification
+pub const NOTIFY_TIMEOUT: u64 = 2;
+
+// Ids of delivered notifications remembered by an inbox (oldest forgotten first)
+pub const INBOX_SEEN: usize = 1024;
+
+// Notifications an event channel holds until read (later ones are dropped)
+pub const INBOX_CAPACITY: usize = 256;
+
+/**
+ * Auction event pushed to subscribers:
+ *  - Bid: accepted bid, outbid is the previous highest bidder.
+ *  - Extended: soft close moved the end to ttl.
+ *  - Ended: END_TOPIC recorded (price, winner), none if unsold.
+ *  - Cancelled: CANCEL_TOPIC recorded.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum AuctionEvent {
+    Bid { bid: usize, bidder: String, outbid: Option<String> },
+    Extended { ttl: String },
+    Ended { price: Option<usize>, winner: Option<String> },
+    Cancelled,
+}
+
+/**
+ * Notification:
+ *  Event of a topic, id is the hash of both, thus
+ *  retries of the same event are delivered once.
+ *  Signed by the node sending it (sender address and
+ *  public key), see KademliaInstance::authentic.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub id: String,
+    pub topic: String,
+    pub event: AuctionEvent,
+    #[serde(default)]
+    pub sender: String,
+    #[serde(default)]
+    pub public_key: String,
+    #[serde(default)]
+    pub signature: String,
+}
+
+impl Notification {
+    pub fn new(topic: &str, event: AuctionEvent) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{}|{}", topic, serde_json::to_string(&event).unwrap()).as_bytes());
+        Self {
+            id: hex::encode(hasher.finalize()),
+            topic: topic.to_string(),
+            event,
+            sender: String::new(),
+            public_key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    // Id of the event, by the sender (address)
+    pub fn sign(mut self, identity: &Identity, sender: &str) -> Self {
+        self.sender = sender.to_string();
+        self.public_key = identity.public_key();
+        self.signature = identity.sign(format!("{}|{}", self.id, self.sender).as_bytes());
+        self
+    }
+
+    // Signature matches the event (its id) and sender
+    pub fn verify(&self) -> bool {
+        self.id == Self::new(&self.topic, self.event.clone()).id
+            && verify(&self.public_key, format!("{}|{}", self.id, self.sender).as_bytes(), &self.signature)
+    }
+
+    // Address lost the lead of the topic
+    pub fn outbids(&self, addr: &str) -> bool {
+        matches!(&self.event, AuctionEvent::Bid { bidder, outbid: Some(outbid), .. } if outbid == addr && bidder != addr)
+    }
+}
+
+/**
+ * Inbox:
+ *  Notifications received by a node, each id (of the
+ *  last INBOX_SEEN) is only passed once to every event
+ *  channel (see App::events). The inbox only keeps the
+ *  senders of the channels, those whose receiver was
+ *  dropped are forgotten.
+**/
+#[derive(Debug, Clone, Default)]
+pub struct Inbox {
+    seen: Arc<Mutex<(HashSet<String>, VecDeque<String>)>>,
+    listeners: Arc<Mutex<Vec<Sender<Notification>>>>,
+}
+
+impl Inbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // False if already delivered
+    pub fn deliver(&self, notification: Notification) -> bool {
+        let mut seen = self.seen.lock()
+            .expect("Error setting lock in inbox");
+        let (ids, order) = &mut *seen;
+        let new = ids.insert(notification.id.clone());
+        if new {
+            order.push_back(notification.id.clone());
+            if order.len() > INBOX_SEEN {
+                if let Some(oldest) = order.pop_front() {
+                    ids.remove(&oldest);
+                }
+            }
+        }
+        drop(seen);
+        if new {
+            let mut listeners = self.listeners.lock()
+                .expect("Error setting lock in inbox listeners");
+            listeners.retain(|listener| !matches!(listener.try_send(notification.clone()), Err(TrySendError::Disconnected(_))));
+            drop(listeners);
+        }
+        new
+    }
+
+    // Channel of the notifications delivered from now on
+    pub fn events(&self) -> Receiver<Notification> {
+        let (sender, receiver) = crossbeam_channel::bounded(INBOX_CAPACITY);
+        let mut listeners = self.listeners.lock()
+            .expect("Error setting lock in inbox listeners");
+        listeners.push(sender);
+        drop(listeners);
+        receiver
+    }
+}
+
+pub fn addr_node(addr: &str) -> Option<Node> {
+    let (ip, port) = addr.split_once(':')?;
+    Some(Node::new(ip.to_string(), port.parse().ok()?))
+}
+
+/**
+ * NOTIFICATIONS
+**/
+impl KademliaInstance {
+    // Signed by its sender, with the key registered for its address if our chain knows it
+    pub fn authentic(&self, notification: &Notification) -> bool {
+        if !notification.verify() {
+            return false
+        }
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let registered = blockchain.with_tip_state(|state| state.registered.get(&notification.sender).cloned())
+            .flatten();
+        drop(blockchain);
+        registered.is_none_or(|key| key == notification.public_key)
+    }
+
+    // Notify request, resent until acknowledged (up to NOTIFY_RETRIES)
+    pub fn notify(&self, node: Node, notification: Notification) -> bool {
+        for _ in 0..NOTIFY_RETRIES {
+            let request = KademliaRequest::Notify(notification.clone());
+            let res = timed_rpc_proc(&self.rpc, request, node.clone(), Duration::from_secs(NOTIFY_TIMEOUT));
+            if let Some(KademliaResponse::Ping) = res {
+                return true
+            }
+        }
+        false
+    }
+
+    /*
+        Notification pushed to every address but ours, each in
+        its own thread, delivered is called with the address of
+        every node and whether it acknowledged it.
+    */
+    pub fn fan_out<F>(&self, addrs: Vec<String>, notification: Notification, delivered: F)
+    where F: Fn(&str, bool) + Clone + Send + 'static {
+        let own = self.node.get_addr();
+        for node in addrs.iter().filter(|addr| **addr != own).filter_map(|addr| addr_node(addr)) {
+            let kad = self.clone();
+            let notification = notification.clone();
+            let delivered = delivered.clone();
+            spawn(move || {
+                let reached = kad.notify(node.clone(), notification);
+                if !reached {
+                    println!("\t[AN{}]: Unable to notify {}", kad.node.port, node.get_addr());
+                }
+                delivered(&node.get_addr(), reached);
+            });
+        }
+    }
+}
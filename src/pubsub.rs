@@ -1,9 +1,22 @@
 use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, Formatter, Result};
-use base64::{encode};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Local};
 use sha2::{Sha256, Digest};
 use serde_json::{json, Value};
+use super::auction::{AuctionKind, AuctionSpec, Bid};
+use super::softclose::SoftClose;
+
+// Seconds a subscription lasts unless renewed (see App::renew_leases)
+pub const SUBSCRIPTION_LEASE: i64 = 120;
+
+// Notifications in a row a subscriber may miss before it is dropped
+pub const MAX_MISSED: usize = 3;
+
+// Version of the DHT representation (see PubSubRecord)
+pub const PUBSUB_VERSION: u32 = 2;
 
 #[derive(Debug, Clone)]
 pub struct PubSubInstance {
@@ -12,6 +25,14 @@ pub struct PubSubInstance {
     pub substack: Arc<Mutex<Vec<String>>>,
     pub publisher: String,
     pub ttl: Option<DateTime<Local>>,
+    pub kind: AuctionKind,
+    pub soft_close: Option<SoftClose>,
+    pub spec: Option<AuctionSpec>,
+    pub cancelled: bool,
+    // subscriber -> lease expiry (unix secs)
+    pub leases: Arc<Mutex<HashMap<String, i64>>>,
+    // subscriber -> notifications missed in a row
+    pub missed: Arc<Mutex<HashMap<String, usize>>>,
 }
 
 /*
@@ -24,8 +45,7 @@ pub struct PubSubInstance {
 
 /*
     PubSubInstance: 
-    Represented as the following string in kademlia DHT:
-        <id> : <publisher_addr> : <msgstack> : <substack> : <TTL>
+    Represented as a versioned json record in kademlia DHT (see PubSubRecord).
     Used to save state of our bidding sessions.
 */
 impl PubSubInstance {
@@ -37,7 +57,13 @@ impl PubSubInstance {
                     msgstack: Arc::new(Mutex::new(msgstack.unwrap())),
                     substack: Arc::new(Mutex::new(substack.unwrap())),
                     publisher: publisher,
-                    ttl: None
+                    ttl: None,
+                    kind: AuctionKind::default(),
+                    soft_close: None,
+                    spec: None,
+                    cancelled: false,
+                    leases: Arc::new(Mutex::new(HashMap::new())),
+                    missed: Arc::new(Mutex::new(HashMap::new()))
                 }
         }
         let mut hasher = Sha256::new();
@@ -47,7 +73,13 @@ impl PubSubInstance {
             msgstack: Arc::new(Mutex::new(Vec::new())),
             substack: Arc::new(Mutex::new(Vec::new())),
             publisher: publisher,
-            ttl: None
+            ttl: None,
+            kind: AuctionKind::default(),
+            soft_close: None,
+            spec: None,
+            cancelled: false,
+            leases: Arc::new(Mutex::new(HashMap::new())),
+            missed: Arc::new(Mutex::new(HashMap::new()))
         }
     }
     
@@ -55,11 +87,29 @@ impl PubSubInstance {
         self.ttl = Some(ttl);
     }
 
+    pub fn set_kind(&mut self, kind: AuctionKind) {
+        self.kind = kind;
+    }
+
+    pub fn set_soft_close(&mut self, soft_close: Option<SoftClose>) {
+        self.soft_close = soft_close;
+    }
+
+    pub fn set_spec(&mut self, spec: Option<AuctionSpec>) {
+        self.spec = spec;
+    }
+
+    // Withdrawn by the publisher: no bids or subs, no outcome
+    pub fn cancel(&mut self) {
+        self.cancelled = true;
+    }
+
     // NOTE: Msgs should be of type "<raise_num>;<addr>"
-    pub fn add_msg(&self, msg: String) -> usize {
+    // Bid made at (block time of its escrow), see verify_msg
+    pub fn add_msg(&self, msg: String, at: DateTime<Local>) -> usize {
         let id: &str = &self.id.clone()[..4];
         if self.verify_pubsub() {
-            if self.verify_msg(msg.clone()) {
+            if self.verify_msg(msg.clone(), at) {
                 let mut msgstack = self.msgstack.lock()
                     .expect("Error setting lock in msg stack");
                 msgstack.push(msg);
@@ -79,38 +129,135 @@ impl PubSubInstance {
     }
 
     // TODO: handle concurrent subs
+    // Subscribing again renews the lease of sub
     pub fn add_sub(&self, sub: String) {
-        if self.verify_pubsub() && !self.verify_addr(sub.clone()) {
+        if self.verify_pubsub() && sub != self.publisher {
             let mut substack = self.substack.lock()
                 .expect("Error setting lock in msg stack");
-            substack.push(sub);
-            drop(substack)
+            if !substack.contains(&sub) {
+                substack.push(sub.clone());
+            }
+            drop(substack);
+            self.set_lease(sub, Local::now().timestamp() + SUBSCRIPTION_LEASE);
+        }
+    }
+
+    pub fn set_lease(&self, sub: String, expiry: i64) {
+        let mut leases = self.leases.lock()
+            .expect("Error setting lock in leases");
+        leases.insert(sub, expiry);
+        drop(leases)
+    }
+
+    pub fn remove_sub(&self, sub: &str) -> bool {
+        let mut substack = self.substack.lock()
+            .expect("Error setting lock in substack");
+        let len = substack.len();
+        substack.retain(|x| x != sub);
+        let removed = substack.len() != len;
+        drop(substack);
+        let mut leases = self.leases.lock()
+            .expect("Error setting lock in leases");
+        leases.remove(sub);
+        drop(leases);
+        let mut missed = self.missed.lock()
+            .expect("Error setting lock in missed");
+        missed.remove(sub);
+        drop(missed);
+        removed
+    }
+
+    /*
+        Notification not acknowledged by sub: it is dropped once
+        MAX_MISSED were missed in a row (see reach). Returns
+        whether sub was dropped.
+    */
+    pub fn miss(&self, sub: &str) -> bool {
+        if !self.verify_addr(sub.to_string()) || sub == self.publisher {
+            return false
         }
+        let mut missed = self.missed.lock()
+            .expect("Error setting lock in missed");
+        let count = missed.entry(sub.to_string()).or_default();
+        *count += 1;
+        let dead = *count >= MAX_MISSED;
+        drop(missed);
+        dead && self.remove_sub(sub)
+    }
+
+    // Notification acknowledged by sub, its misses are forgotten
+    pub fn reach(&self, sub: &str) -> bool {
+        let mut missed = self.missed.lock()
+            .expect("Error setting lock in missed");
+        let res = missed.remove(sub).is_some();
+        drop(missed);
+        res
+    }
+
+    pub fn missed(&self, sub: &str) -> usize {
+        let missed = self.missed.lock()
+            .expect("Error setting lock in missed");
+        let res = missed.get(sub).copied().unwrap_or_default();
+        drop(missed);
+        res
+    }
+
+    /*
+        Subscribers whose lease expired at now are removed,
+        subscribers without a lease (instances encoded before
+        leases) are granted a fresh one. Returns the removed.
+    */
+    pub fn prune_leases(&self, now: DateTime<Local>) -> Vec<String> {
+        let substack = self.substack.lock()
+            .expect("Error setting lock in substack");
+        let subs: Vec<String> = substack.iter().filter(|sub| !sub.is_empty()).cloned().collect();
+        drop(substack);
+        let mut leases = self.leases.lock()
+            .expect("Error setting lock in leases");
+        let expired: Vec<String> = subs.into_iter()
+            .filter(|sub| *leases.entry(sub.clone()).or_insert(now.timestamp() + SUBSCRIPTION_LEASE) < now.timestamp())
+            .collect();
+        drop(leases);
+        for sub in &expired {
+            self.remove_sub(sub);
+        }
+        expired
     }
 
     //  - verify msg (tuple -> (number to raise bid; sender addr))
     // NOTE: Msgs should be of type "<raise_num>;<addr>"
-    pub fn verify_msg(&self, msg: String) -> bool {
+    // Bid is validated by our auction kind (see auction.rs) at
+    // the time it was made (as the ledger does, at the block
+    // time of its escrow), sealed bids are never stored in the
+    // msgstack and no bid is taken before the listing starts
+    pub fn verify_msg(&self, msg: String, at: DateTime<Local>) -> bool {
+        if self.kind.sealed() || !self.spec.as_ref().is_none_or(|spec| spec.started(at)) {
+            return false
+        }
+        match Bid::from_msg(&msg) {
+            Some(bid) => self.kind.verify_bid(&self.bids(), &bid, at),
+            None => false
+        }
+    }
+
+    // Accepted bids (empty msg of a decoded instance is skipped)
+    pub fn bids(&self) -> Vec<Bid> {
         let msgstack = self.msgstack.lock()
             .expect("Error setting lock in msg stack");
-        
-        if msgstack.is_empty() || (msgstack.last().unwrap() == "" && msgstack.len() == 1){
-            drop(msgstack);
-            return true
-        } else {
-            let stack = msgstack.clone();
-            drop(msgstack);
-            let last_val: Value = serde_json::from_str(stack.last().unwrap()).unwrap();
-            let new_val: Value = serde_json::from_str(&msg).unwrap();
-
-            let last_raise: usize = last_val["data"].to_string().parse::<usize>().unwrap();
-            let new_raise: usize = new_val["data"].to_string().parse::<usize>().unwrap();
+        let bids = msgstack.iter()
+            .filter_map(|msg| Bid::from_msg(msg))
+            .collect();
+        drop(msgstack);
+        bids
+    }
 
-            if new_raise > last_raise {
-                return true
-            }
-        }
-        false
+    // Sequence number of the last stored msg (see coordinator.rs)
+    pub fn seq(&self) -> usize {
+        let msgstack = self.msgstack.lock()
+            .expect("Error setting lock in msg stack");
+        let seq = msgstack.len();
+        drop(msgstack);
+        seq
     }
 
     pub fn verify_addr(&self, addr: String) -> bool {
@@ -128,8 +275,22 @@ impl PubSubInstance {
         false
     }
 
+    // Addresses notified of its events: publisher and subscribers
+    pub fn audience(&self) -> Vec<String> {
+        let substack = self.substack.lock()
+            .expect("Error setting lock in substack");
+        let mut res = vec![self.publisher.clone()];
+        for sub in substack.iter().filter(|sub| !sub.is_empty()) {
+            if !res.contains(sub) {
+                res.push(sub.clone());
+            }
+        }
+        drop(substack);
+        res
+    }
+
     pub fn verify_pubsub(&self) -> bool { // TODO: diff calc
-        if self.ttl == None {
+        if self.ttl == None || self.cancelled {
             return false
         }
 
@@ -144,20 +305,33 @@ impl PubSubInstance {
 
     // ---
 
-    // PubSub info sent to App -> used at cli
+    /*
+        PubSub info sent to App -> used at cli
+        Highest bid is hidden while a sealed auction is open,
+        outcome (winner, price) is only set once it closed
+        (and the reserve of its spec is met), never once cancelled.
+        Multi kinds add the allocation of their bids once closed.
+    */
     pub fn as_json(&self) -> Value {
         let id: &str = &self.id.clone()[..4];
-        let msgstack = &self.msgstack.lock()
-            .expect("Error setting lock in msgstack");
-        let stack = msgstack;
-        drop(msgstack);
-        let last_entry: &String = stack.last().unwrap();
-        let mut last_entry_json: Value = json!({"data":0,"sender_addr":"unknown"});
-        if last_entry != ""
-        {
-            last_entry_json = serde_json::from_str(last_entry).unwrap();
+        let bids = self.bids();
+        let open = self.verify_pubsub();
+        let mut highest_json: Value = json!({"data":0,"sender_addr":"unknown"});
+        if !(open && self.kind.sealed()) {
+            if let Some(bid) = bids.iter().rev().max_by_key(|bid| bid.data) {
+                highest_json = json!(bid);
+            }
         }
-        let substack = &self.substack.lock()
+        let outcome = match &self.spec {
+            _ if open || self.cancelled => None,
+            Some(spec) => spec.sold(self.kind.outcome(&bids)),
+            None => self.kind.outcome(&bids)
+        };
+        let allocation = match outcome {
+            Some(_) if self.kind.multi() => self.kind.allocate(&bids),
+            _ => Vec::new()
+        };
+        let substack = self.substack.lock()
             .expect("Error setting lock in substack");
         let num_subs = substack.len();
         drop(substack);
@@ -165,33 +339,21 @@ impl PubSubInstance {
             {
                 "id": id,
                 "num_subs": num_subs,
-                "highest_bid": last_entry_json["data"],
-                "highest_bidder": last_entry_json["sender_addr"],
+                "highest_bid": highest_json["data"],
+                "highest_bidder": highest_json["sender_addr"],
+                "kind": self.kind,
+                "clock_price": self.kind.clock_price(Local::now()),
+                "soft_close": self.soft_close,
+                "spec": self.spec,
+                "cancelled": self.cancelled,
+                "price": outcome.as_ref().map(|(price, _)| *price),
+                "winner": outcome.map(|(_, winner)| winner),
+                "allocation": allocation,
                 "ttl": format!("{}", self.ttl.unwrap()),
             }
         )
     } 
 
-    fn print_msgstack(&self) -> String {
-        let mut msgstack_str = String::new();
-        let msgstack = self.msgstack.lock()
-            .expect("Error setting lock in msg stack");
-        let msgstack_clone = msgstack.clone();
-        drop(msgstack);
-        let msgstack_len = msgstack_clone.len();
-        let mut iter = 0;
-        for msg in msgstack_clone {
-            let mut full = String::new();
-            full.push_str(&msg);
-            if iter < msgstack_len-1 {
-                full.push_str(" ");
-            }
-            msgstack_str.push_str(&full);
-            iter += 1;
-        }
-        format!("{}", msgstack_str)
-    }
-
     pub fn print_substack(&self) -> String {
         let mut substack_str = String::new();
         let substack = self.substack.lock()
@@ -214,31 +376,159 @@ impl PubSubInstance {
         format!("{}", substack_str)
     }
 
-    fn encode_instance(&self) -> String {
-        let mut str_to_encode = String::new();
-        str_to_encode.push_str(&self.id);
-        str_to_encode.push_str(";");
-        str_to_encode.push_str(&self.publisher);
-        str_to_encode.push_str(";");
-        str_to_encode.push_str(&self.print_substack());
-        str_to_encode.push_str(";");
-        str_to_encode.push_str(&self.print_msgstack());
-        str_to_encode.push_str(";");
-        if self.ttl != None {
-            str_to_encode.push_str(&format!("{}", (self.ttl.unwrap())))
-        } else {
-            str_to_encode.push_str("NONE")
+    pub fn to_record(&self) -> PubSubRecord {
+        let msgstack = self.msgstack.lock()
+            .expect("Error setting lock in msg stack");
+        let msgs = msgstack.clone();
+        drop(msgstack);
+        let substack = self.substack.lock()
+            .expect("Error setting lock in substack");
+        let subs = substack.clone();
+        drop(substack);
+        let leases = self.leases.lock()
+            .expect("Error setting lock in leases");
+        let leases = leases.iter().map(|(sub, expiry)| (sub.clone(), *expiry)).collect();
+        let missed = self.missed.lock()
+            .expect("Error setting lock in missed");
+        let missed = missed.iter().map(|(sub, count)| (sub.clone(), *count)).collect();
+        PubSubRecord {
+            version: PUBSUB_VERSION,
+            id: self.id.clone(),
+            publisher: self.publisher.clone(),
+            subs,
+            msgs,
+            ttl: self.ttl.map(|ttl| format!("{}", ttl)),
+            kind: self.kind.clone(),
+            soft_close: self.soft_close,
+            spec: self.spec.clone(),
+            cancelled: self.cancelled,
+            leases,
+            missed,
+        }
+    }
+
+    // None if the record breaks the schema
+    pub fn from_record(record: PubSubRecord) -> Option<Self> {
+        if !record.validate() {
+            return None
+        }
+        let mut pubsub = Self::new(Some(record.id), record.publisher, Some(record.msgs), Some(record.subs));
+        pubsub.ttl = record.ttl.and_then(|ttl| ttl.parse().ok());
+        pubsub.kind = record.kind;
+        pubsub.soft_close = record.soft_close;
+        pubsub.spec = record.spec;
+        pubsub.cancelled = record.cancelled;
+        pubsub.leases = Arc::new(Mutex::new(record.leases.into_iter().collect()));
+        pubsub.missed = Arc::new(Mutex::new(record.missed.into_iter().collect()));
+        Some(pubsub)
+    }
+
+    // DHT value, current records or legacy (migrated) strings
+    pub fn decode(value: &str) -> Option<Self> {
+        match serde_json::from_str::<PubSubRecord>(value) {
+            Ok(record) => Self::from_record(record),
+            Err(_) => Self::from_record(PubSubRecord::migrate(value)?)
+        }
+    }
+}
+
+/**
+ * PubSub record:
+ *  Serialized (json) form of a PubSubInstance kept in the DHT,
+ *  version 1 was the legacy base64 string of fields joined by
+ *  ';' (stacks joined by ' '), only read for migration.
+ *
+ *  - subs: subscribers (never the publisher), leases and missed
+ *    notifications (see PubSubInstance::miss) by subscriber.
+ *  - msgs: accepted bids, as Bid json.
+ *  - ttl: auction end (DateTime<Local> string), None if not set.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PubSubRecord {
+    pub version: u32,
+    pub id: String,
+    pub publisher: String,
+    pub subs: Vec<String>,
+    pub msgs: Vec<String>,
+    pub ttl: Option<String>,
+    pub kind: AuctionKind,
+    pub soft_close: Option<SoftClose>,
+    pub spec: Option<AuctionSpec>,
+    pub cancelled: bool,
+    pub leases: BTreeMap<String, i64>,
+    #[serde(default)]
+    pub missed: BTreeMap<String, usize>,
+}
+
+impl PubSubRecord {
+    /*
+        Schema: current version, id and publisher set, ttl a valid
+        time, every msg a bid, subscribers unique and not empty
+        (nor the publisher), leases and misses of subscribers only.
+    */
+    pub fn validate(&self) -> bool {
+        let mut subs: Vec<&String> = self.subs.iter().collect();
+        subs.sort();
+        subs.dedup();
+        self.version == PUBSUB_VERSION
+            && !self.id.is_empty()
+            && !self.publisher.is_empty()
+            && self.ttl.as_ref().is_none_or(|ttl| ttl.parse::<DateTime<Local>>().is_ok())
+            && self.msgs.iter().all(|msg| Bid::from_msg(msg).is_some())
+            && subs.len() == self.subs.len()
+            && self.subs.iter().all(|sub| !sub.is_empty() && *sub != self.publisher)
+            && self.leases.keys().all(|sub| self.subs.contains(sub))
+            && self.missed.keys().all(|sub| self.subs.contains(sub))
+    }
+
+    /*
+        Migration of a legacy (version 1) value:
+            <id>;<publisher>;<substack>;<msgstack>;<ttl>[;<kind>;<soft_close>;<spec>;<cancelled>;<leases>]
+        Empty stack entries, msgs that are not bids (split by the old
+        separators) and repeated subscribers are dropped.
+    */
+    pub fn migrate(value: &str) -> Option<Self> {
+        let decoded = String::from_utf8(STANDARD.decode(value).ok()?).ok()?;
+        let fields: Vec<&str> = decoded.split(';').collect();
+        let publisher = fields.get(1)?.to_string();
+        let mut subs: Vec<String> = Vec::new();
+        for sub in fields.get(2)?.split(' ').filter(|sub| !sub.is_empty() && **sub != publisher) {
+            if !subs.iter().any(|x| x == sub) {
+                subs.push(sub.to_string());
+            }
         }
-        // ---
-        // println!("PUBSUB BEFORE ENCODE: {}", str_to_encode);
-        // ---
-        encode(str_to_encode)
+        let msgs = fields.get(3)?.split(' ')
+            .filter(|msg| Bid::from_msg(msg).is_some())
+            .map(String::from)
+            .collect();
+        let none = |index: usize| fields.get(index).copied().filter(|field| *field != "NONE");
+        let leases = none(9).map_or(Vec::new(), |leases| leases.split(' ').collect()).into_iter()
+            .filter_map(|lease| {
+                let (sub, expiry) = lease.rsplit_once('@')?;
+                Some((sub.to_string(), expiry.parse().ok()?))
+            })
+            .filter(|(sub, _)| subs.contains(sub))
+            .collect();
+        Some(Self {
+            version: PUBSUB_VERSION,
+            id: fields.first()?.to_string(),
+            publisher,
+            subs,
+            msgs,
+            ttl: none(4).map(String::from),
+            kind: fields.get(5).and_then(|kind| AuctionKind::from_json(kind)).unwrap_or_default(),
+            soft_close: fields.get(6).and_then(|rule| SoftClose::from_json(rule)),
+            spec: fields.get(7).and_then(|spec| AuctionSpec::decode(spec)),
+            cancelled: fields.get(8) == Some(&"CANCELLED"),
+            leases,
+            missed: BTreeMap::new(),
+        })
     }
 }
 
 impl Display for PubSubInstance {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        write!(f, "{}", self.encode_instance())
+        write!(f, "{}", serde_json::to_string(&self.to_record()).expect("Error serializing pubsub record"))
     }
 }
 
@@ -0,0 +1,142 @@
+use super::blockchain::Blockchain;
+use super::bootstrap::Data;
+use super::explorer::msg_fields;
+use super::snapshot::LedgerState;
+
+use serde::{Serialize, Deserialize};
+
+// Rating of a counterparty after a settlement
+pub const FEEDBACK_MSG_TYPE: usize = 10;
+
+/*
+    Feedback record, msg format (rating is 1 or -1):
+        10 -> "FEEDBACK: <topic>|RATER: <addr>|RATED: <addr>|RATING: <rating>"
+*/
+pub fn feedback_msg(topic: &str, rater: &str, rated: &str, positive: bool) -> String {
+    format!("FEEDBACK: {}|RATER: {}|RATED: {}|RATING: {}", topic, rater, rated, if positive { 1 } else { -1 })
+}
+
+/**
+ * Reputation (of a registered key, shared by its addresses, from chain history):
+ *  - trades: settlements it took part in (as seller or winner).
+ *  - unpaid: settlements it won without the funds to pay.
+ *  - positive, negative: feedback of its counterparties.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reputation {
+    pub trades: u64,
+    pub unpaid: u64,
+    pub positive: u64,
+    pub negative: u64,
+}
+
+impl Reputation {
+    // Ratings balance, every unpaid settlement counts as a negative one
+    pub fn score(&self) -> i64 {
+        self.positive as i64 - self.negative as i64 - self.unpaid as i64
+    }
+}
+
+/**
+ * Bid policy (of an auction spec), a bidder must have:
+ *  - min_score: a reputation score of at least it.
+ *  - min_trades: taken part in at least these settlements.
+ *  - max_unpaid: left at most these settlements unpaid.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BidPolicy {
+    pub min_score: Option<i64>,
+    #[serde(default)]
+    pub min_trades: u64,
+    pub max_unpaid: Option<u64>,
+}
+
+impl BidPolicy {
+    pub fn allows(&self, reputation: &Reputation) -> bool {
+        self.min_score.is_none_or(|min| reputation.score() >= min)
+            && reputation.trades >= self.min_trades
+            && self.max_unpaid.is_none_or(|max| reputation.unpaid <= max)
+    }
+}
+
+/**
+ * REPUTATION
+ *  Every settlement paid from escrow is a trade of the seller
+ *  with each winner, both may then rate the other once
+ *  (FEEDBACK). Reputation is kept by registered key, so new
+ *  addresses of a key do not start afresh.
+**/
+impl LedgerState {
+    pub fn reputation(&self, addr: &str) -> Reputation {
+        self.registered.get(addr)
+            .and_then(|key| self.reputation.get(key))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /*
+        Settlement of price, paid units drawn from the winner
+        escrow: a trade if paid in full, unpaid (of the winner)
+        otherwise. Free settlements and those of a key with
+        itself are no trade.
+    */
+    pub(crate) fn record_trade(&mut self, topic: &str, seller: &str, winner: &str, price: u64, paid: u64) {
+        let (seller_key, winner_key) = match (self.registered.get(seller), self.registered.get(winner)) {
+            (Some(seller_key), Some(winner_key)) if seller_key != winner_key => (seller_key.clone(), winner_key.clone()),
+            _ => return
+        };
+        if price == 0 {
+            return
+        }
+        if paid < price {
+            self.reputation.entry(winner_key).or_default().unpaid += 1;
+            return
+        }
+        self.reputation.entry(seller_key).or_default().trades += 1;
+        self.reputation.entry(winner_key).or_default().trades += 1;
+        let pending = self.feedback.entry(topic.to_string()).or_default();
+        pending.push((seller.to_string(), winner.to_string()));
+        pending.push((winner.to_string(), seller.to_string()));
+    }
+
+    // Rater traded with rated on topic and did not rate it yet
+    pub fn can_rate(&self, topic: &str, rater: &str, rated: &str) -> bool {
+        self.feedback.get(topic).is_some_and(|pending| pending.iter().any(|(x, y)| x == rater && y == rated))
+    }
+
+    pub(crate) fn rate(&mut self, topic: &str, data: &Data) {
+        let record = msg_fields(data.msg());
+        let (rater, rated) = match (record.get("RATER"), record.get("RATED")) {
+            (Some(rater), Some(rated)) if self.can_rate(topic, rater, rated) => (rater.to_string(), rated.to_string()),
+            _ => return
+        };
+        let key = match self.registered.get(&rated) {
+            Some(key) => key.clone(),
+            None => return
+        };
+        let reputation = self.reputation.entry(key).or_default();
+        match record.get("RATING") {
+            Some(&"1") => reputation.positive += 1,
+            Some(&"-1") => reputation.negative += 1,
+            _ => return
+        }
+        if let Some(pending) = self.feedback.get_mut(topic) {
+            pending.retain(|(x, y)| !(*x == rater && *y == rated));
+            if pending.is_empty() {
+                self.feedback.remove(topic);
+            }
+        }
+    }
+
+    // Bidder meets the policy of topic (topics without one take any bidder)
+    pub fn may_bid(&self, topic: &str, bidder: &str) -> bool {
+        self.auctions.get(topic).is_none_or(|auction| auction.policy.allows(&self.reputation(bidder)))
+    }
+}
+
+impl Blockchain {
+    // Reputation of address at our tip (None if its state was pruned)
+    pub fn reputation(&self, addr: &str) -> Option<Reputation> {
+        self.with_tip_state(|state| state.reputation(addr))
+    }
+}
@@ -9,9 +9,36 @@ use std::thread;
 use std::time::{SystemTime, Duration};
 
 use super::node::{Key, NodeWithDistance, Node};
-use super::blockchain::{Block};
+use super::blockchain::{Block, BlockHeader, ChainTip};
+use super::mempool::{Transaction};
+use super::snapshot::Snapshot;
+use super::finality::Checkpoint;
+use super::light::{ProofQuery, TxProof};
+use super::notify::Notification;
+use super::search::{TopicQuery, TopicEntry};
+use super::coordinator::{BidRequest, BidReceipt, TopicUpdate, UpdateReceipt};
 use super::TREPLICATE;
 
+// Max size of an RPC datagram (max udp payload)
+pub const RPC_BUF_SIZE: usize = 65507;
+
+// Bytes of a response left for its items (the rest is the RpcMessage envelope)
+pub const MAX_PAYLOAD: usize = RPC_BUF_SIZE - 1024;
+
+// Longest prefix of items whose encoding fits MAX_PAYLOAD (responses are capped by size)
+pub fn fit_payload<T: Serialize>(items: impl IntoIterator<Item = T>) -> Vec<T> {
+    let mut size = 0;
+    let mut res = Vec::new();
+    for item in items {
+        size += serde_json::to_string(&item).map_or(MAX_PAYLOAD, |encoded| encoded.len()) + 1;
+        if size > MAX_PAYLOAD {
+            break
+        }
+        res.push(item);
+    }
+    res
+}
+
 // ENUM -> define types
 // STRUCTS -> define obj
 
@@ -29,6 +56,25 @@ pub enum KademliaRequest {
     // BLOCKCHAIN REQUESTS ----
     QueryLocalBlockChain,
     AddBlock(Block),
+    SubmitTransaction(Transaction),
+    AnnounceBlock(BlockHeader),
+    GetTip,
+    GetHeaders(Vec<String>),
+    GetBlocks(Vec<String>),
+    GetSnapshot,
+    GetCheckpoint,
+    GetTxProofs(ProofQuery),
+    // (topic, bidder)
+    GetFunds(String, String),
+    // ----
+
+    // PUBSUB REQUESTS ----
+    Notify(Notification),
+    SearchTopics(TopicQuery),
+    SubmitBid(BidRequest),
+    // (topic, update)
+    UpdateTopic(String, TopicUpdate),
+    Depart(Node),
     // ----
 
     NodeJoin(Node)
@@ -43,6 +89,20 @@ pub enum KademliaResponse {
 
     // BLOCKCHAIN RESPONSES ----
     QueryLocalBlockChain(Vec<Block>),
+    GetTip(ChainTip),
+    GetHeaders(Vec<BlockHeader>),
+    GetBlocks(Vec<Block>),
+    // snapshot with its commitment transaction
+    GetSnapshot(Option<(Snapshot, String)>),
+    GetCheckpoint(Option<Checkpoint>),
+    GetTxProofs(Vec<TxProof>),
+    GetFunds(Option<u64>),
+    // ----
+
+    // PUBSUB RESPONSES ----
+    SearchTopics(Vec<TopicEntry>),
+    SubmitBid(BidReceipt),
+    UpdateTopic(UpdateReceipt),
     // ----
 
     NodeJoin(Vec<Node>)
@@ -118,17 +178,18 @@ impl Rpc {
 
     pub fn init(rpc: Rpc, sender_ch: crossbeam_channel::Sender<RpcRequestWithMeta>) {
         thread::spawn(move || {
-            let mut buf = [0u8; 8192]; // TODO: Set buf size as parameter
+            let mut buf = vec![0u8; RPC_BUF_SIZE];
 
             loop {
-                let (len, src_addr) = rpc.socket.recv_from(&mut buf)
-                    .expect("Error receiving data from node");
-                
-                let payload = String::from(str::from_utf8(&buf[..len])
-                    .expect("Error parsing bytes as string"));
-
-                let mut content: RpcMessage = serde_json::from_str(&payload)
-                    .expect("Error decoding content from payload");
+                // undecodable datagrams (truncated or malformed) are dropped
+                let (len, src_addr) = match rpc.socket.recv_from(&mut buf) {
+                    Ok(res) => res,
+                    Err(_) => continue
+                };
+                let mut content: RpcMessage = match str::from_utf8(&buf[..len]).ok().and_then(|payload| serde_json::from_str(payload).ok()) {
+                    Some(content) => content,
+                    None => continue
+                };
 
                 content.src = src_addr.to_string();
 
@@ -214,8 +275,9 @@ impl Rpc {
         let encodedmsg = serde_json::to_string(&rpcmsg)
             .expect("Error serializing RpcMessage while handeling request");
             
-        self.socket.send_to(&encodedmsg.as_bytes(), &rpcmsg.dst)
-            .expect("Error sending RpcMessage while handeling request");
+        if let Err(err) = self.socket.send_to(encodedmsg.as_bytes(), &rpcmsg.dst) {
+            eprintln!("Error sending RpcMessage to {}: {}", rpcmsg.dst, err);
+        }
     }
 }
 
@@ -225,3 +287,8 @@ pub fn full_rpc_proc(rpc: &Rpc, request: KademliaRequest, node: Node) -> Option<
         .expect("Error receiving response from rpc")
 }
 
+
+// RPC proc giving up after timeout (None), used where the caller retries
+pub fn timed_rpc_proc(rpc: &Rpc, request: KademliaRequest, node: Node, timeout: Duration) -> Option<KademliaResponse> {
+    rpc.handle_request(request, node).recv_timeout(timeout).ok().flatten()
+}
@@ -0,0 +1,89 @@
+use super::auction::{AuctionKind, Bid};
+use super::blockchain::Blockchain;
+use super::engine::AuctionEngine;
+
+use chrono::{DateTime, Local};
+use sha2::{Digest, Sha256};
+
+// Sealed bid commitment (before the deadline)
+pub const COMMIT_MSG_TYPE: usize = 4;
+
+// Sealed bid reveal (within the reveal window)
+pub const REVEAL_MSG_TYPE: usize = 5;
+
+// Seconds after the deadline in which commitments may be revealed
+pub const REVEAL_WINDOW: i64 = 60;
+
+/*
+    Sealed bid records, msg formats:
+        4 -> "COMMIT: <topic>|BIDDER: <addr>|HASH: <commitment>"
+        5 -> "REVEAL: <topic>|BIDDER: <addr>|BID: <bid>|SALT: <salt>"
+*/
+pub fn commit_msg(topic: &str, bidder: &str, hash: &str) -> String {
+    format!("COMMIT: {}|BIDDER: {}|HASH: {}", topic, bidder, hash)
+}
+
+pub fn reveal_msg(topic: &str, bidder: &str, bid: usize, salt: &str) -> String {
+    format!("REVEAL: {}|BIDDER: {}|BID: {}|SALT: {}", topic, bidder, bid, salt)
+}
+
+// Hash binding a bid (and salt) to an auction and its bidder
+pub fn commitment(topic: &str, bid: usize, salt: &str, bidder: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}|{}|{}|{}", topic, bid, salt, bidder).as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+pub fn new_salt() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+/**
+ * Sealed bid:
+ *  Bid committed by us, bid and salt are kept
+ *  locally until revealed after the deadline.
+**/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedBid {
+    pub topic: String,
+    pub bid: usize,
+    pub salt: String,
+    pub deadline: DateTime<Local>,
+}
+
+/**
+ * Sealed outcome:
+ *  - revealed: valid reveals, in commitment order.
+ *  - forfeited: (bidder, deposit) of committers without a
+ *    valid reveal, the deposit is the auction reserve.
+ *  - winner: (price, bidder) given by the auction kind.
+**/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedOutcome {
+    pub kind: AuctionKind,
+    pub revealed: Vec<Bid>,
+    pub forfeited: Vec<(String, usize)>,
+    pub winner: Option<(usize, String)>,
+}
+
+/*
+    Sealed outcome of topic from its events (block timestamp,
+    tx), in chain order, as taken by the auction engine:
+    commitments count if mined by the deadline (first one per
+    bidder), reveals if mined within the reveal window and
+    matching the commitment. None if the topic is not a
+    published sealed auction.
+*/
+pub fn sealed_outcome(topic: &str, events: &[(i64, String)]) -> Option<SealedOutcome> {
+    let engine = AuctionEngine::replay(events);
+    engine.auction(topic)?.sealed()
+}
+
+/**
+ * SEALED AUCTIONS
+**/
+impl Blockchain {
+    pub fn sealed_outcome(&self, topic: &str) -> Option<SealedOutcome> {
+        self.auction(topic)?.sealed()
+    }
+}
@@ -0,0 +1,224 @@
+use super::auction::{AuctionSpec, local};
+use super::blockchain::Blockchain;
+use super::coordinator::TopicUpdate;
+use super::engine::{AuctionEngine, AuctionPhase, AuctionRecord};
+use super::kademlia::KademliaInstance;
+use super::node::Node;
+use super::rpc::{full_rpc_proc, KademliaRequest, KademliaResponse};
+
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+// Results returned by a SearchTopics query (capped by size when answered, see fit_payload)
+pub const MAX_RESULTS: usize = 20;
+
+// DHT key of the topics listed under keyword
+pub fn keyword_key(keyword: &str) -> String {
+    format!("KEYWORD: {}", keyword)
+}
+
+/*
+    Keywords of a listing: lowercase words of its topic
+    and title (split on anything but letters and digits)
+    and its tags, without repetitions.
+*/
+pub fn keywords(topic: &str, spec: Option<&AuctionSpec>) -> Vec<String> {
+    let mut text = vec![topic.to_string()];
+    if let Some(spec) = spec {
+        text.push(spec.title.clone());
+        text.extend(spec.tags.iter().cloned());
+    }
+    let mut res: Vec<String> = Vec::new();
+    for word in text.iter().flat_map(|text| text.split(|c: char| !c.is_alphanumeric())) {
+        let word = word.to_lowercase();
+        if !word.is_empty() && !res.contains(&word) {
+            res.push(word);
+        }
+    }
+    res
+}
+
+/**
+ * Topic query, every set field must match:
+ *  - keyword: listed under it (candidates taken from the DHT index).
+ *  - prefix: topic name starts with it (case insensitive).
+ *  - tag, seller: listing tag, publisher address.
+ *  - min_price, max_price: current price range.
+ *  - ends_after, ends_before: end time range (unix secs).
+ *  - closed: settled and cancelled auctions are included.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopicQuery {
+    pub keyword: Option<String>,
+    pub prefix: Option<String>,
+    pub tag: Option<String>,
+    pub seller: Option<String>,
+    pub min_price: Option<usize>,
+    pub max_price: Option<usize>,
+    pub ends_after: Option<i64>,
+    pub ends_before: Option<i64>,
+    pub closed: bool,
+}
+
+/**
+ * Topic entry:
+ *  Listing found by a query, price is the current one (outcome,
+ *  Dutch clock, highest escrowed bid or opening price).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TopicEntry {
+    pub topic: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub seller: String,
+    pub price: usize,
+    pub end: i64,
+    pub phase: AuctionPhase,
+}
+
+impl TopicEntry {
+    pub fn new(record: &AuctionRecord, now: i64) -> Self {
+        let price = match record.outcome_at(now) {
+            Some((price, _)) => price,
+            None => record.kind.clock_price(local(now))
+                .unwrap_or(record.highest().map_or(record.kind.reserve(), |bid| bid.data))
+        };
+        Self {
+            topic: record.topic.clone(),
+            title: record.spec.as_ref().map_or(record.topic.clone(), |spec| spec.title.clone()),
+            tags: record.spec.as_ref().map_or(Vec::new(), |spec| spec.tags.clone()),
+            seller: record.publisher.clone(),
+            price,
+            end: record.end,
+            phase: record.phase_at(now),
+        }
+    }
+
+    pub fn matches(&self, query: &TopicQuery) -> bool {
+        let lowercase = |value: &Option<String>| value.as_ref().map(|value| value.to_lowercase());
+        let keyword = lowercase(&query.keyword);
+        let tag = lowercase(&query.tag);
+        (query.closed || !self.phase.terminal())
+            && keyword.is_none_or(|keyword| keywords(&self.topic, None).contains(&keyword)
+                || keywords(&self.title, None).contains(&keyword)
+                || self.tags.iter().any(|x| x.to_lowercase() == keyword))
+            && lowercase(&query.prefix).is_none_or(|prefix| self.topic.to_lowercase().starts_with(&prefix))
+            && tag.is_none_or(|tag| self.tags.iter().any(|x| x.to_lowercase() == tag))
+            && query.seller.as_ref().is_none_or(|seller| *seller == self.seller)
+            && query.min_price.is_none_or(|min| self.price >= min)
+            && query.max_price.is_none_or(|max| self.price <= max)
+            && query.ends_after.is_none_or(|after| self.end >= after)
+            && query.ends_before.is_none_or(|before| self.end <= before)
+    }
+}
+
+/**
+ * Topic index:
+ *  Every auction of the ledger (see AuctionEngine) at a given time.
+**/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopicIndex {
+    pub entries: BTreeMap<String, TopicEntry>,
+}
+
+impl TopicIndex {
+    pub fn new(engine: &AuctionEngine, now: i64) -> Self {
+        let entries = engine.auctions.iter()
+            .map(|(topic, record)| (topic.clone(), TopicEntry::new(record, now)))
+            .collect();
+        Self { entries }
+    }
+
+    // Matching entries (of candidates, if any), soonest end first
+    pub fn search(&self, query: &TopicQuery, candidates: Option<&[String]>) -> Vec<TopicEntry> {
+        let mut res: Vec<TopicEntry> = self.entries.values()
+            .filter(|entry| candidates.is_none_or(|candidates| candidates.contains(&entry.topic)))
+            .filter(|entry| entry.matches(query))
+            .cloned()
+            .collect();
+        res.sort_by_key(|entry| (entry.end, entry.topic.clone()));
+        res.truncate(MAX_RESULTS);
+        res
+    }
+}
+
+/**
+ * Topic cache:
+ *  Auction engine replayed at a tip (by hash), indexes of later
+ *  queries at the same tip are built from it without replaying
+ *  the chain again.
+**/
+#[derive(Debug, Clone, Default)]
+pub struct TopicCache {
+    engine: Arc<Mutex<Option<(String, AuctionEngine)>>>,
+}
+
+impl TopicCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn index(&self, blockchain: &Blockchain, now: i64) -> TopicIndex {
+        let tip = blockchain.tip().hash;
+        let mut engine = self.engine.lock()
+            .expect("Error setting lock in topic cache");
+        if engine.as_ref().is_none_or(|(hash, _)| *hash != tip) {
+            *engine = Some((tip, blockchain.auction_engine()));
+        }
+        let index = engine.as_ref().map_or_else(TopicIndex::default, |(_, engine)| TopicIndex::new(engine, now));
+        drop(engine);
+        index
+    }
+}
+
+/**
+ * TOPIC INDEX
+**/
+impl Blockchain {
+    pub fn topic_index(&self, now: i64) -> TopicIndex {
+        self.with_tip_state(|state| TopicIndex::new(&state.engine, now)).unwrap_or_default()
+    }
+}
+
+/**
+ * SEARCH (keyword index in the DHT, SearchTopics queries)
+**/
+impl KademliaInstance {
+    // Topic is merged into the DHT list of each keyword (by its coordinator)
+    pub fn index_keywords(&self, topic: &str, keywords: &[String]) {
+        for keyword in keywords {
+            self.submit_update(&keyword_key(keyword), TopicUpdate::Index(topic.to_string()));
+        }
+    }
+
+    pub fn keyword_topics(&self, keyword: &str) -> Vec<String> {
+        self.get(keyword_key(&keyword.to_lowercase()))
+            .and_then(|topics| serde_json::from_str(&topics).ok())
+            .unwrap_or_default()
+    }
+
+    /*
+        Answer of a SearchTopics query, taken from our ledger,
+        keyword queries only consider the topics the DHT lists
+        under the keyword. The ledger is replayed once per tip
+        (see TopicCache).
+    */
+    pub fn search_topics(&self, query: &TopicQuery, now: i64) -> Vec<TopicEntry> {
+        let candidates = query.keyword.as_ref().map(|keyword| self.keyword_topics(keyword));
+        let blockchain = self.blockchain.lock()
+            .expect("Error setting lock in local blockchain");
+        let index = self.topic_cache.index(&blockchain, now);
+        drop(blockchain);
+        index.search(query, candidates.as_deref())
+    }
+
+    pub fn query_topics(&self, qynode: Node, query: TopicQuery) -> Option<Vec<TopicEntry>> {
+        let res = full_rpc_proc(&self.rpc, KademliaRequest::SearchTopics(query), qynode);
+        if let Some(KademliaResponse::SearchTopics(entries)) = res {
+            Some(entries)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use super::blockchain::{Block, Blockchain};
+use super::explorer::tx_keys;
+use super::bootstrap::Data;
+use super::identity::Identity;
+use super::cancel::CANCEL_MSG_TYPE;
+use super::sealed::{COMMIT_MSG_TYPE, REVEAL_MSG_TYPE};
+use super::accounts::{MintPolicy, MINT_MSG_TYPE, ESCROW_MSG_TYPE};
+use super::auction::{AuctionKind, AuctionSpec};
+use super::explorer::msg_fields;
+use super::reputation::{BidPolicy, Reputation, FEEDBACK_MSG_TYPE};
+use super::engine::{AuctionEngine, LedgerEvent};
+
+use sha2::{Digest, Sha256};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// Blocks between snapshots taken by bootstrap nodes
+pub const SNAPSHOT_INTERVAL: u64 = 50;
+
+// Data msg_type of a snapshot commitment ("SNAPSHOT: <height>|HASH: <hash>")
+pub const SNAPSHOT_MSG_TYPE: usize = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Auction {
+    pub publisher: String,
+    pub exp_time: Option<String>,
+    // bidders allowed by the spec (see reputation.rs)
+    #[serde(default)]
+    pub policy: BidPolicy,
+}
+
+/**
+ * Ledger state:
+ *  Result of applying every transaction up to a height,
+ *  ordered collections so equal states hash the same.
+ *
+ *  - registered: address -> public key of registered nodes.
+ *  - auctions: published (not yet ended or cancelled) topics.
+ *  - balances, escrow, deposits: accounts (see accounts.rs).
+ *  - reputation, feedback: trades and ratings (see reputation.rs).
+ *  - engine: auction records, transactions of an auction are
+ *    only applied if taken by its record (see engine.rs).
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LedgerState {
+    pub registered: BTreeMap<String, String>,
+    pub auctions: BTreeMap<String, Auction>,
+    #[serde(default)]
+    pub balances: BTreeMap<String, u64>,
+    // topic -> (bidder, units) locked by its highest bid
+    #[serde(default)]
+    pub escrow: BTreeMap<String, (String, u64)>,
+    // topic -> bidder -> units locked by its sealed bid (deposit, raised to the bid on reveal) or multi kind bids
+    #[serde(default)]
+    pub deposits: BTreeMap<String, BTreeMap<String, u64>>,
+    // registered key -> reputation (shared by its addresses)
+    #[serde(default)]
+    pub reputation: BTreeMap<String, Reputation>,
+    // topic -> (rater, rated) feedback still allowed
+    #[serde(default)]
+    pub feedback: BTreeMap<String, Vec<(String, String)>>,
+    #[serde(default)]
+    pub engine: AuctionEngine,
+}
+
+impl LedgerState {
+    pub fn apply_block(&mut self, block: &Block, policy: &MintPolicy) {
+        if block.id == 0 {
+            return
+        }
+        for tx in block.transactions() {
+            self.apply_tx(block.timestamp, &tx, policy);
+        }
+    }
+
+    /*
+        Applies tx mined at timestamp (block time), returns false
+        (nothing applied) if it is an auction transaction its
+        engine record does not take.
+    */
+    pub fn apply_tx(&mut self, timestamp: i64, tx: &str, policy: &MintPolicy) -> bool {
+        let (msg_type, topic, addresses) = match tx_keys(tx) {
+            Some(keys) => keys,
+            None => return false
+        };
+        let data = match Data::from_json(tx) {
+            Some(data) => data,
+            None => return false
+        };
+        if LedgerEvent::auction_tx(msg_type) && !self.engine.apply(timestamp, tx) {
+            return false
+        }
+        match (msg_type, topic) {
+            (0, _) => {
+                for address in addresses {
+                    if !self.registered.contains_key(&address) {
+                        // faucet once per key, further addresses of a key start empty
+                        let funded = self.registered.values().any(|key| key == data.author());
+                        self.registered.insert(address.clone(), data.author().to_string());
+                        if !funded {
+                            self.credit(&address, policy.faucet);
+                        }
+                    }
+                }
+            },
+            (1, Some(topic)) => {
+                let auction = Auction {
+                    publisher: addresses.first().cloned().unwrap_or_default(),
+                    exp_time: data.exp_time().map(String::from),
+                    policy: msg_fields(data.msg()).get("SPEC")
+                        .and_then(|spec| AuctionSpec::decode(spec))
+                        .map_or(BidPolicy::default(), |spec| spec.policy),
+                };
+                self.auctions.insert(topic, auction);
+            },
+            (2, Some(topic)) => {
+                self.settle(&topic);
+                self.close(&topic);
+            },
+            (CANCEL_MSG_TYPE, Some(topic)) => {
+                self.release(&topic);
+                self.close(&topic);
+            },
+            (MINT_MSG_TYPE, _) => {
+                if let Some(address) = addresses.first() {
+                    self.mint(address, &data);
+                }
+            },
+            (ESCROW_MSG_TYPE, Some(topic)) => {
+                if let Some(bidder) = addresses.first() {
+                    self.lock(&topic, bidder, &data);
+                }
+            },
+            (COMMIT_MSG_TYPE, Some(topic)) => {
+                if let Some(bidder) = addresses.first() {
+                    self.lock_deposit(&topic, bidder, self.deposit(&topic));
+                }
+            },
+            (REVEAL_MSG_TYPE, Some(topic)) => {
+                let bid = msg_fields(data.msg()).get("BID").and_then(|bid| bid.parse().ok());
+                if let (Some(bidder), Some(bid)) = (addresses.first(), bid) {
+                    self.lock_deposit(&topic, bidder, bid);
+                }
+            },
+            (FEEDBACK_MSG_TYPE, Some(topic)) => self.rate(&topic, &data),
+            _ => {}
+        }
+        true
+    }
+
+    // Ended topic, with the topics of a bundle (ended by the engine alike)
+    fn close(&mut self, topic: &str) {
+        if let Some(AuctionKind::Bundle { topics, .. }) = self.engine.auction(topic).map(|auction| &auction.kind) {
+            for lot in topics {
+                self.auctions.remove(lot);
+            }
+        }
+        self.auctions.remove(topic);
+    }
+}
+
+/**
+ * Snapshot:
+ *  Ledger state at a given block, its hash is committed
+ *  in a later block (checkpoint), thus a new node may
+ *  start from a verified snapshot instead of every body.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub height: u64,
+    pub block_hash: String,
+    pub state: LedgerState,
+}
+
+impl Snapshot {
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_string(self).expect("Error serializing snapshot").as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    // Commitment transaction (serialized Data record)
+    pub fn commitment(&self, identity: &Identity) -> String {
+        Data::new(format!("SNAPSHOT: {}|HASH: {}", self.height, self.hash()), SNAPSHOT_MSG_TYPE, None)
+            .sign(identity)
+            .to_json()
+    }
+}
+
+// (height, snapshot hash) of a commitment transaction (signer not checked)
+pub fn parse_commitment(tx: &str) -> Option<(u64, String)> {
+    let data: Value = serde_json::from_str(tx).ok()?;
+    if data["msg_type"].as_u64()? as usize != SNAPSHOT_MSG_TYPE {
+        return None
+    }
+    let (height, hash) = data["msg"].as_str()?
+        .strip_prefix("SNAPSHOT: ")?
+        .split_once("|HASH: ")?;
+    Some((height.parse().ok()?, hash.to_string()))
+}
+
+/**
+ * SNAPSHOTS & PRUNING
+**/
+impl Blockchain {
+    /*
+        Ledger state at height:
+            Starts from our snapshot (if not after height),
+            otherwise from genesis. None if a required block
+            body was pruned. The state at our tip is kept as
+            blocks are applied, only replayed after a reorg.
+    */
+    pub fn state_at(&self, height: u64) -> Option<LedgerState> {
+        if let Some(state) = self.kept_state().filter(|_| self.tip().height == height) {
+            return Some(state.clone())
+        }
+        let (mut state, start) = match &self.snapshot {
+            Some(snapshot) if snapshot.height <= height => (snapshot.state.clone(), snapshot.height + 1),
+            _ => (LedgerState::default(), 0)
+        };
+        for id in start..=height {
+            let block = self.block_by_id(id)?;
+            if block.pruned {
+                return None
+            }
+            state.apply_block(block, &self.mint_policy());
+        }
+        Some(state)
+    }
+
+    // Ledger state kept at our tip (see add_block), None once the chain moved away from it
+    fn kept_state(&self) -> Option<&LedgerState> {
+        let (hash, state) = self.tip_state.as_ref()?;
+        self.blocks.last().filter(|tip| tip.hash == *hash).map(|_| state)
+    }
+
+    // Lookup on the state at our tip, replayed only if not kept (None if pruned)
+    pub fn with_tip_state<T>(&self, lookup: impl FnOnce(&LedgerState) -> T) -> Option<T> {
+        match self.kept_state() {
+            Some(state) => Some(lookup(state)),
+            None => self.state_at(self.tip().height).map(|state| lookup(&state))
+        }
+    }
+
+    // State at our (new) tip replayed if not kept, after a reorg below our tip
+    pub fn keep_tip_state(&mut self) {
+        if self.kept_state().is_none() {
+            let tip = self.tip();
+            self.tip_state = self.state_at(tip.height).map(|state| (tip.hash, state));
+        }
+    }
+
+    /*
+        Snapshot signers:
+            Keys trusted to commit snapshots, the authorities
+            of the engine (validators) or, if anyone may seal,
+            our checkpoint signers (bootstrap nodes).
+    */
+    pub fn snapshot_signers(&self) -> Vec<String> {
+        let authorities = self.consensus.authorities();
+        if authorities.is_empty() {
+            return self.finality.signers.clone()
+        }
+        authorities
+    }
+
+    // (height, snapshot hash) of a commitment signed by a snapshot signer
+    pub fn verify_commitment(&self, tx: &str) -> Option<(u64, String)> {
+        let data = Data::from_json(tx)?;
+        if !data.verify() || !self.snapshot_signers().iter().any(|signer| signer == data.author()) {
+            return None
+        }
+        parse_commitment(tx)
+    }
+
+    // Commitment transaction of our snapshot (served with it, see install_snapshot)
+    pub fn snapshot_commitment(&self) -> Option<String> {
+        let snapshot = self.snapshot.as_ref()?;
+        let commitment = (snapshot.height, snapshot.hash());
+        self.txs_by_type(SNAPSHOT_MSG_TYPE).into_iter()
+            .map(|tx| tx.data)
+            .find(|tx| parse_commitment(tx).as_ref() == Some(&commitment))
+    }
+
+    /*
+        Fast bootstrap:
+            Snapshot received with its commitment is installed
+            if a snapshot signer committed to it, then bodies up
+            to its height may be received pruned (see apply_blocks).
+    */
+    pub fn install_snapshot(&mut self, snapshot: Snapshot, commitment: &str) -> bool {
+        if self.verify_commitment(commitment) != Some((snapshot.height, snapshot.hash())) {
+            return false
+        }
+        let known = self.block_by_id(snapshot.height).map(|block| block.hash.clone());
+        if known.is_some_and(|hash| hash != snapshot.block_hash) {
+            return false
+        }
+        if self.snapshot.as_ref().is_none_or(|current| current.height < snapshot.height) {
+            self.snapshot = Some(snapshot);
+        }
+        true
+    }
+
+    // Block is received without its body, at or below our snapshot
+    pub fn accepts_pruned(&self, block: &Block) -> bool {
+        block.data.is_empty() && block.id != 0 && self.snapshot.as_ref().is_some_and(|snapshot| block.id <= snapshot.height)
+    }
+
+    // Latest snapshot commitment (height, hash) in chain
+    pub fn latest_commitment(&self) -> Option<(u64, String)> {
+        self.txs_by_type(SNAPSHOT_MSG_TYPE).iter()
+            .filter_map(|tx| self.verify_commitment(&tx.data))
+            .max_by_key(|(height, _)| *height)
+    }
+
+    // Snapshot of tip, if interval blocks were added since the last commitment
+    pub fn snapshot_due(&self, interval: u64) -> Option<Snapshot> {
+        let tip = self.blocks.last()?;
+        let last = self.latest_commitment().map_or(0, |(height, _)| height);
+        if tip.id < last + interval {
+            return None
+        }
+        Some(Snapshot {
+            height: tip.id,
+            block_hash: tip.hash.clone(),
+            state: self.state_at(tip.id)?,
+        })
+    }
+
+    // Snapshot matches a commitment and the block at its height
+    pub fn verify_snapshot(&self, snapshot: &Snapshot) -> bool {
+        let committed = self.txs_by_type(SNAPSHOT_MSG_TYPE).iter()
+            .filter_map(|tx| self.verify_commitment(&tx.data))
+            .any(|commitment| commitment == (snapshot.height, snapshot.hash()));
+        let block_hash = self.block_by_id(snapshot.height).map(|block| block.hash.as_str());
+        committed && block_hash == Some(snapshot.block_hash.as_str())
+    }
+
+    /*
+        Prune:
+            Snapshot of the latest commitment is computed (or
+            the received one is kept) and verified, then bodies
+            of blocks up to its height are dropped (genesis is
+            kept, it selects the consensus engine). Returns
+            the number of pruned blocks.
+    */
+    pub fn prune(&mut self) -> usize {
+        let (height, _) = match self.latest_commitment() {
+            Some(commitment) => commitment,
+            None => return 0
+        };
+        if self.snapshot.as_ref().map(|snapshot| snapshot.height) != Some(height) {
+            let snapshot = match (self.state_at(height), self.block_by_id(height)) {
+                (Some(state), Some(block)) => Snapshot { height, block_hash: block.hash.clone(), state },
+                _ => return 0
+            };
+            if !self.verify_snapshot(&snapshot) {
+                return 0
+            }
+            self.snapshot = Some(snapshot);
+        }
+
+        let mut res = 0;
+        for id in 1..=height as usize {
+            if self.blocks[id].pruned {
+                continue
+            }
+            self.index.unindex_block(&self.blocks[id]);
+            self.blocks[id].data = String::new();
+            self.blocks[id].pruned = true;
+            self.index.index_block(&self.blocks[id]);
+            res += 1;
+        }
+        res
+    }
+}
@@ -0,0 +1,77 @@
+use super::auction::local;
+use super::blockchain::Blockchain;
+use super::engine::AuctionEngine;
+
+use chrono::{DateTime, Duration, Local};
+use serde::{Serialize, Deserialize};
+
+// Bid within the soft-close window of an auction (extends its end)
+pub const EXTEND_MSG_TYPE: usize = 6;
+
+// Times the end of an auction may be extended (bidding wars end)
+pub const MAX_EXTENSIONS: usize = 10;
+
+/*
+    Extension record, msg format:
+        6 -> "EXTEND: <topic>|BIDDER: <addr>"
+*/
+pub fn extend_msg(topic: &str, bidder: &str) -> String {
+    format!("EXTEND: {}|BIDDER: {}", topic, bidder)
+}
+
+/**
+ * Soft close (chosen at publish time, open auctions only):
+ *  A bid (escrow) in the last window seconds of the auction
+ *  extends its end by extension seconds, at most
+ *  MAX_EXTENSIONS times. Both are positive.
+**/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoftClose {
+    pub window: i64,
+    pub extension: i64,
+}
+
+impl SoftClose {
+    pub fn new(window: i64, extension: i64) -> Option<Self> {
+        (window > 0 && extension > 0).then_some(Self { window, extension })
+    }
+
+    pub fn from_json(rule: &str) -> Option<Self> {
+        let rule: Self = serde_json::from_str(rule).ok()?;
+        Self::new(rule.window, rule.extension)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    // Bid at timestamp (unix secs) is within the window before end
+    pub fn triggered(&self, end: DateTime<Local>, timestamp: i64) -> bool {
+        timestamp <= end.timestamp() && timestamp >= end.timestamp() - self.window
+    }
+
+    pub fn extend(&self, end: DateTime<Local>) -> DateTime<Local> {
+        end + Duration::seconds(self.extension)
+    }
+}
+
+/*
+    Effective end of the auction of topic from its events (block
+    timestamp, tx), in chain order, as taken by the auction engine: the
+    PUB_TOPIC deadline, extended by every EXTEND of an escrow taken
+    within the soft-close window of the end at the time. None if the
+    topic is not published.
+*/
+pub fn effective_end(topic: &str, events: &[(i64, String)]) -> Option<DateTime<Local>> {
+    let engine = AuctionEngine::replay(events);
+    Some(local(engine.auction(topic)?.end))
+}
+
+/**
+ * SOFT CLOSE
+**/
+impl Blockchain {
+    pub fn effective_end(&self, topic: &str) -> Option<DateTime<Local>> {
+        Some(local(self.auction(topic)?.end))
+    }
+}